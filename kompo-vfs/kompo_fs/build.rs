@@ -2,5 +2,7 @@ use cc;
 
 fn main() {
     println!("cargo::rerun-if-changed=./dummy_fs.c");
+    println!("cargo::rerun-if-changed=./exec_list.c");
     cc::Build::new().file("./dummy_fs.c").compile("dummy_fs.o");
+    cc::Build::new().file("./exec_list.c").compile("exec_list");
 }
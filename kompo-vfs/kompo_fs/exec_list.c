@@ -0,0 +1,74 @@
+// execl(3), execlp(3) and execle(3) are variadic, which stable Rust can't define. these only
+// gather their arguments into an argv on the stack and hand it to execv(), execvp() and
+// execve(), so the hooks in kompo_wrap see every exec. glibc's own versions call its internal
+// execve() directly and would slip past them.
+#ifdef __linux__
+
+#include <stdarg.h>
+#include <stddef.h>
+#include <unistd.h>
+
+int execl(const char *path, const char *arg, ...)
+{
+    va_list ap;
+    size_t argc = 1;
+
+    va_start(ap, arg);
+    while (va_arg(ap, const char *) != NULL)
+        argc++;
+    va_end(ap);
+
+    const char *argv[argc + 1];
+    argv[0] = arg;
+    va_start(ap, arg);
+    for (size_t i = 1; i <= argc; i++)
+        argv[i] = va_arg(ap, const char *);
+    va_end(ap);
+
+    return execv(path, (char *const *)argv);
+}
+
+int execlp(const char *file, const char *arg, ...)
+{
+    va_list ap;
+    size_t argc = 1;
+
+    va_start(ap, arg);
+    while (va_arg(ap, const char *) != NULL)
+        argc++;
+    va_end(ap);
+
+    const char *argv[argc + 1];
+    argv[0] = arg;
+    va_start(ap, arg);
+    for (size_t i = 1; i <= argc; i++)
+        argv[i] = va_arg(ap, const char *);
+    va_end(ap);
+
+    return execvp(file, (char *const *)argv);
+}
+
+// the environment follows the NULL that ends the arguments.
+int execle(const char *path, const char *arg, ...)
+{
+    va_list ap;
+    size_t argc = 1;
+
+    va_start(ap, arg);
+    while (va_arg(ap, const char *) != NULL)
+        argc++;
+    va_end(ap);
+
+    const char *argv[argc + 1];
+    char *const *envp;
+    argv[0] = arg;
+    va_start(ap, arg);
+    for (size_t i = 1; i <= argc; i++)
+        argv[i] = va_arg(ap, const char *);
+    envp = va_arg(ap, char *const *);
+    va_end(ap);
+
+    return execve(path, (char *const *)argv, envp);
+}
+
+#endif
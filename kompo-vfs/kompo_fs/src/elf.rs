@@ -0,0 +1,222 @@
+// just enough ELF64 (little endian) reading to list a shared object's DT_NEEDED entries.
+
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const DT_NULL: u64 = 0;
+const DT_NEEDED: u64 = 1;
+const DT_STRTAB: u64 = 5;
+
+fn read_u16(image: &[u8], offset: usize) -> Option<u16> {
+    let bytes = image.get(offset..offset.checked_add(2)?)?;
+    Some(u16::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn read_u32(image: &[u8], offset: usize) -> Option<u32> {
+    let bytes = image.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn read_u64(image: &[u8], offset: usize) -> Option<u64> {
+    let bytes = image.get(offset..offset.checked_add(8)?)?;
+    Some(u64::from_le_bytes(bytes.try_into().ok()?))
+}
+
+struct ProgramHeader {
+    p_type: u32,
+    offset: u64,
+    vaddr: u64,
+    filesz: u64,
+}
+
+fn program_headers(image: &[u8]) -> Option<Vec<ProgramHeader>> {
+    // ELFCLASS64, ELFDATA2LSB
+    if image.get(..4)? != b"\x7fELF" || *image.get(4)? != 2 || *image.get(5)? != 1 {
+        return None;
+    }
+
+    let phoff = read_u64(image, 0x20)? as usize;
+    let phentsize = read_u16(image, 0x36)? as usize;
+    let phnum = read_u16(image, 0x38)? as usize;
+
+    (0..phnum)
+        .map(|i| {
+            let header = phoff.checked_add(i.checked_mul(phentsize)?)?;
+
+            Some(ProgramHeader {
+                p_type: read_u32(image, header)?,
+                offset: read_u64(image, header.checked_add(8)?)?,
+                vaddr: read_u64(image, header.checked_add(16)?)?,
+                filesz: read_u64(image, header.checked_add(32)?)?,
+            })
+        })
+        .collect()
+}
+
+fn vaddr_to_offset(headers: &[ProgramHeader], vaddr: u64) -> Option<usize> {
+    headers
+        .iter()
+        .filter(|header| header.p_type == PT_LOAD)
+        .find(|header| {
+            header.vaddr <= vaddr
+                && header
+                    .vaddr
+                    .checked_add(header.filesz)
+                    .is_some_and(|end| vaddr < end)
+        })
+        .and_then(|header| (vaddr - header.vaddr).checked_add(header.offset))
+        .and_then(|offset| usize::try_from(offset).ok())
+}
+
+// returns an empty list for anything that isn't a well-formed ELF64 LE image.
+pub fn needed_libraries(image: &[u8]) -> Vec<Vec<u8>> {
+    fn inner(image: &[u8]) -> Option<Vec<Vec<u8>>> {
+        let headers = program_headers(image)?;
+        let dynamic = headers.iter().find(|header| header.p_type == PT_DYNAMIC)?;
+
+        let mut needed = vec![];
+        let mut strtab = None;
+        let mut entry = usize::try_from(dynamic.offset).ok()?;
+        let end = entry.checked_add(usize::try_from(dynamic.filesz).ok()?)?;
+
+        while entry.checked_add(16)? <= end {
+            let tag = read_u64(image, entry)?;
+            let val = read_u64(image, entry + 8)?;
+
+            match tag {
+                DT_NULL => break,
+                DT_NEEDED => needed.push(usize::try_from(val).ok()?),
+                DT_STRTAB => strtab = Some(val),
+                _ => {}
+            }
+
+            entry += 16;
+        }
+
+        let strtab = vaddr_to_offset(&headers, strtab?)?;
+
+        needed
+            .into_iter()
+            .map(|name| {
+                let start = strtab.checked_add(name)?;
+                let len = image.get(start..)?.iter().position(|b| *b == 0)?;
+
+                Some(image[start..start + len].to_vec())
+            })
+            .collect()
+    }
+
+    inner(image).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const BASE: u64 = 0x40_0000;
+    const PHOFF: usize = 64;
+    const DYNAMIC: usize = PHOFF + 2 * 56;
+
+    // a minimal shared object: a PT_LOAD covering the whole file at BASE, and a PT_DYNAMIC
+    // with one DT_NEEDED per name followed by DT_STRTAB and DT_NULL.
+    fn image(needed: &[&[u8]]) -> Vec<u8> {
+        let dynamic_size = (needed.len() + 2) * 16;
+        let strtab = DYNAMIC + dynamic_size;
+
+        let mut names = vec![0];
+        let mut name_offsets = vec![];
+        for name in needed {
+            name_offsets.push(names.len() as u64);
+            names.extend_from_slice(name);
+            names.push(0);
+        }
+        let len = strtab + names.len();
+
+        let mut image = vec![0; 64];
+        image[..6].copy_from_slice(b"\x7fELF\x02\x01");
+        image[0x20..0x28].copy_from_slice(&(PHOFF as u64).to_le_bytes());
+        image[0x36..0x38].copy_from_slice(&56u16.to_le_bytes());
+        image[0x38..0x3a].copy_from_slice(&2u16.to_le_bytes());
+
+        let mut header = |p_type: u32, offset: usize, filesz: usize| {
+            let mut ph = vec![0; 56];
+            ph[..4].copy_from_slice(&p_type.to_le_bytes());
+            ph[8..16].copy_from_slice(&(offset as u64).to_le_bytes());
+            ph[16..24].copy_from_slice(&(BASE + offset as u64).to_le_bytes());
+            ph[32..40].copy_from_slice(&(filesz as u64).to_le_bytes());
+            image.extend(ph);
+        };
+        header(PT_LOAD, 0, len);
+        header(PT_DYNAMIC, DYNAMIC, dynamic_size);
+
+        let mut entry = |tag: u64, val: u64| {
+            image.extend(tag.to_le_bytes());
+            image.extend(val.to_le_bytes());
+        };
+        for offset in name_offsets {
+            entry(DT_NEEDED, offset);
+        }
+        entry(DT_STRTAB, BASE + strtab as u64);
+        entry(DT_NULL, 0);
+
+        image.extend(names);
+        image
+    }
+
+    fn set_u64(image: &mut [u8], offset: usize, val: u64) {
+        image[offset..offset + 8].copy_from_slice(&val.to_le_bytes());
+    }
+
+    #[test]
+    fn test_needed_libraries() {
+        assert_eq!(
+            needed_libraries(&image(&[b"libfoo.so", b"libbar.so.1"])),
+            vec![b"libfoo.so".to_vec(), b"libbar.so.1".to_vec()]
+        );
+
+        assert!(needed_libraries(&image(&[])).is_empty());
+        assert!(needed_libraries(b"#!/bin/sh\n").is_empty());
+        assert!(needed_libraries(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_truncated_images() {
+        let image = image(&[b"libfoo.so"]);
+        for len in 0..image.len() {
+            // anything cut short of the name's terminator yields nothing rather than a guess.
+            assert!(needed_libraries(&image[..len]).is_empty(), "len {len}");
+        }
+    }
+
+    #[test]
+    fn test_corrupt_images() {
+        let valid = image(&[b"libfoo.so"]);
+        let load = PHOFF;
+        let dynamic = PHOFF + 56;
+
+        let corrupt = |f: &dyn Fn(&mut Vec<u8>)| {
+            let mut image = valid.clone();
+            f(&mut image);
+            needed_libraries(&image)
+        };
+
+        // a segment whose vaddr + filesz overflows.
+        assert!(corrupt(&|image| {
+            set_u64(image, load + 16, u64::MAX - 8);
+            set_u64(image, load + 32, 64);
+        })
+        .is_empty());
+        // a segment whose offset overflows once the string table is rebased onto it.
+        assert!(corrupt(&|image| set_u64(image, load + 8, u64::MAX)).is_empty());
+        // a dynamic section at the very end of the address space.
+        assert!(corrupt(&|image| set_u64(image, dynamic + 8, u64::MAX - 4)).is_empty());
+        assert!(corrupt(&|image| set_u64(image, dynamic + 32, u64::MAX)).is_empty());
+        // program headers past the end of the file, or wrapping around.
+        assert!(corrupt(&|image| set_u64(image, 0x20, u64::MAX - 16)).is_empty());
+        assert!(corrupt(&|image| set_u64(image, 0x20, 1 << 40)).is_empty());
+        // a name offset outside the string table.
+        assert!(corrupt(&|image| set_u64(image, DYNAMIC + 8, u64::MAX)).is_empty());
+        // big endian or 32-bit.
+        assert!(corrupt(&|image| image[5] = 2).is_empty());
+        assert!(corrupt(&|image| image[4] = 1).is_empty());
+    }
+}
@@ -0,0 +1,389 @@
+use std::{
+    ffi::{CStr, CString, OsStr},
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use crate::{memfd, overlay, util, WORKING_DIR};
+
+// the kompo binary's main() runs this file instead of the packed entrypoint when it finds
+// KOMPO_ENTRYPOINT in its environment. it is unset again before any Ruby code runs.
+const ENTRYPOINT_ENV: &[u8] = b"KOMPO_ENTRYPOINT";
+
+// with KOMPO_AS_RUBY in its environment, main() passes its arguments on to Ruby untouched,
+// like the `ruby` command would.
+const AS_RUBY_ENV: &[u8] = b"KOMPO_AS_RUBY";
+
+// the parent's virtual working directory, picked up by Init_kompo_fs in the child.
+pub const WORKING_DIR_ENV: &[u8] = b"KOMPO_WORKING_DIR";
+
+const SELF_EXE: &CStr = c"/proc/self/exe";
+
+extern "C" {
+    pub static environ: *const *const libc::c_char;
+}
+
+// what actually gets handed to the host's exec*/posix_spawn* for an embedded file.
+pub struct ExecTarget {
+    pub path: CString,
+    // `NAME=value` variables to set in the new image, replacing any inherited ones.
+    env: Vec<CString>,
+    // the memfd backing `path`, to be closed once the exec has failed or the spawn is done.
+    fd: Option<libc::c_int>,
+}
+
+impl ExecTarget {
+    // re-executes the kompo binary, with `var` telling its main() what to run.
+    fn self_exe(var: &[u8], value: &[u8]) -> Self {
+        let mut env = vec![env_var(var, value)];
+        if let Some(working_dir) = WORKING_DIR.get() {
+            env.push(env_var(WORKING_DIR_ENV, working_dir.as_os_str().as_bytes()));
+        }
+
+        ExecTarget {
+            path: SELF_EXE.to_owned(),
+            env,
+            fd: None,
+        }
+    }
+
+    // the environment for the new image: `envp` with this target's variables swapped in.
+    pub fn envp(&self, envp: *const *const libc::c_char) -> CStrArray {
+        let names = self
+            .env
+            .iter()
+            .map(|var| var_name(var.to_bytes()))
+            .collect::<Vec<_>>();
+
+        let mut vars = read_array(envp)
+            .into_iter()
+            .filter(|var| !names.contains(&var_name(var.to_bytes())))
+            .map(CStr::to_owned)
+            .collect::<Vec<_>>();
+        vars.extend(self.env.iter().cloned());
+
+        CStrArray::new(vars)
+    }
+
+    pub fn close(self) {
+        if let Some(fd) = self.fd {
+            let err = errno::errno();
+            unsafe { kompo_wrap::CLOSE_HANDLE(fd) };
+            errno::set_errno(err);
+        }
+    }
+}
+
+// a NULL-terminated argv or envp built here.
+pub struct CStrArray {
+    _strings: Vec<CString>,
+    ptrs: Vec<*const libc::c_char>,
+}
+
+impl CStrArray {
+    fn new(strings: Vec<CString>) -> Self {
+        let mut ptrs = strings.iter().map(|s| s.as_ptr()).collect::<Vec<_>>();
+        ptrs.push(std::ptr::null());
+
+        CStrArray {
+            _strings: strings,
+            ptrs,
+        }
+    }
+
+    pub fn as_ptr(&self) -> *const *const libc::c_char {
+        self.ptrs.as_ptr()
+    }
+}
+
+// the strings of a NULL-terminated array such as argv, which may itself be NULL.
+fn read_array<'p>(array: *const *const libc::c_char) -> Vec<&'p CStr> {
+    let mut strings = Vec::new();
+    if array.is_null() {
+        return strings;
+    }
+
+    let mut i = 0;
+    loop {
+        let string = unsafe { *array.add(i) };
+        if string.is_null() {
+            return strings;
+        }
+
+        strings.push(unsafe { CStr::from_ptr(string) });
+        i += 1;
+    }
+}
+
+fn env_var(name: &[u8], value: &[u8]) -> CString {
+    let mut var = name.to_vec();
+    var.push(b'=');
+    var.extend_from_slice(value);
+
+    CString::new(var).expect("invalid environment variable")
+}
+
+fn var_name(var: &[u8]) -> &[u8] {
+    var.split(|b| *b == b'=').next().unwrap_or_default()
+}
+
+// bundler's own executable, found through the embedded gems rather than $PATH.
+const BUNDLE_SCRIPT: &CStr = c"load Gem.bin_path('bundler', 'bundle')";
+
+// an exec of `ruby`, `env ruby` or `bundle`, whether looked up along $PATH or given as a path
+// such as RbConfig.ruby, with its argv. the child gets the kompo binary running as ruby
+// instead, so it sees the same embedded files as we do. None for any other program, and for
+// env(1) with options, which run as they are.
+pub fn ruby_command(
+    file: *const libc::c_char,
+    argv: *const *const libc::c_char,
+) -> Option<(ExecTarget, CStrArray)> {
+    if file.is_null() {
+        return None;
+    }
+    let file = unsafe { CStr::from_ptr(file) };
+    let args = read_array(argv);
+    let mut target = ExecTarget::self_exe(AS_RUBY_ENV, b"1");
+
+    let args = match basename(file.to_bytes()) {
+        b"ruby" => args.into_iter().map(CStr::to_owned).collect(),
+        // `bundle exec rake` is `ruby -e <bundle> -- exec rake`.
+        b"bundle" => {
+            let mut ruby_args = vec![
+                args.first().copied().unwrap_or(c"bundle").to_owned(),
+                c"-e".to_owned(),
+                BUNDLE_SCRIPT.to_owned(),
+                c"--".to_owned(),
+            ];
+            ruby_args.extend(args.iter().skip(1).map(|&arg| arg.to_owned()));
+            ruby_args
+        }
+        // `env NAME=value ruby ...` sets the variables and runs ruby.
+        b"env" => {
+            let rest = args.get(1..).unwrap_or_default();
+            let command = rest
+                .iter()
+                .position(|arg| !arg.to_bytes().contains(&b'='))?;
+            if basename(rest[command].to_bytes()) != b"ruby" {
+                return None;
+            }
+
+            target
+                .env
+                .extend(rest[..command].iter().map(|&var| var.to_owned()));
+            rest[command..].iter().map(|&arg| arg.to_owned()).collect()
+        }
+        _ => return None,
+    };
+
+    Some((target, CStrArray::new(args)))
+}
+
+fn basename(path: &[u8]) -> &[u8] {
+    path.rsplit(|b| *b == b'/').next().unwrap_or_default()
+}
+
+// a `#!` line that runs ruby itself, by path or through env(1): `#!/usr/bin/ruby -w`,
+// `#!/usr/bin/env ruby` or `#!/usr/bin/env -S ruby --jit`. jruby, truffleruby and the like
+// are other programs and run as they are.
+fn is_ruby_script(contents: &[u8]) -> bool {
+    let Some(shebang) = contents.strip_prefix(b"#!") else {
+        return false;
+    };
+    let line = shebang.split(|b| *b == b'\n').next().unwrap_or_default();
+    let mut words = line
+        .split(|b| b.is_ascii_whitespace())
+        .filter(|word| !word.is_empty());
+
+    match words.next().map(basename) {
+        Some(b"ruby") => true,
+        // env's own options and variable assignments come before the command.
+        Some(b"env") => words
+            .find(|word| !word.starts_with(b"-") && !word.contains(&b'='))
+            .is_some_and(|command| basename(command) == b"ruby"),
+        _ => false,
+    }
+}
+
+// the absolute path `path` names in the VFS, if exec'ing it should be handled by kompo_fs.
+// Err is a VFS path that doesn't resolve, with the errno to report.
+pub fn vfs_path(path: *const libc::c_char) -> Option<Result<PathBuf, libc::c_int>> {
+    match util::route(path) {
+        util::Route::Vfs(path) => Some(Ok(PathBuf::from(OsStr::from_bytes(path.to_bytes())))),
+        util::Route::Host(_) => None,
+        util::Route::Error(err) => Some(Err(err)),
+    }
+}
+
+// execvp(3)-style lookup of `file` along $PATH. Some only if the first hit is embedded.
+pub fn search_vfs_path(file: *const libc::c_char) -> Option<Result<PathBuf, libc::c_int>> {
+    let name = unsafe { CStr::from_ptr(file) };
+    if name.to_bytes().is_empty() {
+        return None;
+    }
+    if name.to_bytes().contains(&b'/') {
+        return vfs_path(file);
+    }
+
+    let paths = std::env::var_os("PATH").unwrap_or_else(|| "/bin:/usr/bin".into());
+    for dir in paths.as_bytes().split(|b| *b == b':') {
+        let dir = if dir.is_empty() { b".".as_slice() } else { dir };
+        let mut candidate = dir.to_vec();
+        candidate.push(b'/');
+        candidate.extend_from_slice(name.to_bytes());
+        let candidate = CString::new(candidate).expect("invalid path");
+
+        match vfs_path(candidate.as_ptr()) {
+            Some(Ok(path)) if is_embedded_file(&path) => return Some(Ok(path)),
+            Some(_) => {}
+            None => {
+                if unsafe { libc::access(candidate.as_ptr(), libc::X_OK) } == 0 {
+                    return None;
+                }
+            }
+        }
+    }
+
+    None
+}
+
+fn is_embedded_file(path: &Path) -> bool {
+    overlay::file_contents(path).is_some()
+}
+
+// Ruby scripts re-execute the kompo binary with the script as its entrypoint; anything else
+// (native executables, other interpreters' scripts) runs from a memfd through /proc/self/fd.
+// None with errno set if the file isn't embedded or can't be materialized.
+pub fn target(path: Result<PathBuf, libc::c_int>) -> Option<ExecTarget> {
+    let path = match path {
+        Ok(path) => path,
+        Err(err) => return util::fail(err, None),
+    };
+    let search_path = path.iter().collect::<Vec<_>>();
+    let Some(contents) = overlay::file_contents(&path) else {
+        errno::set_errno(errno::Errno(libc::ENOENT));
+        return None;
+    };
+
+    if is_ruby_script(&contents) {
+        return Some(ExecTarget::self_exe(
+            ENTRYPOINT_ENV,
+            path.as_os_str().as_bytes(),
+        ));
+    }
+
+    // a copied-up file runs from the overlay directory, where it is a real file already.
+    if let Some(host) = overlay::host_file(&path) {
+        return Some(ExecTarget {
+            path: host,
+            env: vec![],
+            fd: None,
+        });
+    }
+
+    // a script's interpreter opens /proc/self/fd/N after the exec, so its fd has to survive it.
+    let oflag = if contents.starts_with(b"#!") {
+        0
+    } else {
+        libc::O_CLOEXEC
+    };
+    let fd = memfd::open_memfd(&search_path, oflag)?;
+    let proc_path = CString::new(format!("/proc/self/fd/{}", fd)).expect("invalid path");
+
+    Some(ExecTarget {
+        path: proc_path,
+        env: vec![],
+        fd: Some(fd),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ruby_command() {
+        let command = |file: &CStr, args: &[&CStr]| {
+            let mut argv = args.iter().map(|arg| arg.as_ptr()).collect::<Vec<_>>();
+            argv.push(std::ptr::null());
+
+            ruby_command(file.as_ptr(), argv.as_ptr()).map(|(target, argv)| {
+                let env = target
+                    .env
+                    .iter()
+                    .map(|var| var.to_str().unwrap().to_owned())
+                    .collect::<Vec<_>>();
+                let argv = read_array(argv.as_ptr())
+                    .into_iter()
+                    .map(|arg| arg.to_str().unwrap().to_owned())
+                    .collect::<Vec<_>>();
+                (env, argv)
+            })
+        };
+
+        assert_eq!(
+            command(c"/usr/bin/ruby", &[c"ruby", c"-e", c"p 1"]),
+            Some((
+                vec!["KOMPO_AS_RUBY=1".to_owned()],
+                vec!["ruby".to_owned(), "-e".to_owned(), "p 1".to_owned()]
+            ))
+        );
+        assert_eq!(
+            command(c"bundle", &[c"bundle", c"exec", c"rake"]),
+            Some((
+                vec!["KOMPO_AS_RUBY=1".to_owned()],
+                [
+                    "bundle",
+                    "-e",
+                    BUNDLE_SCRIPT.to_str().unwrap(),
+                    "--",
+                    "exec",
+                    "rake"
+                ]
+                .map(str::to_owned)
+                .to_vec()
+            ))
+        );
+        assert_eq!(
+            command(c"/usr/bin/env", &[c"env", c"RUBYOPT=-W0", c"ruby", c"x.rb"]),
+            Some((
+                vec!["KOMPO_AS_RUBY=1".to_owned(), "RUBYOPT=-W0".to_owned()],
+                vec!["ruby".to_owned(), "x.rb".to_owned()]
+            ))
+        );
+
+        assert_eq!(command(c"/usr/bin/env", &[c"env", c"-i", c"ruby"]), None);
+        assert_eq!(command(c"/usr/bin/env", &[c"env", c"python3"]), None);
+        assert_eq!(command(c"/usr/bin/env", &[c"env", c"A=1"]), None);
+        assert_eq!(command(c"/usr/bin/jruby", &[c"jruby"]), None);
+        assert_eq!(command(c"rubyx", &[c"rubyx"]), None);
+    }
+
+    #[test]
+    fn test_is_ruby_script() {
+        for shebang in [
+            "#!/usr/bin/ruby\n",
+            "#!/usr/local/bin/ruby -w\nputs 1\n",
+            "#! /usr/bin/ruby",
+            "#!/usr/bin/env ruby\n",
+            "#!/usr/bin/env -S ruby --jit\n",
+            "#!/usr/bin/env RUBYOPT=-W0 ruby\r\n",
+            "#!/usr/bin/env /opt/ruby/bin/ruby\n",
+        ] {
+            assert!(is_ruby_script(shebang.as_bytes()), "{shebang:?}");
+        }
+
+        for shebang in [
+            "#!/usr/bin/jruby\n",
+            "#!/usr/bin/env truffleruby\n",
+            "#!/usr/bin/env ruby-build\n",
+            "#!/bin/sh\n# ruby\n",
+            "#!/usr/bin/env\n",
+            "#!\n",
+            "puts 'ruby'\n",
+            "",
+        ] {
+            assert!(!is_ruby_script(shebang.as_bytes()), "{shebang:?}");
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use std::{
+    ffi::{CStr, CString, OsStr},
+    fs::File,
+    hash::{DefaultHasher, Hash, Hasher},
+    io::Read,
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    fork::ForkLock, initialize_trie, util::GlobList, FILES, FILES_SIZE, FILES_SIZES, PATHS,
+    PATHS_SIZE, TRIE, WD,
+};
+
+// KOMPO_EXTRACT is a `:`-separated list of fnmatch(3) globs, e.g. `*.so:*/bin/*`.
+// embedded files matching one of them are written out to the cache directory the first time
+// they are opened, and every later open is redirected to the copy on disk.
+static EXTRACT_PATTERNS: std::sync::LazyLock<GlobList> =
+    std::sync::LazyLock::new(|| GlobList::from_env("KOMPO_EXTRACT", 0));
+
+// files already extracted (or verified) by this process, keyed by their embedded path. each
+// one stays open for the life of the process along with the path handed out for it, so later
+// opens reach the very file that was checked, whatever happens in the cache dir meanwhile.
+static EXTRACTED_FILES: std::sync::LazyLock<
+    std::sync::Mutex<std::collections::HashMap<PathBuf, (File, CString)>>,
+> = std::sync::LazyLock::new(|| std::sync::Mutex::new(std::collections::HashMap::new()));
+
+pub struct ExtractedFiles;
+
+impl ForkLock for ExtractedFiles {
+    fn lock() -> Box<dyn std::any::Any> {
+        Box::new(
+            EXTRACTED_FILES
+                .lock()
+                .unwrap_or_else(|err| err.into_inner()),
+        )
+    }
+}
+
+pub fn is_extract_path(path: &Vec<&OsStr>) -> bool {
+    EXTRACT_PATTERNS.matches(&path.iter().collect::<PathBuf>())
+}
+
+// `$XDG_CACHE_HOME/kompo/<archive-hash>`, falling back to `~/.cache`.
+fn cache_dir() -> Option<PathBuf> {
+    let mut dir = std::env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+
+    dir.push("kompo");
+    dir.push(format!("{:016x}", archive_hash()));
+
+    Some(dir)
+}
+
+// identifies the embedded archive by its working dir, path table and contents, so that
+// different builds don't share a cache directory. files are still compared byte for byte
+// on reuse; this only keeps builds from overwriting each other's copies.
+pub fn archive_hash() -> u64 {
+    static ARCHIVE_HASH: std::sync::OnceLock<u64> = std::sync::OnceLock::new();
+
+    *ARCHIVE_HASH.get_or_init(|| {
+        let mut hasher = DefaultHasher::new();
+
+        let wd = unsafe { CStr::from_ptr(&WD) };
+        let paths = unsafe { std::slice::from_raw_parts(&PATHS, PATHS_SIZE as _) };
+        let count = paths.iter().filter(|b| **b == b'\0').count();
+        let files_sizes = unsafe { std::slice::from_raw_parts(&FILES_SIZES, count + 1) };
+        let files = unsafe { std::slice::from_raw_parts(&FILES, FILES_SIZE as _) };
+
+        wd.to_bytes().hash(&mut hasher);
+        paths.hash(&mut hasher);
+        files_sizes.hash(&mut hasher);
+        files.hash(&mut hasher);
+
+        hasher.finish()
+    })
+}
+
+// returns a path to the on-disk copy of an embedded file, extracting it first if the cache
+// doesn't already hold an identical one. on Linux that's `/proc/self/fd/N` of the checked
+// copy rather than its name in the cache dir, which another process could swap out. None
+// means the file isn't embedded or couldn't be written.
+pub fn extracted_path(path: &Vec<&OsStr>) -> Option<CString> {
+    let key = path.iter().collect::<PathBuf>();
+    let mut files = EXTRACTED_FILES
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    if let Some((_, extracted)) = files.get(&key) {
+        return Some(extracted.clone());
+    }
+
+    let contents = {
+        let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
+
+        trie.file_contents(path)?
+    };
+
+    // mirror the embedded path below the cache dir so that siblings stay siblings.
+    let mut target = cache_dir()?;
+    target.extend(path.iter().skip(1));
+
+    let file = match open_identical(&target, &contents) {
+        Some(file) => file,
+        None => {
+            extract(&target, &contents).ok()?;
+            open_identical(&target, &contents)?
+        }
+    };
+
+    #[cfg(target_os = "linux")]
+    let extracted = {
+        use std::os::fd::AsRawFd;
+        CString::new(format!("/proc/self/fd/{}", file.as_raw_fd())).ok()?
+    };
+    #[cfg(not(target_os = "linux"))]
+    let extracted = {
+        use std::os::unix::ffi::OsStrExt;
+        CString::new(target.as_os_str().as_bytes()).ok()?
+    };
+
+    files.insert(key, (file, extracted.clone()));
+
+    Some(extracted)
+}
+
+// opens the regular file at `target`, without following a symlink there, if reading it
+// through that same fd gives exactly `contents`.
+fn open_identical(target: &Path, contents: &[u8]) -> Option<File> {
+    let mut file = File::options()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(target)
+        .ok()?;
+
+    let metadata = file.metadata().ok()?;
+    if !metadata.is_file() || metadata.len() != contents.len() as u64 {
+        return None;
+    }
+
+    let mut on_disk = Vec::with_capacity(contents.len());
+    file.read_to_end(&mut on_disk).ok()?;
+
+    (on_disk == contents).then_some(file)
+}
+
+// tells apart the temporary files of concurrent extract() calls within this process.
+static TMP_COUNTER: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+// writes to a temporary file and renames it into place, so a concurrent process or thread
+// never sees a partially written copy.
+pub fn extract(target: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let dir = target.parent().expect("extract target has no parent");
+    std::fs::create_dir_all(dir)?;
+
+    let mut tmp = target.as_os_str().to_os_string();
+    tmp.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        TMP_COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+    ));
+
+    // executables and shared objects have to keep their exec bit to be of any use on disk.
+    let mode = if contents.starts_with(b"\x7fELF") || contents.starts_with(b"#!") {
+        0o755
+    } else {
+        0o644
+    };
+
+    std::fs::write(&tmp, contents)?;
+    std::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(mode))?;
+    std::fs::rename(&tmp, target).inspect_err(|_| {
+        let _ = std::fs::remove_file(&tmp);
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("kompo_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_open_identical() {
+        let dir = temp_dir("open_identical");
+        let target = dir.join("lib.so");
+
+        assert!(open_identical(&target, b"abc").is_none());
+        extract(&target, b"abc").unwrap();
+        assert!(open_identical(&target, b"abc").is_some());
+        assert!(open_identical(&target, b"abd").is_none());
+        assert!(open_identical(&target, b"abcd").is_none());
+
+        // a symlink planted in the cache dir is never followed.
+        let link = dir.join("link.so");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        assert!(open_identical(&link, b"abc").is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // threads copying up the same file each write their own temporary file.
+    #[test]
+    fn test_concurrent_extract() {
+        let dir = temp_dir("concurrent_extract");
+        let target = dir.join("lib.rb");
+
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..50 {
+                        extract(&target, b"abc").unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(std::fs::read(&target).unwrap(), b"abc");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_extracted_path_is_pinned() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = temp_dir("extracted_path");
+        std::env::set_var("XDG_CACHE_HOME", &dir);
+
+        let path = ["/", "__kompo__", "lib", "hello.rb"]
+            .map(OsStr::new)
+            .to_vec();
+        let extracted = extracted_path(&path).unwrap();
+        assert!(extracted.to_bytes().starts_with(b"/proc/self/fd/"));
+        let read = |path: &CStr| std::fs::read(OsStr::from_bytes(path.to_bytes())).unwrap();
+        assert_eq!(read(&extracted), b"puts 'hello'\n");
+
+        // swapping the file in the cache dir doesn't reach this process any more.
+        let target = cache_dir().unwrap().join("__kompo__/lib/hello.rb");
+        extract(&target, b"system('evil')\n").unwrap();
+        assert_eq!(extracted_path(&path).unwrap(), extracted);
+        assert_eq!(read(&extracted), b"puts 'hello'\n");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,126 @@
+use std::{
+    ffi::{CStr, CString},
+    os::unix::ffi::OsStrExt,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{util::GlobList, WD};
+
+// KOMPO_HOST_LOOKUP picks where a path under the working dir is looked up:
+// `embedded` (the default) only in the archive, `embedded-first` on the host when the archive
+// doesn't have it, and `host-first` in the archive when the host doesn't.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Order {
+    Embedded,
+    EmbeddedFirst,
+    HostFirst,
+}
+
+static ORDER: std::sync::LazyLock<Order> =
+    std::sync::LazyLock::new(|| match std::env::var_os("KOMPO_HOST_LOOKUP").as_deref() {
+        Some(order) if order == "embedded-first" => Order::EmbeddedFirst,
+        Some(order) if order == "host-first" => Order::HostFirst,
+        _ => Order::Embedded,
+    });
+
+// KOMPO_HOST_DIR is the host directory standing in for the working dir, by default the one
+// holding the kompo binary.
+static HOST_DIR: std::sync::LazyLock<Option<PathBuf>> = std::sync::LazyLock::new(|| {
+    match std::env::var_os("KOMPO_HOST_DIR").filter(|dir| !dir.is_empty()) {
+        Some(dir) => Some(PathBuf::from(dir)),
+        None => std::env::current_exe()
+            .ok()?
+            .parent()
+            .map(|dir| dir.to_path_buf()),
+    }
+});
+
+// KOMPO_HOST_ALLOW is a `:`-separated list of fnmatch(3) globs, e.g. `config/*.yml:.env`,
+// matched against paths relative to the working dir. only matching paths fall through to the
+// host; without it, all of them do. `*` stops at `/` and doesn't match a leading `.`, so
+// `config/*.yml` lets neither `config/deep/secret.yml` nor `config/.hidden.yml` through.
+const ALLOW_FLAGS: libc::c_int = libc::FNM_PATHNAME | libc::FNM_PERIOD;
+
+static ALLOW_PATTERNS: std::sync::LazyLock<GlobList> =
+    std::sync::LazyLock::new(|| GlobList::from_env("KOMPO_HOST_ALLOW", ALLOW_FLAGS));
+
+// nothing may climb out of the host dir, whatever the caller normalized.
+fn is_allowed(patterns: &GlobList, relative: &Path) -> bool {
+    let is_escaping = relative
+        .components()
+        .any(|component| component == Component::ParentDir);
+
+    !is_escaping && (patterns.is_empty() || patterns.matches(relative))
+}
+
+// the host file standing in for the VFS path `path`, if it's allowed to fall through.
+fn host_path(path: &Path) -> Option<CString> {
+    let wd = unsafe { CStr::from_ptr(&WD) };
+    let relative = path
+        .strip_prefix(Path::new(std::ffi::OsStr::from_bytes(wd.to_bytes())))
+        .ok()?;
+    if !is_allowed(&ALLOW_PATTERNS, relative) {
+        return None;
+    }
+
+    let mut host = HOST_DIR.clone()?;
+    host.push(relative);
+
+    CString::new(host.as_os_str().as_bytes()).ok()
+}
+
+// runs `embedded` and `host` on `path` in the configured order. the second one only gets a
+// turn when the first fails with ENOENT; other errors are reported as they are.
+pub fn lookup(
+    path: &Path,
+    embedded: impl FnOnce() -> libc::c_int,
+    host: impl FnOnce(&CStr) -> libc::c_int,
+) -> libc::c_int {
+    let order = *ORDER;
+    if order == Order::Embedded {
+        return embedded();
+    }
+    let Some(host_path) = host_path(path) else {
+        return embedded();
+    };
+
+    let is_missing = |ret: libc::c_int| ret == -1 && errno::errno().0 == libc::ENOENT;
+    if order == Order::HostFirst {
+        let ret = host(&host_path);
+        if !is_missing(ret) {
+            return ret;
+        }
+
+        embedded()
+    } else {
+        let ret = embedded();
+        if !is_missing(ret) {
+            return ret;
+        }
+
+        host(&host_path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_allow_patterns() {
+        let patterns = GlobList::new(b"config/*.yml:.env", ALLOW_FLAGS);
+        let allowed = |relative: &str| is_allowed(&patterns, Path::new(relative));
+
+        assert!(allowed("config/database.yml"));
+        assert!(allowed(".env"));
+        assert!(!allowed("config/deep/secret.yml"));
+        assert!(!allowed("config/.hidden.yml"));
+        assert!(!allowed("config/../../etc/passwd.yml"));
+        assert!(!allowed("app.rb"));
+
+        let everything = GlobList::new(b"", ALLOW_FLAGS);
+        assert!(is_allowed(&everything, Path::new("app.rb")));
+        assert!(!is_allowed(&everything, Path::new("../outside")));
+        assert!(!is_allowed(&everything, Path::new("lib/../../outside")));
+    }
+}
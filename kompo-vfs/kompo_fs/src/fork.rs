@@ -0,0 +1,120 @@
+use std::any::Any;
+use std::cell::RefCell;
+
+#[cfg(target_os = "linux")]
+use crate::memfd;
+use crate::{extract, initialize_trie, FILE_TYPE_CACHE, TRIE, WORKING_DIR};
+
+// pthread_atfork(3) handlers for every process-wide lock. fork() copies only the calling
+// thread, so a lock another thread holds at that moment would stay locked for good in the
+// child. prepare handlers run in the reverse of the order they were registered in, so locks
+// are registered innermost first: prepare takes them in the order the code nests them, and
+// both sides release them the other way round once the fork is done.
+pub fn register() {
+    static REGISTER: std::sync::Once = std::sync::Once::new();
+
+    REGISTER.call_once(|| {
+        // a leaf: nothing is taken while it's held.
+        register_lock::<FileTypeCache>();
+        kompo_storage::register_atfork(TRIE.get_or_init(initialize_trie));
+        WORKING_DIR.register_atfork();
+        // both are held while the file is looked up in the `Fs`, and extracting it goes
+        // through the hooks as well.
+        #[cfg(target_os = "linux")]
+        register_lock::<memfd::SharedObjectFds>();
+        register_lock::<extract::ExtractedFiles>();
+
+        unsafe { libc::pthread_atfork(None, None, Some(crate::reset_thread_context_in_child)) };
+    });
+}
+
+// a process-wide lock held across fork(). `lock()` returns its guard, ignoring poisoning.
+pub trait ForkLock: 'static {
+    fn lock() -> Box<dyn Any>;
+}
+
+struct FileTypeCache;
+
+impl ForkLock for FileTypeCache {
+    fn lock() -> Box<dyn Any> {
+        Box::new(
+            FILE_TYPE_CACHE
+                .write()
+                .unwrap_or_else(|err| err.into_inner()),
+        )
+    }
+}
+
+thread_local! {
+    static FORK_GUARDS: RefCell<Vec<Box<dyn Any>>> = const { RefCell::new(Vec::new()) };
+}
+
+extern "C" fn lock_before_fork<L: ForkLock>() {
+    let guard = L::lock();
+    FORK_GUARDS.with(|guards| guards.borrow_mut().push(guard));
+}
+
+// runs in registration order, the reverse of prepare, so the last guard taken is the first
+// one dropped.
+extern "C" fn unlock_after_fork<L: ForkLock>() {
+    FORK_GUARDS.with(|guards| guards.borrow_mut().pop());
+}
+
+fn register_lock<L: ForkLock>() {
+    unsafe {
+        libc::pthread_atfork(
+            Some(lock_before_fork::<L>),
+            Some(unlock_after_fork::<L>),
+            Some(unlock_after_fork::<L>),
+        )
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::glue;
+
+    #[test]
+    fn test_fork_during_stat() {
+        register();
+
+        let hello = c"/__kompo__/lib/hello.rb";
+        let stop = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let threads = (0..4)
+            .map(|_| {
+                let stop = std::sync::Arc::clone(&stop);
+                std::thread::spawn(move || {
+                    let mut stat = unsafe { std::mem::zeroed::<libc::stat>() };
+                    while !stop.load(std::sync::atomic::Ordering::Relaxed) {
+                        // a miss and then a hit, so the cache is written as well as read.
+                        FILE_TYPE_CACHE.write().unwrap().clear();
+                        assert_eq!(glue::stat_from_fs(hello.as_ptr(), &mut stat), 0);
+                        assert_eq!(glue::stat_from_fs(hello.as_ptr(), &mut stat), 0);
+
+                        let fd = glue::open_from_fs(hello.as_ptr(), libc::O_RDONLY, 0);
+                        assert_ne!(fd, -1);
+                        assert_eq!(glue::close_from_fs(fd), 0);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for _ in 0..50 {
+            kompo_storage::fork_and_check(|| {
+                let mut stat = unsafe { std::mem::zeroed::<libc::stat>() };
+                FILE_TYPE_CACHE.write().unwrap().clear();
+
+                glue::stat_from_fs(hello.as_ptr(), &mut stat) == 0
+                    && stat.st_size == 13
+                    && glue::close_from_fs(glue::open_from_fs(hello.as_ptr(), libc::O_RDONLY, 0))
+                        == 0
+            });
+        }
+
+        stop.store(true, std::sync::atomic::Ordering::Relaxed);
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+}
@@ -1,11 +1,14 @@
 use std::{
-    borrow::Cow,
-    ffi::{CStr, CString},
+    ffi::{CStr, CString, OsStr},
+    os::unix::ffi::OsStrExt,
     path::{Path, PathBuf},
-    str::FromStr,
 };
 
-use crate::{initialize_trie, util, FILE_TYPE_CACHE, TRIE, WORKING_DIR};
+#[cfg(target_os = "linux")]
+use crate::{elf, exec, memfd};
+use crate::{
+    extract, fallthrough, initialize_trie, overlay, util, FILE_TYPE_CACHE, TRIE, WORKING_DIR,
+};
 
 #[no_mangle]
 pub fn mmap_from_fs(
@@ -47,34 +50,60 @@ pub fn mmap_from_fs(
     }
 }
 
-#[no_mangle]
-pub fn open_from_fs(path: *const libc::c_char, oflag: libc::c_int, mode: libc::mode_t) -> i32 {
-    fn inner_open(path: *const libc::c_char) -> libc::c_int {
-        let path = unsafe { CStr::from_ptr(path) };
-        let path = Path::new(path.to_str().expect("invalid path"));
-        let path = path.iter().collect::<Vec<_>>();
+// open() on an absolute VFS path, from the overlay, the writable layer or the archive.
+fn open_embedded(path: &Path, oflag: libc::c_int, mode: libc::mode_t) -> libc::c_int {
+    if let Some(fd) = overlay::open(path, oflag, mode) {
+        return fd;
+    }
+    if util::is_write_open(path, oflag) {
+        return open_writable(path, oflag);
+    }
 
-        let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
-        let ret = {
-            let mut trie = trie.lock().unwrap();
+    let path = path.iter().collect::<Vec<_>>();
 
-            trie.open(&path)
-        };
+    if extract::is_extract_path(&path) {
+        if let Some(extracted) = extract::extracted_path(&path) {
+            return unsafe { kompo_wrap::OPEN_HANDLE(extracted.as_ptr(), oflag, 0) };
+        }
+    }
 
-        ret.unwrap_or_else(|| {
-            errno::set_errno(errno::Errno(libc::ENOENT));
-            -1
-        })
+    #[cfg(target_os = "linux")]
+    if memfd::is_memfd_path(&path) {
+        if let Some(fd) = memfd::open_memfd(&path, oflag) {
+            return fd;
+        }
     }
 
-    if unsafe { WORKING_DIR.borrow().is_some() } && unsafe { *path } != b'/' {
-        let expand_path = util::expand_kompo_path(path);
+    let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
+    let ret = trie.open(&path);
 
-        inner_open(expand_path)
-    } else if util::is_under_kompo_working_dir(path) {
-        inner_open(path)
-    } else {
-        unsafe { kompo_wrap::OPEN_HANDLE(path, oflag, mode) }
+    ret.unwrap_or_else(|| {
+        errno::set_errno(errno::Errno(libc::ENOENT));
+        -1
+    })
+}
+
+#[no_mangle]
+pub fn open_from_fs(path: *const libc::c_char, oflag: libc::c_int, mode: libc::mode_t) -> i32 {
+    fn inner_open(
+        path: *const libc::c_char,
+        oflag: libc::c_int,
+        mode: libc::mode_t,
+    ) -> libc::c_int {
+        let path = unsafe { CStr::from_ptr(path) };
+        let path = Path::new(OsStr::from_bytes(path.to_bytes()));
+
+        fallthrough::lookup(
+            path,
+            || open_embedded(path, oflag, mode),
+            |host| unsafe { kompo_wrap::OPEN_HANDLE(host.as_ptr(), oflag, mode) },
+        )
+    }
+
+    match util::route(path) {
+        util::Route::Vfs(path) => inner_open(path.as_ptr(), oflag, mode),
+        util::Route::Host(path) => unsafe { kompo_wrap::OPEN_HANDLE(path.as_ptr(), oflag, mode) },
+        util::Route::Error(err) => util::fail(err, -1),
     }
 }
 
@@ -86,45 +115,45 @@ pub unsafe fn openat_from_fs(
     mode: libc::mode_t,
 ) -> libc::c_int {
     fn inner_openat(
-        dirfd: libc::c_int,
+        base_dir: PathBuf,
         pathname: *const libc::c_char,
         flags: libc::c_int,
         mode: libc::mode_t,
     ) -> libc::c_int {
-        let path = unsafe { CStr::from_ptr(pathname) };
-        let path = PathBuf::from_str(path.to_str().expect("invalid path")).unwrap();
-
-        let current_dir = unsafe { WORKING_DIR.borrow() };
-        let current_dir = current_dir.clone().expect("not found current dir");
-        let mut current_dir = PathBuf::from(current_dir.into_owned());
-
-        util::canonicalize_path(&mut current_dir, &path);
-
-        let path = current_dir.iter().collect::<Vec<_>>();
-
-        let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
-        let ret = {
-            let mut trie = trie.lock().unwrap();
-
-            trie.open(&path)
+        let current_dir = match util::resolve(&base_dir, unsafe { CStr::from_ptr(pathname) }) {
+            Ok(current_dir) => current_dir,
+            Err(err) => return util::fail(err, -1),
         };
 
-        ret.unwrap_or_else(|| {
-            errno::set_errno(errno::Errno(libc::ENOENT));
-            -1
-        })
+        fallthrough::lookup(
+            &current_dir,
+            || open_embedded(&current_dir, flags, mode),
+            |host| unsafe { kompo_wrap::OPEN_HANDLE(host.as_ptr(), flags, mode) },
+        )
     }
 
-    if flags & libc::O_CREAT == libc::O_CREAT || flags & libc::O_TMPFILE == libc::O_TMPFILE {
-        return kompo_wrap::OPENAT_HANDLE(dirfd, pathname, flags, mode);
+    if pathname.is_null() {
+        return util::fail(libc::EFAULT, -1);
     }
-
-    if util::is_under_kompo_working_dir(pathname) {
+    let is_absolute = CStr::from_ptr(pathname).to_bytes().starts_with(b"/");
+    if is_absolute || (dirfd == libc::AT_FDCWD && *pathname != 0) {
         return open_from_fs(pathname, flags, mode);
     }
 
-    if dirfd == libc::AT_FDCWD && WORKING_DIR.borrow().is_some() && *pathname != b'/' {
-        return inner_openat(dirfd, pathname, flags, mode);
+    if util::is_fd_exists_in_kompo(dirfd) {
+        if *pathname == 0 {
+            errno::set_errno(errno::Errno(libc::ENOENT));
+            return -1;
+        }
+        if !util::is_dir_fd_exists_in_kompo(dirfd) {
+            errno::set_errno(errno::Errno(libc::ENOTDIR));
+            return -1;
+        }
+
+        let Some(base_dir) = util::get_path_from_kompo_fd(dirfd) else {
+            return util::fail(libc::EBADF, -1);
+        };
+        return inner_openat(base_dir, pathname, flags, mode);
     }
 
     kompo_wrap::OPENAT_HANDLE(dirfd, pathname, flags, mode)
@@ -133,27 +162,138 @@ pub unsafe fn openat_from_fs(
 #[no_mangle]
 pub fn close_from_fs(fd: i32) -> i32 {
     if util::is_fd_exists_in_kompo(fd) {
-        std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie))
-            .lock()
-            .unwrap()
-            .close(fd);
+        std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie)).close(fd);
     };
 
-    unsafe { kompo_wrap::CLOSE_HANDLE(fd) } // kompo_fs' inner fd made by dup(). so, close it.
+    unsafe { kompo_wrap::CLOSE_HANDLE(fd) } // kompo_fs' inner fd is a real placeholder fd. so, close it.
+}
+
+// the kernel clears FD_CLOEXEC on a dup, but a kompo fd is meaningless to another program.
+fn set_cloexec(fd: i32) {
+    unsafe { kompo_wrap::FCNTL_HANDLE(fd, libc::F_SETFD, libc::FD_CLOEXEC as libc::c_long) };
+}
+
+#[no_mangle]
+pub fn dup_from_fs(fd: i32) -> i32 {
+    let new_fd = unsafe { kompo_wrap::DUP_HANDLE(fd) };
+
+    if new_fd != -1 && util::is_fd_exists_in_kompo(fd) {
+        std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie)).dup(fd, new_fd);
+        set_cloexec(new_fd);
+    }
+
+    new_fd
+}
+
+#[no_mangle]
+pub fn dup2_from_fs(fd: i32, new_fd: i32) -> i32 {
+    let is_kompo_fd = util::is_fd_exists_in_kompo(fd);
+    let ret = unsafe { kompo_wrap::DUP2_HANDLE(fd, new_fd) };
+
+    if ret != -1 && fd != new_fd {
+        {
+            let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
+
+            if is_kompo_fd {
+                trie.dup(fd, new_fd);
+            } else {
+                // the kernel silently closed new_fd; forget it if it was ours.
+                trie.close(new_fd);
+            }
+        }
+
+        if is_kompo_fd {
+            set_cloexec(new_fd);
+        }
+    }
+
+    ret
+}
+
+#[cfg(target_os = "linux")]
+#[no_mangle]
+pub fn dup3_from_fs(fd: i32, new_fd: i32, flags: libc::c_int) -> i32 {
+    let is_kompo_fd = util::is_fd_exists_in_kompo(fd);
+    let ret = unsafe { kompo_wrap::DUP3_HANDLE(fd, new_fd, flags) };
+
+    if ret != -1 {
+        {
+            let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
+
+            if is_kompo_fd {
+                trie.dup(fd, new_fd);
+            } else {
+                trie.close(new_fd);
+            }
+        }
+
+        if is_kompo_fd {
+            set_cloexec(new_fd);
+        }
+    }
+
+    ret
+}
+
+#[no_mangle]
+pub fn fcntl_from_fs(fd: i32, cmd: libc::c_int, arg: libc::c_long) -> i32 {
+    let ret = unsafe { kompo_wrap::FCNTL_HANDLE(fd, cmd, arg) };
+
+    if ret != -1
+        && (cmd == libc::F_DUPFD || cmd == libc::F_DUPFD_CLOEXEC)
+        && util::is_fd_exists_in_kompo(fd)
+    {
+        std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie)).dup(fd, ret);
+        set_cloexec(ret);
+    }
+
+    ret
+}
+
+#[no_mangle]
+pub fn lseek_from_fs(fd: i32, offset: libc::off_t, whence: libc::c_int) -> libc::off_t {
+    fn inner_lseek(fd: i32, offset: libc::off_t, whence: libc::c_int) -> libc::off_t {
+        let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
+        let ret = trie.lseek(fd, offset, whence);
+
+        ret.unwrap_or_else(|err| util::fail(err, -1))
+    }
+
+    if util::is_fd_exists_in_kompo(fd) {
+        inner_lseek(fd, offset, whence)
+    } else {
+        unsafe { kompo_wrap::LSEEK_HANDLE(fd, offset, whence) }
+    }
 }
 
 #[no_mangle]
 pub fn stat_from_fs(path: *const libc::c_char, stat: *mut libc::stat) -> i32 {
     fn inner_stat(path: *const libc::c_char, stat: *mut libc::stat) -> i32 {
         let path = unsafe { CStr::from_ptr(path) };
-        let path = Path::new(path.to_str().expect("invalid path"));
+        let path = Path::new(OsStr::from_bytes(path.to_bytes()));
+
+        fallthrough::lookup(
+            path,
+            || stat_embedded(path, stat),
+            |host| unsafe { kompo_wrap::STAT_HANDLE(host.as_ptr(), stat) },
+        )
+    }
+
+    fn stat_embedded(path: &Path, stat: *mut libc::stat) -> i32 {
+        if let Some(host) = overlay::host_file(path) {
+            return unsafe { kompo_wrap::STAT_HANDLE(host.as_ptr(), stat) };
+        }
         let path = path
             .iter()
             .map(|os_str| os_str.to_os_string())
             .collect::<Vec<_>>();
 
         // TODO: move to trie.stat()
-        if let Some(cache) = unsafe { FILE_TYPE_CACHE.read().unwrap().get(&path) } {
+        if let Some(cache) = FILE_TYPE_CACHE
+            .read()
+            .unwrap_or_else(|err| err.into_inner())
+            .get(&path)
+        {
             unsafe { *stat = cache.clone() };
             return 0;
         }
@@ -165,15 +305,16 @@ pub fn stat_from_fs(path: *const libc::c_char, stat: *mut libc::stat) -> i32 {
 
         let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
         {
-            let trie = trie.lock().unwrap();
             let ret = trie.stat(&sarch_path, stat);
             if ret.is_some() {
-                unsafe {
+                // files in the in-memory layer change size on every write, so they're left
+                // out rather than making each write() drop the cache.
+                if !trie.is_upper_file(&sarch_path) {
                     FILE_TYPE_CACHE
                         .write()
-                        .unwrap()
-                        .insert(path, (*stat).clone())
-                };
+                        .unwrap_or_else(|err| err.into_inner())
+                        .insert(path, unsafe { *stat });
+                }
                 0
             } else {
                 errno::set_errno(errno::Errno(libc::ENOENT));
@@ -182,14 +323,10 @@ pub fn stat_from_fs(path: *const libc::c_char, stat: *mut libc::stat) -> i32 {
         }
     }
 
-    if unsafe { WORKING_DIR.borrow().is_some() } && unsafe { *path } != b'/' {
-        let expand_path = util::expand_kompo_path(path);
-
-        inner_stat(expand_path, stat)
-    } else if util::is_under_kompo_working_dir(path) {
-        inner_stat(path, stat)
-    } else {
-        unsafe { kompo_wrap::STAT_HANDLE(path, stat) }
+    match util::route(path) {
+        util::Route::Vfs(path) => inner_stat(path.as_ptr(), stat),
+        util::Route::Host(path) => unsafe { kompo_wrap::STAT_HANDLE(path.as_ptr(), stat) },
+        util::Route::Error(err) => util::fail(err, -1),
     }
 }
 
@@ -201,26 +338,41 @@ pub unsafe fn fstatat_from_fs(
     flags: libc::c_int,
 ) -> i32 {
     fn inner_fstatat(
-        dirfd: libc::c_int,
+        base_dir: PathBuf,
         path: *const libc::c_char,
         stat: *mut libc::stat,
         flags: libc::c_int,
     ) -> i32 {
-        let path = unsafe { CStr::from_ptr(path) };
-        let path = PathBuf::from_str(path.to_str().expect("invalid path")).expect("invalid path");
+        let current_dir = match util::resolve(&base_dir, unsafe { CStr::from_ptr(path) }) {
+            Ok(current_dir) => current_dir,
+            Err(err) => return util::fail(err, -1),
+        };
 
-        let current_dir = unsafe { WORKING_DIR.borrow() };
-        let current_dir = current_dir.clone().expect("not found current dir");
-        let mut current_dir = PathBuf::from(current_dir.into_owned());
+        fallthrough::lookup(
+            &current_dir,
+            || fstatat_embedded(&current_dir, stat, flags),
+            |host| unsafe {
+                kompo_wrap::FSTATAT_HANDLE(libc::AT_FDCWD, host.as_ptr(), stat, flags)
+            },
+        )
+    }
 
-        util::canonicalize_path(&mut current_dir, &path);
+    fn fstatat_embedded(current_dir: &Path, stat: *mut libc::stat, flags: libc::c_int) -> i32 {
+        if let Some(host) = overlay::host_file(current_dir) {
+            return unsafe {
+                kompo_wrap::FSTATAT_HANDLE(libc::AT_FDCWD, host.as_ptr(), stat, flags)
+            };
+        }
 
         let sarch_path = current_dir.iter().collect::<Vec<_>>();
 
         let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
         {
-            let trie = trie.lock().unwrap();
-            let ret = trie.stat(&sarch_path, stat);
+            let ret = if flags & libc::AT_SYMLINK_NOFOLLOW == libc::AT_SYMLINK_NOFOLLOW {
+                trie.lstat(&sarch_path, stat)
+            } else {
+                trie.stat(&sarch_path, stat)
+            };
             if ret.is_some() {
                 0
             } else {
@@ -230,12 +382,41 @@ pub unsafe fn fstatat_from_fs(
         }
     }
 
-    if util::is_under_kompo_working_dir(pathname) {
-        return stat_from_fs(pathname, buf);
+    if pathname.is_null() {
+        return util::fail(libc::EFAULT, -1);
+    }
+    if *pathname == 0 && flags & libc::AT_EMPTY_PATH == libc::AT_EMPTY_PATH {
+        if util::is_fd_exists_in_kompo(dirfd) {
+            return fstat_from_fs(dirfd, buf);
+        }
+        if let Some(current_dir) = WORKING_DIR.get().filter(|_| dirfd == libc::AT_FDCWD) {
+            return inner_fstatat(current_dir, pathname, buf, flags);
+        }
+    }
+
+    let is_absolute = CStr::from_ptr(pathname).to_bytes().starts_with(b"/");
+    if is_absolute || (dirfd == libc::AT_FDCWD && *pathname != 0) {
+        return match util::route(pathname) {
+            util::Route::Vfs(path) => inner_fstatat(PathBuf::from("/"), path.as_ptr(), buf, flags),
+            util::Route::Host(path) => kompo_wrap::FSTATAT_HANDLE(dirfd, path.as_ptr(), buf, flags),
+            util::Route::Error(err) => util::fail(err, -1),
+        };
     }
 
-    if dirfd == libc::AT_FDCWD && WORKING_DIR.borrow().is_some() && *pathname != b'/' {
-        return inner_fstatat(dirfd, pathname, buf, flags);
+    if util::is_fd_exists_in_kompo(dirfd) {
+        if *pathname == 0 {
+            errno::set_errno(errno::Errno(libc::ENOENT));
+            return -1;
+        }
+        if !util::is_dir_fd_exists_in_kompo(dirfd) {
+            errno::set_errno(errno::Errno(libc::ENOTDIR));
+            return -1;
+        }
+
+        let Some(base_dir) = util::get_path_from_kompo_fd(dirfd) else {
+            return util::fail(libc::EBADF, -1);
+        };
+        return inner_fstatat(base_dir, pathname, buf, flags);
     }
 
     kompo_wrap::FSTATAT_HANDLE(dirfd, pathname, buf, flags)
@@ -245,14 +426,30 @@ pub unsafe fn fstatat_from_fs(
 pub fn lstat_from_fs(path: *const libc::c_char, stat: *mut libc::stat) -> i32 {
     fn inner_lstat(path: *const libc::c_char, stat: *mut libc::stat) -> i32 {
         let path = unsafe { CStr::from_ptr(path) };
-        let path = Path::new(path.to_str().expect("invalid path"));
+        let path = Path::new(OsStr::from_bytes(path.to_bytes()));
+
+        fallthrough::lookup(
+            path,
+            || lstat_embedded(path, stat),
+            |host| unsafe { kompo_wrap::LSTAT_HANDLE(host.as_ptr(), stat) },
+        )
+    }
+
+    fn lstat_embedded(path: &Path, stat: *mut libc::stat) -> i32 {
+        if let Some(host) = overlay::host_file(path) {
+            return unsafe { kompo_wrap::LSTAT_HANDLE(host.as_ptr(), stat) };
+        }
         let path = path
             .iter()
             .map(|os_str| os_str.to_os_string())
             .collect::<Vec<_>>();
 
         // TODO: move to trie.stat()
-        if let Some(cache) = unsafe { FILE_TYPE_CACHE.read().unwrap().get(&path) } {
+        if let Some(cache) = FILE_TYPE_CACHE
+            .read()
+            .unwrap_or_else(|err| err.into_inner())
+            .get(&path)
+        {
             unsafe { *stat = cache.clone() };
             return 0;
         }
@@ -264,15 +461,16 @@ pub fn lstat_from_fs(path: *const libc::c_char, stat: *mut libc::stat) -> i32 {
 
         let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
         {
-            let trie = trie.lock().unwrap();
             let ret = trie.lstat(&sarch_path, stat);
             if ret.is_some() {
-                unsafe {
+                // files in the in-memory layer change size on every write, so they're left
+                // out rather than making each write() drop the cache.
+                if !trie.is_upper_file(&sarch_path) {
                     FILE_TYPE_CACHE
                         .write()
-                        .unwrap()
-                        .insert(path, (*stat).clone())
-                };
+                        .unwrap_or_else(|err| err.into_inner())
+                        .insert(path, unsafe { *stat });
+                }
                 0
             } else {
                 errno::set_errno(errno::Errno(libc::ENOENT));
@@ -281,14 +479,10 @@ pub fn lstat_from_fs(path: *const libc::c_char, stat: *mut libc::stat) -> i32 {
         }
     }
 
-    if unsafe { WORKING_DIR.borrow().is_some() } && unsafe { *path } != b'/' {
-        let expand_path = util::expand_kompo_path(path);
-
-        inner_lstat(expand_path, stat)
-    } else if util::is_under_kompo_working_dir(path) {
-        inner_lstat(path, stat)
-    } else {
-        unsafe { kompo_wrap::LSTAT_HANDLE(path, stat) }
+    match util::route(path) {
+        util::Route::Vfs(path) => inner_lstat(path.as_ptr(), stat),
+        util::Route::Host(path) => unsafe { kompo_wrap::LSTAT_HANDLE(path.as_ptr(), stat) },
+        util::Route::Error(err) => util::fail(err, -1),
     }
 }
 
@@ -296,14 +490,10 @@ pub fn lstat_from_fs(path: *const libc::c_char, stat: *mut libc::stat) -> i32 {
 pub fn fstat_from_fs(fd: i32, stat: *mut libc::stat) -> i32 {
     fn inner_fstat(fd: i32, stat: *mut libc::stat) -> i32 {
         let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
-        let ret = trie.lock().unwrap().fstat(fd, stat);
+        let ret = trie.fstat(fd, stat);
 
-        if ret.is_some() {
-            ret.unwrap()
-        } else {
-            errno::set_errno(errno::Errno(libc::ENOENT));
-            -1
-        }
+        // only a close() racing with this call gets here without an open fd.
+        ret.unwrap_or_else(|| util::fail(libc::EBADF, -1))
     }
 
     if util::is_fd_exists_in_kompo(fd) {
@@ -319,14 +509,9 @@ pub fn read_from_fs(fd: i32, buf: *mut libc::c_void, count: libc::size_t) -> isi
         let mut buf = unsafe { std::slice::from_raw_parts_mut(buf as *mut u8, count as usize) };
 
         let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
-        let ret = trie.lock().expect("trie is poisoned").read(fd, &mut buf);
+        let ret = trie.read(fd, &mut buf);
 
-        if ret.is_some() {
-            ret.unwrap() as isize
-        } else {
-            errno::set_errno(errno::Errno(libc::ENOENT));
-            -1
-        }
+        ret.unwrap_or_else(|err| util::fail(err, -1))
     }
 
     if util::is_fd_exists_in_kompo(fd) {
@@ -338,36 +523,65 @@ pub fn read_from_fs(fd: i32, buf: *mut libc::c_void, count: libc::size_t) -> isi
 
 #[no_mangle]
 pub fn getcwd_from_fs(buf: *mut libc::c_char, count: libc::size_t) -> *const libc::c_char {
-    fn inner_getcwd(buf: *mut libc::c_char, count: libc::size_t) -> *const libc::c_char {
-        let working_dir = unsafe { WORKING_DIR.borrow() };
+    fn inner_getcwd(
+        working_dir: &Path,
+        buf: *mut libc::c_char,
+        count: libc::size_t,
+    ) -> *const libc::c_char {
+        let working_dir = working_dir.as_os_str().as_bytes();
 
-        if working_dir.is_none() {
+        if !buf.is_null() && count == 0 {
+            errno::set_errno(errno::Errno(libc::EINVAL));
+            return std::ptr::null();
+        }
+        if count != 0 && count < working_dir.len() + 1 {
+            errno::set_errno(errno::Errno(libc::ERANGE));
             return std::ptr::null();
         }
 
-        let working_dir = working_dir.clone().unwrap();
-
-        if buf.is_null() {
-            if count == 0 {
-                let working_directory_path =
-                    CString::new(working_dir.to_str().expect("invalid path"))
-                        .expect("invalid path")
-                        .into_boxed_c_str();
-                let ptr = Box::into_raw(working_directory_path);
-
-                ptr as *const libc::c_char
+        // glibc extension: a NULL buffer is allocated with malloc, `count` bytes or just enough.
+        let buf = if buf.is_null() {
+            let size = if count == 0 {
+                working_dir.len() + 1
             } else {
-                todo!()
+                count
+            };
+            let buf = unsafe { libc::malloc(size) as *mut libc::c_char };
+            if buf.is_null() {
+                errno::set_errno(errno::Errno(libc::ENOMEM));
+                return std::ptr::null();
             }
+
+            buf
         } else {
-            todo!()
+            buf
+        };
+
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                working_dir.as_ptr() as *const libc::c_char,
+                buf,
+                working_dir.len(),
+            );
+            *buf.add(working_dir.len()) = 0;
         }
+
+        buf
     }
 
-    if unsafe { WORKING_DIR.borrow().is_some() } {
-        inner_getcwd(buf, count)
+    WORKING_DIR.with(|working_dir| match working_dir {
+        Some(working_dir) => inner_getcwd(working_dir, buf, count),
+        None => unsafe { kompo_wrap::GETCWD_HANDLE(buf, count) },
+    })
+}
+
+#[cfg(target_os = "linux")]
+#[no_mangle]
+pub fn get_current_dir_name_from_fs() -> *mut libc::c_char {
+    if WORKING_DIR.is_virtual() {
+        getcwd_from_fs(std::ptr::null_mut(), 0) as *mut libc::c_char
     } else {
-        unsafe { kompo_wrap::GETCWD_HANDLE(buf, count) }
+        unsafe { kompo_wrap::GET_CURRENT_DIR_NAME_HANDLE() }
     }
 }
 
@@ -375,39 +589,47 @@ pub fn getcwd_from_fs(buf: *mut libc::c_char, count: libc::size_t) -> *const lib
 pub fn chdir_from_fs(path: *const libc::c_char) -> libc::c_int {
     fn inner_chdir(path: *const libc::c_char) -> libc::c_int {
         let path = unsafe { CStr::from_ptr(path) };
-        let path = Path::new(path.to_str().expect("invalid path"));
+        let path = Path::new(OsStr::from_bytes(path.to_bytes()));
 
-        let search_path = path.iter().collect::<Vec<_>>();
-        let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
-        let bool = trie
-            .lock()
-            .expect("trie is poisoned")
-            .is_dir_exists_from_path(&search_path);
-
-        if bool {
-            unsafe {
-                let changed_path = path.as_os_str().to_os_string();
-                let changed_path = Cow::Owned(changed_path);
-                WORKING_DIR.replace(Some(changed_path));
+        match util::kompo_path_kind(path) {
+            Some(true) => {
+                WORKING_DIR.enter(path.to_path_buf());
+                0
             }
-
-            1
-        } else {
-            -1
+            Some(false) => util::fail(libc::ENOTDIR, -1),
+            None => util::fail(libc::ENOENT, -1),
         }
     }
 
-    let change_dir = util::expand_kompo_path(path);
+    match util::route(path) {
+        util::Route::Vfs(path) => inner_chdir(path.as_ptr()),
+        util::Route::Host(path) => {
+            WORKING_DIR.change_on_host(|| unsafe { kompo_wrap::CHDIR_HANDLE(path.as_ptr()) })
+        }
+        util::Route::Error(err) => util::fail(err, -1),
+    }
+}
 
-    if util::is_under_kompo_working_dir(change_dir) {
-        inner_chdir(change_dir)
-    } else {
-        let ret = unsafe { kompo_wrap::CHDIR_HANDLE(path) };
-        if ret == 0 {
-            unsafe { WORKING_DIR.replace(None) };
+#[no_mangle]
+pub fn fchdir_from_fs(fd: i32) -> libc::c_int {
+    fn inner_fchdir(fd: i32) -> libc::c_int {
+        if !util::is_dir_fd_exists_in_kompo(fd) {
+            errno::set_errno(errno::Errno(libc::ENOTDIR));
+            return -1;
         }
 
-        ret
+        let Some(changed_path) = util::get_path_from_kompo_fd(fd) else {
+            return util::fail(libc::EBADF, -1);
+        };
+        WORKING_DIR.enter(changed_path);
+
+        0
+    }
+
+    if util::is_fd_exists_in_kompo(fd) {
+        inner_fchdir(fd)
+    } else {
+        WORKING_DIR.change_on_host(|| unsafe { kompo_wrap::FCHDIR_HANDLE(fd) })
     }
 }
 
@@ -416,14 +638,12 @@ pub fn fdopendir_from_fs(fd: i32) -> *mut libc::DIR {
     fn inner_fdopendir(fd: i32) -> *mut libc::DIR {
         let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
         {
-            let trie = trie.lock().unwrap();
-
             match trie.fdopendir(fd) {
-                Some(dir) => {
+                Ok(dir) => {
                     let dir = Box::new(dir);
                     Box::into_raw(dir) as *mut libc::DIR
                 }
-                None => std::ptr::null_mut(),
+                Err(err) => util::fail(err, std::ptr::null_mut()),
             }
         }
     }
@@ -442,8 +662,6 @@ pub fn readdir_from_fs(dir: *mut libc::DIR) -> *mut libc::dirent {
 
         let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
         {
-            let trie = trie.lock().unwrap();
-
             match trie.readdir(&mut dir) {
                 Some(dirent) => {
                     let _ = Box::into_raw(dir);
@@ -451,7 +669,7 @@ pub fn readdir_from_fs(dir: *mut libc::DIR) -> *mut libc::dirent {
                 }
                 None => {
                     let _ = Box::into_raw(dir);
-                    std::ptr::null_mut()
+                    util::fail(libc::EBADF, std::ptr::null_mut())
                 }
             }
         }
@@ -468,10 +686,7 @@ pub fn readdir_from_fs(dir: *mut libc::DIR) -> *mut libc::dirent {
 pub fn closedir_from_fs(dir: *mut libc::DIR) -> i32 {
     if util::is_dir_exists_in_kompo(dir) {
         let dir = unsafe { Box::from_raw(dir as *mut kompo_storage::FsDir) };
-        std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie))
-            .lock()
-            .unwrap()
-            .closedir(&dir);
+        std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie)).closedir(&dir);
 
         unsafe { kompo_wrap::CLOSE_HANDLE(dir.fd) }
     } else {
@@ -483,31 +698,25 @@ pub fn closedir_from_fs(dir: *mut libc::DIR) -> i32 {
 pub fn opendir_from_fs(path: *const libc::c_char) -> *mut libc::DIR {
     fn inner_opendir(path: *const libc::c_char) -> *mut libc::DIR {
         let path = unsafe { CStr::from_ptr(path) };
-        let path = Path::new(path.to_str().expect("invalid path"));
+        let path = Path::new(OsStr::from_bytes(path.to_bytes()));
         let path = path.iter().collect::<Vec<_>>();
 
         let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
         {
-            let mut trie = trie.lock().unwrap();
-
             match trie.opendir(&path) {
-                Some(dir) => {
+                Ok(dir) => {
                     let dir = Box::new(dir);
                     Box::into_raw(dir) as *mut libc::DIR
                 }
-                None => std::ptr::null_mut(),
+                Err(err) => util::fail(err, std::ptr::null_mut()),
             }
         }
     }
 
-    if unsafe { WORKING_DIR.borrow().is_some() } && unsafe { *path } != b'/' {
-        let expand_path = util::expand_kompo_path(path);
-
-        inner_opendir(expand_path)
-    } else if util::is_under_kompo_working_dir(path) {
-        inner_opendir(path)
-    } else {
-        unsafe { kompo_wrap::OPENDIR_HANDLE(path) }
+    match util::route(path) {
+        util::Route::Vfs(path) => inner_opendir(path.as_ptr()),
+        util::Route::Host(path) => unsafe { kompo_wrap::OPENDIR_HANDLE(path.as_ptr()) },
+        util::Route::Error(err) => util::fail(err, std::ptr::null_mut()),
     }
 }
 
@@ -518,8 +727,6 @@ pub fn rewinddir_from_fs(dir: *mut libc::DIR) {
 
         let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
         {
-            let mut trie = trie.lock().unwrap();
-
             trie.rewinddir(&mut dir);
             let _ = Box::into_raw(dir);
         }
@@ -541,35 +748,1295 @@ pub unsafe extern "C-unwind" fn realpath_from_fs(
         path: *const libc::c_char,
         resolved_path: *mut libc::c_char,
     ) -> *const libc::c_char {
-        if resolved_path.is_null() {
-            let expand_path = util::expand_kompo_path(path);
+        let path = unsafe { CStr::from_ptr(path) };
+        let search_path = Path::new(OsStr::from_bytes(path.to_bytes()))
+            .iter()
+            .collect::<Vec<_>>();
+
+        let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
+        let ret = trie.realpath(&search_path);
+
+        let resolved = match ret {
+            Ok(resolved) => resolved.iter().collect::<PathBuf>(),
+            Err(err) => return util::fail(err, std::ptr::null()),
+        };
+        let resolved = resolved.as_os_str().as_bytes();
+
+        let buf = if resolved_path.is_null() {
+            let buf = unsafe { libc::malloc(resolved.len() + 1) as *mut libc::c_char };
+            if buf.is_null() {
+                errno::set_errno(errno::Errno(libc::ENOMEM));
+                return std::ptr::null();
+            }
 
-            expand_path
+            buf
+        } else if resolved.len() + 1 > libc::PATH_MAX as usize {
+            errno::set_errno(errno::Errno(libc::ENAMETOOLONG));
+            return std::ptr::null();
         } else {
-            let expand_path = unsafe { CStr::from_ptr(util::expand_kompo_path(path)) };
-            let buf =
-                unsafe { std::slice::from_raw_parts_mut(resolved_path, libc::PATH_MAX as usize) };
-            buf.copy_from_slice(expand_path.to_bytes_with_nul());
+            resolved_path
+        };
+
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                resolved.as_ptr() as *const libc::c_char,
+                buf,
+                resolved.len(),
+            );
+            *buf.add(resolved.len()) = 0;
+        }
+
+        buf
+    }
+
+    if path.is_null() {
+        errno::set_errno(errno::Errno(libc::EINVAL));
+        return std::ptr::null();
+    }
+    if *path == 0 {
+        errno::set_errno(errno::Errno(libc::ENOENT));
+        return std::ptr::null();
+    }
+
+    match util::route(path) {
+        util::Route::Vfs(path) => inner_realpath(path.as_ptr(), resolved_path),
+        util::Route::Host(path) => kompo_wrap::REALPATH_HANDLE(path.as_ptr(), resolved_path),
+        util::Route::Error(err) => util::fail(err, std::ptr::null()),
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[no_mangle]
+pub unsafe fn canonicalize_file_name_from_fs(path: *const libc::c_char) -> *mut libc::c_char {
+    if path.is_null() {
+        errno::set_errno(errno::Errno(libc::EINVAL));
+        return std::ptr::null_mut();
+    }
 
-            buf.as_ptr()
+    match util::route(path) {
+        util::Route::Vfs(path) => {
+            realpath_from_fs(path.as_ptr(), std::ptr::null_mut()) as *mut libc::c_char
         }
+        util::Route::Host(path) => kompo_wrap::CANONICALIZE_FILE_NAME_HANDLE(path.as_ptr()),
+        util::Route::Error(err) => util::fail(err, std::ptr::null_mut()),
     }
+}
+
+// mutations of the embedded tree go to kompo_storage's writable layer, which fails them with
+// EROFS when it's disabled. errors come back as errno values and are reported the libc way.
+fn mutate(
+    path: &Path,
+    f: impl FnOnce(&kompo_storage::Fs<'static>, &Vec<&std::ffi::OsStr>) -> Result<(), i32>,
+) -> libc::c_int {
+    let search_path = path.iter().collect::<Vec<_>>();
+    let ret = {
+        let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
+
+        f(&trie, &search_path)
+    };
+
+    match ret {
+        Ok(()) => {
+            invalidate_stat_cache();
+            0
+        }
+        Err(err) => {
+            errno::set_errno(errno::Errno(err));
+            -1
+        }
+    }
+}
 
-    if WORKING_DIR.borrow().is_some() && *path != b'/' {
-        inner_realpath(path, resolved_path)
-    } else if util::is_under_kompo_working_dir(path) {
-        inner_realpath(path, resolved_path)
+fn invalidate_stat_cache() {
+    FILE_TYPE_CACHE
+        .write()
+        .unwrap_or_else(|err| err.into_inner())
+        .clear();
+}
+
+// creating a link at `path`, which the writable layer doesn't support: EEXIST if it's already
+// there, EROFS if its directory exists.
+fn read_only_create(path: &Path) -> libc::c_int {
+    let errno = if util::kompo_path_kind(path).is_some() {
+        libc::EEXIST
+    } else if path
+        .parent()
+        .is_some_and(|dir| util::kompo_path_kind(dir) == Some(true))
+    {
+        libc::EROFS
     } else {
-        unsafe { kompo_wrap::REALPATH_HANDLE(path, resolved_path) }
+        libc::ENOENT
+    };
+    errno::set_errno(errno::Errno(errno));
+
+    -1
+}
+
+fn open_writable(path: &Path, oflag: libc::c_int) -> libc::c_int {
+    let search_path = path.iter().collect::<Vec<_>>();
+    let ret = {
+        let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
+
+        trie.open_with_flags(&search_path, oflag)
+    };
+
+    match ret {
+        Ok(fd) => {
+            invalidate_stat_cache();
+            fd
+        }
+        Err(err) => {
+            errno::set_errno(errno::Errno(err));
+            -1
+        }
     }
 }
 
+// link() with both ends embedded is EROFS, and rename() or link() with only one of them
+// crosses a mount boundary.
+fn read_only_move(old: Option<PathBuf>, new: Option<PathBuf>) -> libc::c_int {
+    let errno = match (old, new) {
+        (Some(old), _) if util::kompo_path_kind(&old).is_none() => libc::ENOENT,
+        (Some(_), Some(new)) => {
+            if util::kompo_path_kind(&new).is_some()
+                || new
+                    .parent()
+                    .is_some_and(|dir| util::kompo_path_kind(dir) == Some(true))
+            {
+                libc::EROFS
+            } else {
+                libc::ENOENT
+            }
+        }
+        _ => libc::EXDEV,
+    };
+    errno::set_errno(errno::Errno(errno));
+
+    -1
+}
+
 #[no_mangle]
 pub fn mkdir_from_fs(path: *const libc::c_char, mode: libc::mode_t) -> libc::c_int {
-    let layout = std::alloc::Layout::new::<libc::stat>();
-    let stat = unsafe { std::alloc::alloc(layout) as *mut libc::stat };
+    match util::kompo_path_at(libc::AT_FDCWD, path) {
+        Ok(Some(path)) if overlay::is_enabled() => overlay::mkdir(&path, mode),
+        Ok(Some(path)) => mutate(&path, |fs, path| fs.mkdir(path)),
+        Ok(None) => unsafe { kompo_wrap::MKDIR_HANDLE(path, mode) },
+        Err(err) => util::fail(err, -1),
+    }
+}
+
+#[no_mangle]
+pub fn mkdirat_from_fs(
+    dirfd: libc::c_int,
+    path: *const libc::c_char,
+    mode: libc::mode_t,
+) -> libc::c_int {
+    match util::kompo_path_at(dirfd, path) {
+        Ok(Some(path)) if overlay::is_enabled() => overlay::mkdir(&path, mode),
+        Ok(Some(path)) => mutate(&path, |fs, path| fs.mkdir(path)),
+        Ok(None) => unsafe { kompo_wrap::MKDIRAT_HANDLE(dirfd, path, mode) },
+        Err(err) => util::fail(err, -1),
+    }
+}
 
-    let ret = stat_from_fs(path, stat);
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct CookieIoFunctions {
+    read: Option<
+        unsafe extern "C" fn(*mut libc::c_void, *mut libc::c_char, libc::size_t) -> libc::ssize_t,
+    >,
+    write: Option<
+        unsafe extern "C" fn(*mut libc::c_void, *const libc::c_char, libc::size_t) -> libc::ssize_t,
+    >,
+    seek: Option<
+        unsafe extern "C" fn(*mut libc::c_void, *mut libc::off64_t, libc::c_int) -> libc::c_int,
+    >,
+    close: Option<unsafe extern "C" fn(*mut libc::c_void) -> libc::c_int>,
+}
 
-    ret
+#[cfg(target_os = "linux")]
+extern "C" {
+    fn fopencookie(
+        cookie: *mut libc::c_void,
+        mode: *const libc::c_char,
+        io_funcs: CookieIoFunctions,
+    ) -> *mut libc::FILE;
+}
+
+#[cfg(target_os = "linux")]
+unsafe extern "C" fn cookie_read(
+    cookie: *mut libc::c_void,
+    buf: *mut libc::c_char,
+    size: libc::size_t,
+) -> libc::ssize_t {
+    read_from_fs(cookie as i32, buf as *mut libc::c_void, size)
+}
+
+#[cfg(target_os = "linux")]
+unsafe extern "C" fn cookie_write(
+    cookie: *mut libc::c_void,
+    buf: *const libc::c_char,
+    size: libc::size_t,
+) -> libc::ssize_t {
+    // stdio takes 0 as the error return here, not -1.
+    write_from_fs(cookie as i32, buf as *const libc::c_void, size).max(0)
+}
+
+#[cfg(target_os = "linux")]
+unsafe extern "C" fn cookie_seek(
+    cookie: *mut libc::c_void,
+    offset: *mut libc::off64_t,
+    whence: libc::c_int,
+) -> libc::c_int {
+    let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
+    let ret = trie.lseek(cookie as i32, *offset, whence);
+
+    match ret {
+        Ok(new_offset) => {
+            *offset = new_offset;
+            0
+        }
+        Err(err) => util::fail(err, -1),
+    }
+}
+
+#[cfg(target_os = "linux")]
+unsafe extern "C" fn cookie_close(cookie: *mut libc::c_void) -> libc::c_int {
+    close_from_fs(cookie as i32)
+}
+
+// glibc's FILE reads through its internal `read`, which we can't interpose, so streams on
+// embedded files are backed by a cookie that forwards to the kompo fd instead.
+#[cfg(target_os = "linux")]
+fn fopen_kompo_fd(fd: i32, mode: *const libc::c_char) -> *mut libc::FILE {
+    // whether writes succeed is up to how the fd was opened.
+    let io_funcs = CookieIoFunctions {
+        read: Some(cookie_read),
+        write: Some(cookie_write),
+        seek: Some(cookie_seek),
+        close: Some(cookie_close),
+    };
+
+    unsafe { fopencookie(fd as usize as *mut libc::c_void, mode, io_funcs) }
+}
+
+// the open(2) flags for an fopen(3) mode string.
+#[cfg(target_os = "linux")]
+fn fopen_oflag(mode: *const libc::c_char) -> libc::c_int {
+    let mode = unsafe { CStr::from_ptr(mode) }.to_bytes();
+
+    let mut oflag = match mode.first() {
+        Some(b'w') => libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC,
+        Some(b'a') => libc::O_WRONLY | libc::O_CREAT | libc::O_APPEND,
+        _ => libc::O_RDONLY,
+    };
+    if mode.contains(&b'+') {
+        oflag = oflag & !libc::O_ACCMODE | libc::O_RDWR;
+    }
+    if mode.contains(&b'x') {
+        oflag |= libc::O_EXCL;
+    }
+    if mode.contains(&b'e') {
+        oflag |= libc::O_CLOEXEC;
+    }
+
+    oflag
+}
+
+#[cfg(target_os = "linux")]
+#[no_mangle]
+pub fn fopen_from_fs(path: *const libc::c_char, mode: *const libc::c_char) -> *mut libc::FILE {
+    fn inner_fopen(path: *const libc::c_char, mode: *const libc::c_char) -> *mut libc::FILE {
+        let fd = open_from_fs(path, fopen_oflag(mode), 0o666);
+        if fd == -1 {
+            return std::ptr::null_mut();
+        }
+        if !util::is_fd_exists_in_kompo(fd) {
+            // a real file (KOMPO_MEMFD, KOMPO_EXTRACT or the overlay), so plain stdio works.
+            return unsafe { kompo_wrap::FDOPEN_HANDLE(fd, mode) };
+        }
+
+        let mut stat = std::mem::MaybeUninit::<libc::stat>::uninit();
+        if fstat_from_fs(fd, stat.as_mut_ptr()) == 0
+            && unsafe { stat.assume_init() }.st_mode & libc::S_IFMT == libc::S_IFDIR
+        {
+            close_from_fs(fd);
+            errno::set_errno(errno::Errno(libc::EISDIR));
+            return std::ptr::null_mut();
+        }
+
+        let file = fopen_kompo_fd(fd, mode);
+        if file.is_null() {
+            let err = errno::errno();
+            close_from_fs(fd);
+            errno::set_errno(err);
+        }
+
+        file
+    }
+
+    match util::route(path) {
+        util::Route::Vfs(path) => inner_fopen(path.as_ptr(), mode),
+        util::Route::Host(path) => unsafe { kompo_wrap::FOPEN_HANDLE(path.as_ptr(), mode) },
+        util::Route::Error(err) => util::fail(err, std::ptr::null_mut()),
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[no_mangle]
+pub fn fopen64_from_fs(path: *const libc::c_char, mode: *const libc::c_char) -> *mut libc::FILE {
+    match util::route(path) {
+        util::Route::Vfs(path) => fopen_from_fs(path.as_ptr(), mode),
+        util::Route::Host(path) => unsafe { kompo_wrap::FOPEN64_HANDLE(path.as_ptr(), mode) },
+        util::Route::Error(err) => util::fail(err, std::ptr::null_mut()),
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[no_mangle]
+pub fn fdopen_from_fs(fd: i32, mode: *const libc::c_char) -> *mut libc::FILE {
+    if util::is_fd_exists_in_kompo(fd) {
+        fopen_kompo_fd(fd, mode)
+    } else {
+        unsafe { kompo_wrap::FDOPEN_HANDLE(fd, mode) }
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[no_mangle]
+pub fn freopen_from_fs(
+    path: *const libc::c_char,
+    mode: *const libc::c_char,
+    stream: *mut libc::FILE,
+) -> *mut libc::FILE {
+    // stdio can only reopen `stream` in place onto a real file, so the host's freopen() gets
+    // one through /proc/self/fd: the overlay's or an extracted copy as they are, and anything
+    // else copied into a memfd. a stream the VFS can't back that way is left untouched.
+    fn inner_freopen(
+        path: *const libc::c_char,
+        mode: *const libc::c_char,
+        stream: *mut libc::FILE,
+    ) -> *mut libc::FILE {
+        let oflag = fopen_oflag(mode);
+        let vfs_path = Path::new(OsStr::from_bytes(
+            unsafe { CStr::from_ptr(path) }.to_bytes(),
+        ));
+        let is_writable = {
+            let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
+
+            trie.is_writable()
+        };
+        // a memfd copy of a file in the in-memory layer would never write back to it.
+        if oflag & libc::O_ACCMODE != libc::O_RDONLY && is_writable && !overlay::is_enabled() {
+            return util::fail(libc::EOPNOTSUPP, std::ptr::null_mut());
+        }
+        if util::kompo_path_kind(vfs_path) == Some(true) {
+            return util::fail(libc::EISDIR, std::ptr::null_mut());
+        }
+
+        let fd = open_from_fs(path, oflag, 0o666);
+        if fd == -1 {
+            return std::ptr::null_mut();
+        }
+        let fd = if util::is_fd_exists_in_kompo(fd) {
+            close_from_fs(fd);
+            match memfd::open_memfd(&vfs_path.iter().collect(), libc::O_CLOEXEC) {
+                Some(fd) => fd,
+                None => return util::fail(libc::ENOMEM, std::ptr::null_mut()),
+            }
+        } else {
+            fd
+        };
+
+        // the file has been created, truncated or checked for O_EXCL already.
+        let mode = unsafe { CStr::from_ptr(mode) }
+            .to_bytes()
+            .iter()
+            .copied()
+            .filter(|b| *b != b'x')
+            .collect::<Vec<_>>();
+        let mode = CString::new(mode).expect("invalid mode");
+        let proc_path = CString::new(format!("/proc/self/fd/{}", fd)).expect("invalid path");
+
+        let file = unsafe { kompo_wrap::FREOPEN_HANDLE(proc_path.as_ptr(), mode.as_ptr(), stream) };
+        let err = errno::errno();
+        unsafe { kompo_wrap::CLOSE_HANDLE(fd) };
+        errno::set_errno(err);
+
+        file
+    }
+
+    if path.is_null() {
+        return unsafe { kompo_wrap::FREOPEN_HANDLE(path, mode, stream) };
+    }
+
+    match util::route(path) {
+        util::Route::Vfs(path) => inner_freopen(path.as_ptr(), mode, stream),
+        util::Route::Host(path) => unsafe {
+            kompo_wrap::FREOPEN_HANDLE(path.as_ptr(), mode, stream)
+        },
+        util::Route::Error(err) => util::fail(err, std::ptr::null_mut()),
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[no_mangle]
+pub fn dlopen_from_fs(filename: *const libc::c_char, flag: libc::c_int) -> *mut libc::c_void {
+    // embedded objects are loaded from a memfd through /proc/self/fd/N. None means the path
+    // isn't embedded, and the host's dlopen() gets to report the error.
+    fn inner_dlopen(
+        path: &Path,
+        flag: libc::c_int,
+        loading: &mut Vec<PathBuf>,
+    ) -> Option<*mut libc::c_void> {
+        let search_path = path.iter().collect::<Vec<_>>();
+        let contents = overlay::file_contents(path)?;
+
+        // load embedded DT_NEEDED siblings first. the loader matches DT_NEEDED names against the
+        // sonames of objects already loaded, so it won't go looking for them on the host.
+        loading.push(path.to_path_buf());
+        if let Some(dir) = path.parent() {
+            for name in elf::needed_libraries(&contents) {
+                let sibling = dir.join(std::ffi::OsStr::from_bytes(&name));
+
+                if !loading.contains(&sibling) {
+                    inner_dlopen(&sibling, flag, loading);
+                }
+            }
+        }
+
+        // a copied-up object is a real file on the host already.
+        if let Some(host) = overlay::host_file(path) {
+            return Some(unsafe { kompo_wrap::DLOPEN_HANDLE(host.as_ptr(), flag) });
+        }
+        if extract::is_extract_path(&search_path) {
+            if let Some(extracted) = extract::extracted_path(&search_path) {
+                return Some(unsafe { kompo_wrap::DLOPEN_HANDLE(extracted.as_ptr(), flag) });
+            }
+        }
+
+        let fd = memfd::shared_object_fd(&search_path)?;
+        let proc_path = CString::new(format!("/proc/self/fd/{}", fd)).expect("invalid path");
+
+        Some(unsafe { kompo_wrap::DLOPEN_HANDLE(proc_path.as_ptr(), flag) })
+    }
+
+    if filename.is_null() {
+        return unsafe { kompo_wrap::DLOPEN_HANDLE(filename, flag) };
+    }
+
+    let name = unsafe { CStr::from_ptr(filename) };
+    // without a slash dlopen() searches the library path, never the current directory.
+    if !name.to_bytes().contains(&b'/') {
+        return unsafe { kompo_wrap::DLOPEN_HANDLE(filename, flag) };
+    }
+
+    let path = match util::route(filename) {
+        util::Route::Vfs(path) => Some(path),
+        // the host's dlopen() reports a path that doesn't resolve, too.
+        util::Route::Host(_) | util::Route::Error(_) => None,
+    };
+
+    path.and_then(|path| {
+        let path = Path::new(OsStr::from_bytes(path.to_bytes()));
+
+        inner_dlopen(path, flag, &mut vec![])
+    })
+    .unwrap_or_else(|| unsafe { kompo_wrap::DLOPEN_HANDLE(filename, flag) })
+}
+
+#[cfg(target_os = "linux")]
+fn execve_target(
+    target: Option<exec::ExecTarget>,
+    argv: *const *const libc::c_char,
+    envp: *const *const libc::c_char,
+) -> libc::c_int {
+    let Some(target) = target else {
+        return -1;
+    };
+
+    let envp = target.envp(envp);
+    let ret = unsafe { kompo_wrap::EXECVE_HANDLE(target.path.as_ptr(), argv, envp.as_ptr()) };
+    target.close();
+
+    ret
+}
+
+#[cfg(target_os = "linux")]
+#[no_mangle]
+pub fn execve_from_fs(
+    path: *const libc::c_char,
+    argv: *const *const libc::c_char,
+    envp: *const *const libc::c_char,
+) -> libc::c_int {
+    if let Some((target, argv)) = exec::ruby_command(path, argv) {
+        return execve_target(Some(target), argv.as_ptr(), envp);
+    }
+
+    match exec::vfs_path(path) {
+        Some(path) => execve_target(exec::target(path), argv, envp),
+        None => unsafe { kompo_wrap::EXECVE_HANDLE(path, argv, envp) },
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[no_mangle]
+pub fn execv_from_fs(path: *const libc::c_char, argv: *const *const libc::c_char) -> libc::c_int {
+    if let Some((target, argv)) = exec::ruby_command(path, argv) {
+        return execve_target(Some(target), argv.as_ptr(), unsafe { exec::environ });
+    }
+
+    match exec::vfs_path(path) {
+        Some(path) => execve_target(exec::target(path), argv, unsafe { exec::environ }),
+        None => unsafe { kompo_wrap::EXECV_HANDLE(path, argv) },
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[no_mangle]
+pub fn execvp_from_fs(file: *const libc::c_char, argv: *const *const libc::c_char) -> libc::c_int {
+    if let Some((target, argv)) = exec::ruby_command(file, argv) {
+        return execve_target(Some(target), argv.as_ptr(), unsafe { exec::environ });
+    }
+
+    match exec::search_vfs_path(file) {
+        Some(path) => execve_target(exec::target(path), argv, unsafe { exec::environ }),
+        None => unsafe { kompo_wrap::EXECVP_HANDLE(file, argv) },
+    }
+}
+
+// the GNU execvp() with an environment; the search still uses the caller's $PATH.
+#[cfg(target_os = "linux")]
+#[no_mangle]
+pub fn execvpe_from_fs(
+    file: *const libc::c_char,
+    argv: *const *const libc::c_char,
+    envp: *const *const libc::c_char,
+) -> libc::c_int {
+    if let Some((target, argv)) = exec::ruby_command(file, argv) {
+        return execve_target(Some(target), argv.as_ptr(), envp);
+    }
+
+    match exec::search_vfs_path(file) {
+        Some(path) => execve_target(exec::target(path), argv, envp),
+        None => unsafe { kompo_wrap::EXECVPE_HANDLE(file, argv, envp) },
+    }
+}
+
+// a kompo fd runs whatever file it was opened from, like exec'ing that path.
+#[cfg(target_os = "linux")]
+#[no_mangle]
+pub fn fexecve_from_fs(
+    fd: libc::c_int,
+    argv: *const *const libc::c_char,
+    envp: *const *const libc::c_char,
+) -> libc::c_int {
+    if !util::is_fd_exists_in_kompo(fd) {
+        return unsafe { kompo_wrap::FEXECVE_HANDLE(fd, argv, envp) };
+    }
+
+    match util::get_path_from_kompo_fd(fd) {
+        Some(path) => execve_target(exec::target(Ok(path)), argv, envp),
+        None => util::fail(libc::EBADF, -1),
+    }
+}
+
+// the exec hooks allocate and take the VFS locks, which a vfork() child mustn't do while it
+// borrows its parent's memory. a fork() child has its own copy, with the locks reset by the
+// atfork handlers, at the cost of copying the page tables. the downgrade is safe because
+// vfork() only allows the child to exec or _exit, which behave the same in a fork() child.
+// the parent just resumes sooner: nothing the child may do could have touched its memory.
+#[cfg(target_os = "linux")]
+#[no_mangle]
+pub fn vfork_from_fs() -> libc::pid_t {
+    unsafe { libc::fork() }
+}
+
+// posix_spawn(3) reports errors through its return value instead of errno.
+#[cfg(target_os = "linux")]
+fn posix_spawn_target(
+    pid: *mut libc::pid_t,
+    target: Option<exec::ExecTarget>,
+    file_actions: *const libc::posix_spawn_file_actions_t,
+    attrp: *const libc::posix_spawnattr_t,
+    argv: *const *mut libc::c_char,
+    envp: *const *mut libc::c_char,
+) -> libc::c_int {
+    let Some(target) = target else {
+        return errno::errno().0;
+    };
+
+    let envp = target.envp(envp as *const *const libc::c_char);
+    let ret = unsafe {
+        kompo_wrap::POSIX_SPAWN_HANDLE(
+            pid,
+            target.path.as_ptr(),
+            file_actions,
+            attrp,
+            argv,
+            envp.as_ptr() as *const *mut libc::c_char,
+        )
+    };
+    target.close();
+
+    ret
+}
+
+#[cfg(target_os = "linux")]
+#[no_mangle]
+pub fn posix_spawn_from_fs(
+    pid: *mut libc::pid_t,
+    path: *const libc::c_char,
+    file_actions: *const libc::posix_spawn_file_actions_t,
+    attrp: *const libc::posix_spawnattr_t,
+    argv: *const *mut libc::c_char,
+    envp: *const *mut libc::c_char,
+) -> libc::c_int {
+    if let Some((target, argv)) = exec::ruby_command(path, argv as *const *const libc::c_char) {
+        let argv = argv.as_ptr() as *const *mut libc::c_char;
+        return posix_spawn_target(pid, Some(target), file_actions, attrp, argv, envp);
+    }
+
+    match exec::vfs_path(path) {
+        Some(path) => {
+            let target = exec::target(path);
+            posix_spawn_target(pid, target, file_actions, attrp, argv, envp)
+        }
+        None => unsafe {
+            kompo_wrap::POSIX_SPAWN_HANDLE(pid, path, file_actions, attrp, argv, envp)
+        },
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[no_mangle]
+pub fn posix_spawnp_from_fs(
+    pid: *mut libc::pid_t,
+    file: *const libc::c_char,
+    file_actions: *const libc::posix_spawn_file_actions_t,
+    attrp: *const libc::posix_spawnattr_t,
+    argv: *const *mut libc::c_char,
+    envp: *const *mut libc::c_char,
+) -> libc::c_int {
+    if let Some((target, argv)) = exec::ruby_command(file, argv as *const *const libc::c_char) {
+        let argv = argv.as_ptr() as *const *mut libc::c_char;
+        return posix_spawn_target(pid, Some(target), file_actions, attrp, argv, envp);
+    }
+
+    match exec::search_vfs_path(file) {
+        Some(path) => {
+            let target = exec::target(path);
+            posix_spawn_target(pid, target, file_actions, attrp, argv, envp)
+        }
+        None => unsafe {
+            kompo_wrap::POSIX_SPAWNP_HANDLE(pid, file, file_actions, attrp, argv, envp)
+        },
+    }
+}
+
+#[no_mangle]
+pub fn unlink_from_fs(path: *const libc::c_char) -> libc::c_int {
+    match util::kompo_path_at(libc::AT_FDCWD, path) {
+        Ok(Some(path)) if overlay::is_enabled() => overlay::unlink(&path),
+        Ok(Some(path)) => mutate(&path, |fs, path| fs.unlink(path)),
+        Ok(None) => unsafe { kompo_wrap::UNLINK_HANDLE(path) },
+        Err(err) => util::fail(err, -1),
+    }
+}
+
+#[no_mangle]
+pub fn unlinkat_from_fs(
+    dirfd: libc::c_int,
+    path: *const libc::c_char,
+    flags: libc::c_int,
+) -> libc::c_int {
+    match util::kompo_path_at(dirfd, path) {
+        Ok(Some(path)) if flags & libc::AT_REMOVEDIR == libc::AT_REMOVEDIR => {
+            if overlay::is_enabled() {
+                overlay::rmdir(&path)
+            } else {
+                mutate(&path, |fs, path| fs.rmdir(path))
+            }
+        }
+        Ok(Some(path)) if overlay::is_enabled() => overlay::unlink(&path),
+        Ok(Some(path)) => mutate(&path, |fs, path| fs.unlink(path)),
+        Ok(None) => unsafe { kompo_wrap::UNLINKAT_HANDLE(dirfd, path, flags) },
+        Err(err) => util::fail(err, -1),
+    }
+}
+
+#[no_mangle]
+pub fn rmdir_from_fs(path: *const libc::c_char) -> libc::c_int {
+    match util::kompo_path_at(libc::AT_FDCWD, path) {
+        Ok(Some(path)) if overlay::is_enabled() => overlay::rmdir(&path),
+        Ok(Some(path)) => mutate(&path, |fs, path| fs.rmdir(path)),
+        Ok(None) => unsafe { kompo_wrap::RMDIR_HANDLE(path) },
+        Err(err) => util::fail(err, -1),
+    }
+}
+
+// the VFS paths of both ends of a rename() or link(), each None if it's on the host.
+fn kompo_paths_at(
+    olddirfd: libc::c_int,
+    old: *const libc::c_char,
+    newdirfd: libc::c_int,
+    new: *const libc::c_char,
+) -> Result<(Option<PathBuf>, Option<PathBuf>), libc::c_int> {
+    Ok((
+        util::kompo_path_at(olddirfd, old)?,
+        util::kompo_path_at(newdirfd, new)?,
+    ))
+}
+
+fn rename_paths(
+    old_path: Option<PathBuf>,
+    new_path: Option<PathBuf>,
+    host: impl FnOnce() -> libc::c_int,
+) -> libc::c_int {
+    match (old_path, new_path) {
+        (None, None) => host(),
+        (Some(old_path), Some(new_path)) if overlay::is_enabled() => {
+            overlay::rename(&old_path, &new_path)
+        }
+        (Some(old_path), Some(new_path)) => mutate(&old_path, |fs, old_path| {
+            fs.rename(old_path, &new_path.iter().collect())
+        }),
+        (old_path, new_path) => read_only_move(old_path, new_path),
+    }
+}
+
+#[no_mangle]
+pub fn rename_from_fs(old: *const libc::c_char, new: *const libc::c_char) -> libc::c_int {
+    match kompo_paths_at(libc::AT_FDCWD, old, libc::AT_FDCWD, new) {
+        Ok((old_path, new_path)) => rename_paths(old_path, new_path, || unsafe {
+            kompo_wrap::RENAME_HANDLE(old, new)
+        }),
+        Err(err) => util::fail(err, -1),
+    }
+}
+
+#[no_mangle]
+pub fn renameat_from_fs(
+    olddirfd: libc::c_int,
+    old: *const libc::c_char,
+    newdirfd: libc::c_int,
+    new: *const libc::c_char,
+) -> libc::c_int {
+    match kompo_paths_at(olddirfd, old, newdirfd, new) {
+        Ok((old_path, new_path)) => rename_paths(old_path, new_path, || unsafe {
+            kompo_wrap::RENAMEAT_HANDLE(olddirfd, old, newdirfd, new)
+        }),
+        Err(err) => util::fail(err, -1),
+    }
+}
+
+// RENAME_NOREPLACE is honored in the VFS. RENAME_EXCHANGE and RENAME_WHITEOUT aren't
+// supported there, and fail with EINVAL as on a filesystem without them.
+#[cfg(target_os = "linux")]
+#[no_mangle]
+pub fn renameat2_from_fs(
+    olddirfd: libc::c_int,
+    old: *const libc::c_char,
+    newdirfd: libc::c_int,
+    new: *const libc::c_char,
+    flags: libc::c_uint,
+) -> libc::c_int {
+    let (old_path, new_path) = match kompo_paths_at(olddirfd, old, newdirfd, new) {
+        Ok(paths) => paths,
+        Err(err) => return util::fail(err, -1),
+    };
+
+    if old_path.is_some() || new_path.is_some() {
+        if flags & !libc::RENAME_NOREPLACE != 0 {
+            return util::fail(libc::EINVAL, -1);
+        }
+        if flags & libc::RENAME_NOREPLACE != 0
+            && new_path
+                .as_deref()
+                .is_some_and(|new_path| util::kompo_path_kind(new_path).is_some())
+        {
+            return util::fail(libc::EEXIST, -1);
+        }
+    }
+
+    rename_paths(old_path, new_path, || unsafe {
+        kompo_wrap::RENAMEAT2_HANDLE(olddirfd, old, newdirfd, new, flags)
+    })
+}
+
+#[no_mangle]
+pub fn link_from_fs(old: *const libc::c_char, new: *const libc::c_char) -> libc::c_int {
+    match kompo_paths_at(libc::AT_FDCWD, old, libc::AT_FDCWD, new) {
+        Ok((None, None)) => unsafe { kompo_wrap::LINK_HANDLE(old, new) },
+        Ok((old_path, new_path)) => read_only_move(old_path, new_path),
+        Err(err) => util::fail(err, -1),
+    }
+}
+
+#[no_mangle]
+pub fn linkat_from_fs(
+    olddirfd: libc::c_int,
+    old: *const libc::c_char,
+    newdirfd: libc::c_int,
+    new: *const libc::c_char,
+    flags: libc::c_int,
+) -> libc::c_int {
+    match kompo_paths_at(olddirfd, old, newdirfd, new) {
+        Ok((None, None)) => unsafe {
+            kompo_wrap::LINKAT_HANDLE(olddirfd, old, newdirfd, new, flags)
+        },
+        Ok((old_path, new_path)) => read_only_move(old_path, new_path),
+        Err(err) => util::fail(err, -1),
+    }
+}
+
+// only the link itself is created; its target is just a string.
+#[no_mangle]
+pub fn symlink_from_fs(target: *const libc::c_char, linkpath: *const libc::c_char) -> libc::c_int {
+    match util::kompo_path_at(libc::AT_FDCWD, linkpath) {
+        Ok(Some(linkpath)) => read_only_create(&linkpath),
+        Ok(None) => unsafe { kompo_wrap::SYMLINK_HANDLE(target, linkpath) },
+        Err(err) => util::fail(err, -1),
+    }
+}
+
+#[no_mangle]
+pub fn symlinkat_from_fs(
+    target: *const libc::c_char,
+    newdirfd: libc::c_int,
+    linkpath: *const libc::c_char,
+) -> libc::c_int {
+    match util::kompo_path_at(newdirfd, linkpath) {
+        Ok(Some(linkpath)) => read_only_create(&linkpath),
+        Ok(None) => unsafe { kompo_wrap::SYMLINKAT_HANDLE(target, newdirfd, linkpath) },
+        Err(err) => util::fail(err, -1),
+    }
+}
+
+#[no_mangle]
+pub fn chmod_from_fs(path: *const libc::c_char, mode: libc::mode_t) -> libc::c_int {
+    match util::kompo_path_at(libc::AT_FDCWD, path) {
+        Ok(Some(path)) if overlay::is_enabled() => overlay::chmod(&path, mode),
+        Ok(Some(path)) => mutate(&path, |fs, path| fs.set_metadata(path)),
+        Ok(None) => unsafe { kompo_wrap::CHMOD_HANDLE(path, mode) },
+        Err(err) => util::fail(err, -1),
+    }
+}
+
+#[no_mangle]
+pub fn fchmodat_from_fs(
+    dirfd: libc::c_int,
+    path: *const libc::c_char,
+    mode: libc::mode_t,
+    flags: libc::c_int,
+) -> libc::c_int {
+    match util::kompo_path_at(dirfd, path) {
+        Ok(Some(path)) if overlay::is_enabled() => overlay::chmod(&path, mode),
+        Ok(Some(path)) => mutate(&path, |fs, path| fs.set_metadata(path)),
+        Ok(None) => unsafe { kompo_wrap::FCHMODAT_HANDLE(dirfd, path, mode, flags) },
+        Err(err) => util::fail(err, -1),
+    }
+}
+
+#[no_mangle]
+pub fn fchmod_from_fs(fd: libc::c_int, mode: libc::mode_t) -> libc::c_int {
+    if !util::is_fd_exists_in_kompo(fd) {
+        return unsafe { kompo_wrap::FCHMOD_HANDLE(fd, mode) };
+    }
+
+    match util::get_path_from_kompo_fd(fd) {
+        Some(path) if overlay::is_enabled() => overlay::chmod(&path, mode),
+        Some(path) => mutate(&path, |fs, path| fs.set_metadata(path)),
+        None => util::fail(libc::EBADF, -1),
+    }
+}
+
+// ownership isn't stored in the writable layer either; chown() there only checks the path.
+fn chown_path(path: &Path, owner: libc::uid_t, group: libc::gid_t) -> libc::c_int {
+    if overlay::is_enabled() {
+        overlay::chown(path, owner, group)
+    } else {
+        mutate(path, |fs, path| fs.set_metadata(path))
+    }
+}
+
+#[no_mangle]
+pub fn chown_from_fs(
+    path: *const libc::c_char,
+    owner: libc::uid_t,
+    group: libc::gid_t,
+) -> libc::c_int {
+    match util::kompo_path_at(libc::AT_FDCWD, path) {
+        Ok(Some(path)) => chown_path(&path, owner, group),
+        Ok(None) => unsafe { kompo_wrap::CHOWN_HANDLE(path, owner, group) },
+        Err(err) => util::fail(err, -1),
+    }
+}
+
+// the VFS has no symlinks, so lchown() is chown() there.
+#[no_mangle]
+pub fn lchown_from_fs(
+    path: *const libc::c_char,
+    owner: libc::uid_t,
+    group: libc::gid_t,
+) -> libc::c_int {
+    match util::kompo_path_at(libc::AT_FDCWD, path) {
+        Ok(Some(path)) => chown_path(&path, owner, group),
+        Ok(None) => unsafe { kompo_wrap::LCHOWN_HANDLE(path, owner, group) },
+        Err(err) => util::fail(err, -1),
+    }
+}
+
+#[no_mangle]
+pub fn fchown_from_fs(fd: libc::c_int, owner: libc::uid_t, group: libc::gid_t) -> libc::c_int {
+    if !util::is_fd_exists_in_kompo(fd) {
+        return unsafe { kompo_wrap::FCHOWN_HANDLE(fd, owner, group) };
+    }
+
+    match util::get_path_from_kompo_fd(fd) {
+        Some(path) => chown_path(&path, owner, group),
+        None => util::fail(libc::EBADF, -1),
+    }
+}
+
+#[no_mangle]
+pub fn fchownat_from_fs(
+    dirfd: libc::c_int,
+    path: *const libc::c_char,
+    owner: libc::uid_t,
+    group: libc::gid_t,
+    flags: libc::c_int,
+) -> libc::c_int {
+    // an empty path with AT_EMPTY_PATH is fchown(dirfd).
+    let is_empty = path.is_null() || unsafe { *path } == 0;
+    if is_empty && flags & libc::AT_EMPTY_PATH != 0 {
+        return fchown_from_fs(dirfd, owner, group);
+    }
+
+    match util::kompo_path_at(dirfd, path) {
+        Ok(Some(path)) => chown_path(&path, owner, group),
+        Ok(None) => unsafe { kompo_wrap::FCHOWNAT_HANDLE(dirfd, path, owner, group, flags) },
+        Err(err) => util::fail(err, -1),
+    }
+}
+
+#[no_mangle]
+pub fn truncate_from_fs(path: *const libc::c_char, length: libc::off_t) -> libc::c_int {
+    match util::kompo_path_at(libc::AT_FDCWD, path) {
+        Ok(Some(path)) if overlay::is_enabled() => overlay::truncate(&path, length),
+        Ok(Some(path)) => mutate(&path, |fs, path| fs.truncate(path, length)),
+        Ok(None) => unsafe { kompo_wrap::TRUNCATE_HANDLE(path, length) },
+        Err(err) => util::fail(err, -1),
+    }
+}
+
+#[no_mangle]
+pub fn utimensat_from_fs(
+    dirfd: libc::c_int,
+    path: *const libc::c_char,
+    times: *const libc::timespec,
+    flags: libc::c_int,
+) -> libc::c_int {
+    // a NULL path is futimens(dirfd).
+    if path.is_null() && util::is_fd_exists_in_kompo(dirfd) {
+        let Some(path) = util::get_path_from_kompo_fd(dirfd) else {
+            return util::fail(libc::EBADF, -1);
+        };
+        if overlay::is_enabled() {
+            return overlay::utimensat(&path, times, 0);
+        }
+        return mutate(&path, |fs, path| fs.set_metadata(path));
+    }
+
+    match util::kompo_path_at(dirfd, path) {
+        Ok(Some(path)) if overlay::is_enabled() => overlay::utimensat(&path, times, flags),
+        Ok(Some(path)) => mutate(&path, |fs, path| fs.set_metadata(path)),
+        Ok(None) => unsafe { kompo_wrap::UTIMENSAT_HANDLE(dirfd, path, times, flags) },
+        Err(err) => util::fail(err, -1),
+    }
+}
+
+#[no_mangle]
+pub fn write_from_fs(fd: i32, buf: *const libc::c_void, count: libc::size_t) -> isize {
+    fn inner_write(fd: i32, buf: *const libc::c_void, count: libc::size_t) -> isize {
+        let buf = unsafe { std::slice::from_raw_parts(buf as *const u8, count) };
+
+        let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
+        let ret = trie.write(fd, buf);
+
+        ret.unwrap_or_else(|err| util::fail(err, -1))
+    }
+
+    if util::is_fd_exists_in_kompo(fd) {
+        inner_write(fd, buf, count)
+    } else {
+        unsafe { kompo_wrap::WRITE_HANDLE(fd, buf, count) }
+    }
+}
+
+#[no_mangle]
+pub fn ftruncate_from_fs(fd: i32, length: libc::off_t) -> libc::c_int {
+    if !util::is_fd_exists_in_kompo(fd) {
+        return unsafe { kompo_wrap::FTRUNCATE_HANDLE(fd, length) };
+    }
+
+    let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
+    let ret = trie.ftruncate(fd, length);
+
+    match ret {
+        Ok(()) => 0,
+        Err(err) => util::fail(err, -1),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn errno_of(ret: libc::c_int) -> Option<libc::c_int> {
+        (ret == -1).then(|| errno::errno().0)
+    }
+
+    // the test archive has no writable layer, so every change to it is refused.
+    #[test]
+    fn test_read_only_mutations() {
+        let hello = c"/__kompo__/lib/hello.rb";
+        let new = c"/__kompo__/lib/new.rb";
+
+        // O_CREAT on a file that's there only opens it.
+        let fd = open_from_fs(hello.as_ptr(), libc::O_RDONLY | libc::O_CREAT, 0o644);
+        assert_ne!(fd, -1);
+        assert_eq!(close_from_fs(fd), 0);
+        assert_eq!(
+            errno_of(open_from_fs(
+                hello.as_ptr(),
+                libc::O_RDONLY | libc::O_CREAT | libc::O_EXCL,
+                0o644
+            )),
+            Some(libc::EEXIST)
+        );
+        assert_eq!(
+            errno_of(open_from_fs(
+                new.as_ptr(),
+                libc::O_RDONLY | libc::O_CREAT,
+                0o644
+            )),
+            Some(libc::EROFS)
+        );
+
+        assert_eq!(
+            errno_of(mkdirat_from_fs(
+                libc::AT_FDCWD,
+                c"/__kompo__/new".as_ptr(),
+                0o755
+            )),
+            Some(libc::EROFS)
+        );
+        assert_eq!(
+            errno_of(mkdirat_from_fs(
+                libc::AT_FDCWD,
+                c"/__kompo__/lib".as_ptr(),
+                0o755
+            )),
+            Some(libc::EEXIST)
+        );
+        assert_eq!(
+            errno_of(renameat_from_fs(
+                libc::AT_FDCWD,
+                hello.as_ptr(),
+                libc::AT_FDCWD,
+                new.as_ptr()
+            )),
+            Some(libc::EROFS)
+        );
+        assert_eq!(
+            errno_of(linkat_from_fs(
+                libc::AT_FDCWD,
+                hello.as_ptr(),
+                libc::AT_FDCWD,
+                new.as_ptr(),
+                0
+            )),
+            Some(libc::EROFS)
+        );
+        assert_eq!(
+            errno_of(symlinkat_from_fs(
+                hello.as_ptr(),
+                libc::AT_FDCWD,
+                new.as_ptr()
+            )),
+            Some(libc::EROFS)
+        );
+        assert_eq!(
+            errno_of(fchmodat_from_fs(libc::AT_FDCWD, hello.as_ptr(), 0o600, 0)),
+            Some(libc::EROFS)
+        );
+        assert_eq!(
+            errno_of(chown_from_fs(hello.as_ptr(), 0, 0)),
+            Some(libc::EROFS)
+        );
+        assert_eq!(
+            errno_of(lchown_from_fs(new.as_ptr(), 0, 0)),
+            Some(libc::ENOENT)
+        );
+        assert_eq!(
+            errno_of(fchownat_from_fs(libc::AT_FDCWD, hello.as_ptr(), 0, 0, 0)),
+            Some(libc::EROFS)
+        );
+
+        let fd = open_from_fs(hello.as_ptr(), libc::O_RDONLY, 0);
+        assert_eq!(errno_of(fchmod_from_fs(fd, 0o600)), Some(libc::EROFS));
+        assert_eq!(errno_of(fchown_from_fs(fd, 0, 0)), Some(libc::EROFS));
+        assert_eq!(
+            errno_of(fchownat_from_fs(
+                fd,
+                c"".as_ptr(),
+                0,
+                0,
+                libc::AT_EMPTY_PATH
+            )),
+            Some(libc::EROFS)
+        );
+        assert_eq!(close_from_fs(fd), 0);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_freopen_keeps_stream() {
+        let stream = unsafe { libc::tmpfile() };
+        assert!(!stream.is_null());
+
+        // a failed reopen leaves the stream open and usable.
+        let file = freopen_from_fs(c"/__kompo__/lib/hello.rb/x".as_ptr(), c"r".as_ptr(), stream);
+        assert!(file.is_null());
+        assert_eq!(errno::errno().0, libc::ENOTDIR);
+        assert_eq!(
+            unsafe { libc::fputc(b'x' as libc::c_int, stream) },
+            b'x' as libc::c_int
+        );
+        assert_eq!(
+            freopen_from_fs(c"/__kompo__/lib".as_ptr(), c"r".as_ptr(), stream),
+            std::ptr::null_mut()
+        );
+        assert_eq!(errno::errno().0, libc::EISDIR);
+
+        // the stream itself is reopened onto the embedded file.
+        let file = freopen_from_fs(c"/__kompo__/lib/hello.rb".as_ptr(), c"r".as_ptr(), stream);
+        assert_eq!(file, stream);
+        let mut buf = [0u8; 64];
+        let read =
+            unsafe { libc::fread(buf.as_mut_ptr() as *mut libc::c_void, 1, buf.len(), stream) };
+        assert_eq!(&buf[..read], b"puts 'hello'\n");
+
+        assert_eq!(unsafe { libc::fclose(stream) }, 0);
+    }
+
+    // runs `child` in a fork()ed process, returning its exit status.
+    #[cfg(target_os = "linux")]
+    fn exit_status(child: impl FnOnce()) -> libc::c_int {
+        let pid = vfork_from_fs();
+        assert_ne!(pid, -1);
+        if pid == 0 {
+            child();
+            unsafe { libc::_exit(127) };
+        }
+
+        let mut status = 0;
+        assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+        assert!(libc::WIFEXITED(status));
+        libc::WEXITSTATUS(status)
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_exec_list() {
+        extern "C" {
+            fn execl(path: *const libc::c_char, arg: *const libc::c_char, ...) -> libc::c_int;
+            fn execle(path: *const libc::c_char, arg: *const libc::c_char, ...) -> libc::c_int;
+        }
+
+        let status = exit_status(|| unsafe {
+            execl(
+                c"/bin/sh".as_ptr(),
+                c"sh".as_ptr(),
+                c"-c".as_ptr(),
+                c"exit 3".as_ptr(),
+                std::ptr::null::<libc::c_char>(),
+            );
+        });
+        assert_eq!(status, 3);
+
+        let envp = [c"STATUS=5".as_ptr(), std::ptr::null()];
+        let status = exit_status(|| unsafe {
+            execle(
+                c"/bin/sh".as_ptr(),
+                c"sh".as_ptr(),
+                c"-c".as_ptr(),
+                c"exit $STATUS".as_ptr(),
+                std::ptr::null::<libc::c_char>(),
+                envp.as_ptr(),
+            );
+        });
+        assert_eq!(status, 5);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_renameat2_flags() {
+        let hello = c"/__kompo__/lib/hello.rb";
+
+        assert_eq!(
+            errno_of(renameat2_from_fs(
+                libc::AT_FDCWD,
+                hello.as_ptr(),
+                libc::AT_FDCWD,
+                c"/__kompo__/lib/dir/a.rb".as_ptr(),
+                libc::RENAME_NOREPLACE
+            )),
+            Some(libc::EEXIST)
+        );
+        assert_eq!(
+            errno_of(renameat2_from_fs(
+                libc::AT_FDCWD,
+                hello.as_ptr(),
+                libc::AT_FDCWD,
+                c"/__kompo__/bin/tool".as_ptr(),
+                libc::RENAME_EXCHANGE
+            )),
+            Some(libc::EINVAL)
+        );
+    }
+
+    #[test]
+    fn test_opendir_errors() {
+        let hello = c"/__kompo__/lib/hello.rb";
+        // errno is cleared first, so only the hook can have set it.
+        let errno_of_dir = |dir: *mut libc::DIR| {
+            assert!(dir.is_null());
+            errno::errno().0
+        };
+
+        errno::set_errno(errno::Errno(0));
+        assert_eq!(errno_of_dir(opendir_from_fs(hello.as_ptr())), libc::ENOTDIR);
+        errno::set_errno(errno::Errno(0));
+        assert_eq!(
+            errno_of_dir(opendir_from_fs(c"/__kompo__/lib/missing".as_ptr())),
+            libc::ENOENT
+        );
+        errno::set_errno(errno::Errno(0));
+        assert_eq!(
+            errno_of_dir(opendir_from_fs(c"/__kompo__/lib/hello.rb/dir".as_ptr())),
+            libc::ENOTDIR
+        );
+
+        let fd = open_from_fs(hello.as_ptr(), libc::O_RDONLY, 0);
+        assert_ne!(fd, -1);
+        errno::set_errno(errno::Errno(0));
+        assert_eq!(errno_of_dir(fdopendir_from_fs(fd)), libc::ENOTDIR);
+        assert_eq!(close_from_fs(fd), 0);
+
+        let dir = opendir_from_fs(c"/__kompo__/lib".as_ptr());
+        assert!(!dir.is_null());
+        assert_eq!(closedir_from_fs(dir), 0);
+    }
 }
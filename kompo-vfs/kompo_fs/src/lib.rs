@@ -1,28 +1,37 @@
+#[cfg(target_os = "linux")]
+mod elf;
+#[cfg(target_os = "linux")]
+mod exec;
+mod extract;
+mod fallthrough;
+mod fork;
 mod glue;
+#[cfg(target_os = "linux")]
+mod memfd;
+mod mount;
+mod overlay;
+#[cfg(test)]
+mod test_archive;
 pub mod util;
-use std::collections::HashMap;
-use std::ffi::CStr;
 use std::ffi::CString;
+use std::ffi::{CStr, OsStr};
 use std::ops::Range;
+use std::os::unix::ffi::OsStrExt;
 use std::path::Path;
 use trie_rs::map::TrieBuilder;
 
-static TRIE: std::sync::OnceLock<std::sync::Arc<std::sync::Mutex<kompo_storage::Fs>>> =
-    std::sync::OnceLock::new();
+// `Fs` does its own locking, so it's shared as it is.
+static TRIE: std::sync::OnceLock<std::sync::Arc<kompo_storage::Fs>> = std::sync::OnceLock::new();
 
-pub static mut WORKING_DIR: std::cell::RefCell<Option<std::borrow::Cow<'static, std::ffi::OsStr>>> =
-    std::cell::RefCell::new(None);
-
-static FD_TABLE: std::sync::LazyLock<std::sync::Arc<std::sync::RwLock<HashMap<i32, Vec<u8>>>>> =
-    std::sync::LazyLock::new(|| std::sync::Arc::new(std::sync::RwLock::new(HashMap::new())));
+pub static WORKING_DIR: kompo_storage::WorkingDir = kompo_storage::WorkingDir::new();
 
 pub static mut THREAD_CONTEXT: std::sync::OnceLock<
     std::sync::Arc<std::sync::RwLock<std::collections::HashMap<libc::pthread_t, bool>>>,
 > = std::sync::OnceLock::new();
 
-static mut FILE_TYPE_CACHE: std::cell::LazyCell<
+static FILE_TYPE_CACHE: std::sync::LazyLock<
     std::sync::RwLock<std::collections::HashMap<Vec<std::ffi::OsString>, libc::stat>>,
-> = std::cell::LazyCell::new(|| std::sync::RwLock::new(std::collections::HashMap::new()));
+> = std::sync::LazyLock::new(Default::default);
 
 type VALUE = u64;
 enum Ruby {
@@ -30,11 +39,13 @@ enum Ruby {
     NIL = 0x04,
     TRUE = 0x14,
 }
+// FILES and PATHS are raw bytes, not text: c_char is signed on some targets, and paths needn't
+// be UTF-8.
 extern "C" {
-    static FILES: libc::c_char;
+    static FILES: u8;
     static FILES_SIZES: libc::c_ulonglong;
     static FILES_SIZE: libc::c_int;
-    static PATHS: libc::c_char;
+    static PATHS: u8;
     static PATHS_SIZE: libc::c_int;
     static WD: libc::c_char;
     static START_FILE_PATH: libc::c_char;
@@ -69,7 +80,7 @@ pub unsafe extern "C-unwind" fn get_start_file() -> *const libc::c_char {
 
     // let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
     // {
-    //     let trie = trie.lock().expect("trie is poisoned");
+    //     let trie = trie.read().expect("trie is poisoned");
 
     //     trie.file_read(&path).expect("Not fund start file")
     // }
@@ -81,31 +92,30 @@ pub unsafe extern "C-unwind" fn get_start_file_name() -> *const libc::c_char {
     std::ffi::CStr::from_ptr(&START_FILE_PATH).as_ptr()
 }
 
-fn initialize_trie() -> std::sync::Arc<std::sync::Mutex<kompo_storage::Fs<'static>>> {
-    std::sync::Arc::new(std::sync::Mutex::new(initialize_fs()))
+fn initialize_trie() -> std::sync::Arc<kompo_storage::Fs<'static>> {
+    std::sync::Arc::new(initialize_fs())
+}
+
+// nothing else fills THREAD_CONTEXT, so the first `Kompo.context` call creates it.
+unsafe fn thread_context(
+) -> std::sync::Arc<std::sync::RwLock<std::collections::HashMap<libc::pthread_t, bool>>> {
+    let thread_context = &*std::ptr::addr_of!(THREAD_CONTEXT);
+    std::sync::Arc::clone(thread_context.get_or_init(Default::default))
 }
 
 unsafe extern "C" fn context_func(_: VALUE, _: VALUE) -> VALUE {
     rb_need_block();
 
-    let binding = std::sync::Arc::clone(
-        THREAD_CONTEXT
-            .get()
-            .expect("not initialized THREAD_CONTEXT"),
-    );
     {
-        let mut binding = binding.write().expect("THREAD_CONTEXT is posioned");
+        let binding = thread_context();
+        let mut binding = binding.write().unwrap_or_else(|err| err.into_inner());
         binding.insert(libc::pthread_self(), true);
     }
 
     unsafe extern "C" fn close(_: VALUE) -> VALUE {
-        let binding = std::sync::Arc::clone(
-            THREAD_CONTEXT
-                .get()
-                .expect("not initialized THREAD_CONTEXT"),
-        );
         {
-            let mut binding = binding.write().expect("THREAD_CONTEXT is posioned");
+            let binding = thread_context();
+            let mut binding = binding.write().unwrap_or_else(|err| err.into_inner());
             binding.insert(libc::pthread_self(), false);
         }
 
@@ -115,23 +125,14 @@ unsafe extern "C" fn context_func(_: VALUE, _: VALUE) -> VALUE {
     return rb_ensure(rb_yield, Ruby::NIL as VALUE, close, Ruby::NIL as VALUE);
 }
 
+// a thread that never entered `Kompo.context` isn't in one.
 unsafe extern "C" fn is_context_func(_: VALUE, _: VALUE) -> VALUE {
-    let binding = std::sync::Arc::clone(
-        THREAD_CONTEXT
-            .get()
-            .expect("not initialized THREAD_CONTEXT"),
-    );
-    {
-        let binding = binding.read().expect("THREAD_CONTEXT is posioned");
-        if let Some(bool) = binding.get(&libc::pthread_self()) {
-            if *bool {
-                Ruby::TRUE as VALUE
-            } else {
-                Ruby::FALSE as VALUE
-            }
-        } else {
-            unreachable!("not found pthread_t")
-        }
+    let binding = thread_context();
+    let binding = binding.read().unwrap_or_else(|err| err.into_inner());
+    if binding.get(&libc::pthread_self()) == Some(&true) {
+        Ruby::TRUE as VALUE
+    } else {
+        Ruby::FALSE as VALUE
     }
 }
 
@@ -149,11 +150,9 @@ pub fn initialize_fs() -> kompo_storage::Fs<'static> {
         unsafe { std::slice::from_raw_parts(&FILES_SIZES, splited_path_array.len() + 1) };
 
     for (i, path_byte) in splited_path_array.into_iter().enumerate() {
-        let path = Path::new(unsafe {
-            CStr::from_bytes_with_nul_unchecked(path_byte)
-                .to_str()
-                .unwrap()
-        });
+        let path = Path::new(OsStr::from_bytes(
+            unsafe { CStr::from_bytes_with_nul_unchecked(path_byte) }.to_bytes(),
+        ));
         let path = path.iter().collect::<Vec<_>>();
 
         let range: Range<usize> = files_sizes[i] as usize..files_sizes[i + 1] as usize;
@@ -162,7 +161,16 @@ pub fn initialize_fs() -> kompo_storage::Fs<'static> {
         builder.push(path, file);
     }
 
-    kompo_storage::Fs::new(builder)
+    let mut fs = kompo_storage::Fs::new(builder);
+    mount::apply(&mut fs);
+    // KOMPO_TMPFS=1 makes the embedded tree writable, in memory, for the life of the process.
+    // KOMPO_OVERLAY takes precedence, and only uses the layer as an index of its directory.
+    if overlay::is_enabled() || std::env::var_os("KOMPO_TMPFS").is_some_and(|value| value == "1") {
+        fs.enable_upper();
+        kompo_wrap::WRITE_HOOKED.store(true, std::sync::atomic::Ordering::Release);
+    }
+
+    fs
 }
 
 #[no_mangle]
@@ -173,4 +181,57 @@ pub unsafe extern "C-unwind" fn Init_kompo_fs() {
     let class = rb_define_class(c_name.as_ptr(), rb_cObject);
     rb_define_singleton_method(class, context.as_ptr(), context_func, 0);
     rb_define_singleton_method(class, is_context.as_ptr(), is_context_func, 0);
+
+    fork::register();
+
+    overlay::load();
+
+    #[cfg(target_os = "linux")]
+    inherit_working_dir();
+}
+
+// only the forking thread survives in the child, and the lock may have been held by one that
+// didn't. start over with a fresh map that keeps just this thread's entry.
+unsafe extern "C" fn reset_thread_context_in_child() {
+    let thread_context = &mut *std::ptr::addr_of_mut!(THREAD_CONTEXT);
+    let Some(context) = thread_context.take() else {
+        return;
+    };
+
+    let thread = libc::pthread_self();
+    let mut map = std::collections::HashMap::new();
+    if let Some(in_context) = context
+        .try_read()
+        .ok()
+        .and_then(|context| context.get(&thread).copied())
+    {
+        map.insert(thread, in_context);
+    }
+
+    let _ = thread_context.set(std::sync::Arc::new(std::sync::RwLock::new(map)));
+}
+
+// a kompo process that re-executed itself for `ruby` or an embedded script passes its virtual
+// working directory down, so relative paths resolve the same in the child.
+#[cfg(target_os = "linux")]
+fn inherit_working_dir() {
+    use std::os::unix::ffi::OsStrExt;
+
+    let name = std::ffi::OsStr::from_bytes(exec::WORKING_DIR_ENV);
+    let Some(working_dir) = std::env::var_os(name) else {
+        return;
+    };
+    std::env::remove_var(name);
+
+    let path = Path::new(&working_dir);
+    let search_path = path.iter().collect::<Vec<_>>();
+    let is_dir = {
+        let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
+
+        trie.is_dir_exists_from_path(&search_path)
+    };
+
+    if is_dir {
+        WORKING_DIR.enter(path.to_path_buf());
+    }
 }
@@ -0,0 +1,110 @@
+use std::{
+    ffi::{CString, OsStr},
+    os::unix::ffi::OsStrExt,
+    path::PathBuf,
+};
+
+use crate::{fork::ForkLock, initialize_trie, util::GlobList, TRIE};
+
+// KOMPO_MEMFD is a `:`-separated list of fnmatch(3) globs, e.g. `*.so:*/certs/*.pem`.
+// they are matched against the absolute path of the embedded file, and `*` also matches `/`.
+static MEMFD_PATTERNS: std::sync::LazyLock<GlobList> =
+    std::sync::LazyLock::new(|| GlobList::from_env("KOMPO_MEMFD", 0));
+
+pub fn is_memfd_path(path: &Vec<&OsStr>) -> bool {
+    MEMFD_PATTERNS.matches(&path.iter().collect::<PathBuf>())
+}
+
+// a sealed memfd holding a copy of the embedded file. unlike a kompo fd it is a real file to
+// the kernel, so it works with raw syscalls, sendfile, fd passing and the like; it costs a copy
+// of the file in memory and is never tracked by kompo_storage.
+pub fn open_memfd(path: &Vec<&OsStr>, oflag: libc::c_int) -> Option<libc::c_int> {
+    let contents = {
+        let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
+
+        trie.file_contents(path)?
+    };
+
+    let name = path
+        .last()
+        .map(|name| name.as_bytes())
+        .unwrap_or(b"kompo_fs");
+    let name = CString::new(name).unwrap_or_else(|_| CString::from(c"kompo_fs"));
+    let mut flags = libc::MFD_ALLOW_SEALING;
+    if oflag & libc::O_CLOEXEC == libc::O_CLOEXEC {
+        flags |= libc::MFD_CLOEXEC;
+    }
+
+    let fd = unsafe { libc::memfd_create(name.as_ptr(), flags) };
+    if fd == -1 {
+        return None;
+    }
+
+    if write_all(fd, &contents) && seal(fd) {
+        Some(fd)
+    } else {
+        let err = errno::errno();
+        unsafe { kompo_wrap::CLOSE_HANDLE(fd) };
+        errno::set_errno(err);
+
+        None
+    }
+}
+
+fn write_all(fd: libc::c_int, mut contents: &[u8]) -> bool {
+    while !contents.is_empty() {
+        let ret =
+            unsafe { libc::write(fd, contents.as_ptr() as *const libc::c_void, contents.len()) };
+        if ret == -1 {
+            if errno::errno().0 == libc::EINTR {
+                continue;
+            }
+            return false;
+        }
+
+        contents = &contents[ret as usize..];
+    }
+
+    unsafe { kompo_wrap::LSEEK_HANDLE(fd, 0, libc::SEEK_SET) != -1 }
+}
+
+fn seal(fd: libc::c_int) -> bool {
+    let seals = libc::F_SEAL_SEAL | libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE;
+
+    unsafe { kompo_wrap::FCNTL_HANDLE(fd, libc::F_ADD_SEALS, seals as libc::c_long) != -1 }
+}
+
+// memfds backing dlopen()ed shared objects. they stay open for the life of the process, so
+// dlopen()ing the same embedded path again reaches the same file and the loader just bumps
+// its reference count instead of mapping a second copy.
+static SHARED_OBJECT_FDS: std::sync::LazyLock<
+    std::sync::Mutex<std::collections::HashMap<PathBuf, libc::c_int>>,
+> = std::sync::LazyLock::new(|| std::sync::Mutex::new(std::collections::HashMap::new()));
+
+pub struct SharedObjectFds;
+
+impl ForkLock for SharedObjectFds {
+    fn lock() -> Box<dyn std::any::Any> {
+        Box::new(
+            SHARED_OBJECT_FDS
+                .lock()
+                .unwrap_or_else(|err| err.into_inner()),
+        )
+    }
+}
+
+pub fn shared_object_fd(path: &Vec<&OsStr>) -> Option<libc::c_int> {
+    let key = path.iter().collect::<PathBuf>();
+    let mut fds = SHARED_OBJECT_FDS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+
+    if let Some(fd) = fds.get(&key) {
+        return Some(*fd);
+    }
+
+    let fd = open_memfd(path, libc::O_CLOEXEC)?;
+    fds.insert(key, fd);
+
+    Some(fd)
+}
@@ -0,0 +1,56 @@
+use std::{
+    ffi::{CStr, OsStr},
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use kompo_storage::MountTable;
+
+use crate::WD;
+
+// the working dir is always mounted onto itself, at its build-time path. KOMPO_MOUNTS adds
+// more as a `:`-separated list of `prefix=subtree` pairs, e.g.
+// `/app=src:/gems=bundle/ruby/3.4.0/gems`. a relative subtree is taken from the working dir.
+pub static MOUNTS: std::sync::LazyLock<MountTable> = std::sync::LazyLock::new(|| {
+    let wd = Path::new(OsStr::from_bytes(unsafe { CStr::from_ptr(&WD) }.to_bytes()));
+    let wd = kompo_storage::normalize(Path::new("/"), wd);
+    let mut mounts = MountTable::new();
+    mounts.add(&wd, &wd);
+
+    if let Some(table) = std::env::var_os("KOMPO_MOUNTS") {
+        for entry in table.as_bytes().split(|b| *b == b':') {
+            let mut pair = entry.splitn(2, |b| *b == b'=');
+            let (Some(prefix), Some(target)) = (pair.next(), pair.next()) else {
+                continue;
+            };
+            let prefix = Path::new(OsStr::from_bytes(prefix));
+            if !prefix.is_absolute() {
+                continue;
+            }
+            let prefix = kompo_storage::normalize(Path::new("/"), prefix);
+            if prefix.parent().is_none() {
+                continue;
+            }
+
+            mounts.add(&prefix, &wd.join(OsStr::from_bytes(target)));
+        }
+    }
+
+    mounts
+});
+
+// `path` has to be normalized already; prefixes are matched component by component.
+pub fn is_mounted(path: &Path) -> bool {
+    MOUNTS.contains(path)
+}
+
+// where the VFS path `path` lives in the archive, if it's under a mount.
+pub fn archive_path(path: &Path) -> Option<PathBuf> {
+    let search_path = path.iter().collect::<Vec<_>>();
+
+    Some(MOUNTS.archive_path(&search_path)?.into_iter().collect())
+}
+
+pub fn apply(fs: &mut kompo_storage::Fs) {
+    fs.set_mounts(MOUNTS.clone());
+}
@@ -0,0 +1,512 @@
+use std::{
+    borrow::Cow,
+    ffi::{CString, OsStr, OsString},
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use kompo_storage::LayerEntry;
+
+use crate::{extract, initialize_trie, mount, util, FILE_TYPE_CACHE, TRIE};
+
+// overlayfs-style markers, as plain files since we can't make whiteout devices:
+// `.wh.<name>` hides the embedded `<name>`, and a directory holding `.wh..wh..opq` hides
+// everything embedded below it.
+const WHITEOUT_PREFIX: &[u8] = b".wh.";
+const OPAQUE_MARKER: &str = ".wh..wh..opq";
+
+// KOMPO_OVERLAY=1 keeps changes to the embedded tree in `$XDG_DATA_HOME/kompo/<archive-hash>`,
+// falling back to `~/.local/share`. any other value is the overlay directory itself.
+static OVERLAY_DIR: std::sync::LazyLock<Option<PathBuf>> = std::sync::LazyLock::new(|| {
+    let value = std::env::var_os("KOMPO_OVERLAY").filter(|value| !value.is_empty())?;
+    if value != "1" {
+        return Some(PathBuf::from(value));
+    }
+
+    let mut dir = std::env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share"))
+        })?;
+
+    dir.push("kompo");
+    dir.push(format!("{:016x}", extract::archive_hash()));
+
+    Some(dir)
+});
+
+pub fn is_enabled() -> bool {
+    OVERLAY_DIR.is_some()
+}
+
+// indexes what earlier runs left in the overlay directory. called once from Init_kompo_fs.
+pub fn load() {
+    // initialize_trie() enables the writable layer whenever the overlay is, so this can't fail.
+    if is_enabled() {
+        let _ = refresh(Path::new("/"));
+    }
+}
+
+// the overlay directory mirrors the archive, not the mounts, so a file reachable through
+// several mounts has a single copy on the host. paths outside every mount are taken as they are.
+fn archive_path(path: &Path) -> PathBuf {
+    mount::archive_path(path).unwrap_or_else(|| path.to_path_buf())
+}
+
+// where the archive path `archive_path` lives in the overlay directory.
+fn overlay_path(archive_path: &Path) -> PathBuf {
+    let mut host = OVERLAY_DIR.clone().expect("overlay is disabled");
+    host.extend(archive_path.iter().skip(1));
+
+    host
+}
+
+// where the VFS path `path` lives in the overlay directory.
+fn host_path(path: &Path) -> PathBuf {
+    overlay_path(&archive_path(path))
+}
+
+fn host_cstring(path: &Path) -> CString {
+    CString::new(host_path(path).as_os_str().as_bytes()).expect("invalid path")
+}
+
+fn whiteout_path(path: &Path) -> Option<PathBuf> {
+    archive_whiteout_path(&archive_path(path))
+}
+
+fn archive_whiteout_path(archive_path: &Path) -> Option<PathBuf> {
+    let mut marker = OsString::from(OsStr::from_bytes(WHITEOUT_PREFIX));
+    marker.push(archive_path.file_name()?);
+
+    Some(overlay_path(archive_path.parent()?).join(marker))
+}
+
+// the file on the host backing `path`, if it has been copied up or created in the overlay.
+pub fn host_file(path: &Path) -> Option<CString> {
+    if !is_enabled() || !is_host_file(path) {
+        return None;
+    }
+
+    Some(host_cstring(path))
+}
+
+// the bytes of the file at `path`, wherever it lives: in the archive, in the in-memory layer,
+// or in the overlay directory once it has been copied up. for dlopen() and exec(), which look
+// inside the file before handing it on.
+pub fn file_contents(path: &Path) -> Option<Cow<'static, [u8]>> {
+    let search_path = path.iter().collect::<Vec<_>>();
+    let contents = {
+        let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
+
+        trie.file_contents(&search_path)
+    };
+    if contents.is_some() {
+        return contents;
+    }
+
+    let host = host_file(path)?;
+    std::fs::read(OsStr::from_bytes(host.to_bytes()))
+        .ok()
+        .map(Cow::Owned)
+}
+
+fn is_host_file(path: &Path) -> bool {
+    let search_path = path.iter().collect::<Vec<_>>();
+    let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
+
+    trie.is_host_file(&search_path)
+}
+
+fn in_archive(path: &Path) -> Option<bool> {
+    let search_path = path.iter().collect::<Vec<_>>();
+    let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
+
+    trie.in_archive(&search_path)
+}
+
+fn is_empty_dir(path: &Path) -> bool {
+    let search_path = path.iter().collect::<Vec<_>>();
+    let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
+
+    trie.dir_entries(&search_path)
+        .is_some_and(|entries| entries.is_empty())
+}
+
+// re-reads the overlay directory at and below `path` into kompo_storage, which merges it
+// with the archive for every lookup and listing. both index it by archive path. fails with
+// EROFS if kompo_storage has no writable layer to hold the index.
+fn refresh(path: &Path) -> Result<(), i32> {
+    let path = archive_path(path);
+    let mut entries = Vec::new();
+    if archive_whiteout_path(&path).is_some_and(|whiteout| whiteout.exists()) {
+        entries.push((path.clone(), LayerEntry::Whiteout));
+    }
+    scan(&path, &mut entries);
+
+    {
+        let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
+        let entries = entries
+            .iter()
+            .map(|(entry_path, entry)| (entry_path.iter().collect(), *entry))
+            .collect::<Vec<_>>();
+
+        trie.record_tree(&path.iter().collect(), &entries)?;
+    }
+
+    // the cache is keyed by VFS path, so entries are matched by where they live in the
+    // archive: that catches `path` under every mount it's reachable through.
+    FILE_TYPE_CACHE
+        .write()
+        .unwrap_or_else(|err| err.into_inner())
+        .retain(|key, _| !archive_path(&key.iter().collect::<PathBuf>()).starts_with(&path));
+
+    Ok(())
+}
+
+fn scan(path: &Path, entries: &mut Vec<(PathBuf, LayerEntry)>) {
+    let host = overlay_path(path);
+    let Ok(metadata) = std::fs::symlink_metadata(&host) else {
+        return;
+    };
+    if !metadata.is_dir() {
+        entries.push((path.to_path_buf(), LayerEntry::File));
+        return;
+    }
+
+    // the root is always there; only what's below it is recorded.
+    if path.parent().is_some() {
+        let opaque = host.join(OPAQUE_MARKER).exists();
+        entries.push((path.to_path_buf(), LayerEntry::Directory { opaque }));
+    }
+
+    let Ok(dir) = std::fs::read_dir(&host) else {
+        return;
+    };
+    for child in dir.flatten() {
+        let name = child.file_name();
+        if name == OPAQUE_MARKER {
+            continue;
+        }
+
+        match name.as_bytes().strip_prefix(WHITEOUT_PREFIX) {
+            Some(hidden) => {
+                entries.push((path.join(OsStr::from_bytes(hidden)), LayerEntry::Whiteout))
+            }
+            None => scan(&path.join(name), entries),
+        }
+    }
+}
+
+fn io_errno(err: std::io::Error) -> i32 {
+    err.raw_os_error().unwrap_or(libc::EIO)
+}
+
+fn fail(err: i32) -> libc::c_int {
+    errno::set_errno(errno::Errno(err));
+    -1
+}
+
+fn report(ret: Result<(), i32>) -> libc::c_int {
+    match ret {
+        Ok(()) => 0,
+        Err(err) => fail(err),
+    }
+}
+
+// creates `dir` and its parents in the overlay directory, mirroring the embedded ones.
+fn ensure_host_dir(dir: &Path) -> Result<(), i32> {
+    std::fs::create_dir_all(OVERLAY_DIR.as_ref().expect("overlay is disabled"))
+        .map_err(io_errno)?;
+
+    let dir = archive_path(dir);
+    let mut ancestors = dir.ancestors().collect::<Vec<_>>();
+    ancestors.reverse();
+    for ancestor in ancestors.into_iter().skip(1) {
+        let host = overlay_path(ancestor);
+        if host.is_dir() {
+            continue;
+        }
+
+        std::fs::create_dir(&host).map_err(io_errno)?;
+        let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
+        trie.record(
+            &ancestor.iter().collect(),
+            LayerEntry::Directory { opaque: false },
+        )?;
+    }
+
+    Ok(())
+}
+
+// copies an embedded file into the overlay directory before it's first modified.
+fn copy_up(path: &Path) -> Result<(), i32> {
+    let search_path = path.iter().collect::<Vec<_>>();
+    let contents = {
+        let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
+
+        trie.file_contents(&search_path)
+            .map(|contents| contents.into_owned())
+    }
+    .ok_or(libc::ENOENT)?;
+
+    ensure_host_dir(path.parent().ok_or(libc::EISDIR)?)?;
+    extract::extract(&host_path(path), &contents).map_err(io_errno)?;
+
+    refresh(path)
+}
+
+// copies everything visible at and below `path` into the overlay directory.
+fn copy_up_tree(path: &Path) -> Result<(), i32> {
+    match util::kompo_path_kind(path) {
+        Some(true) => {
+            ensure_host_dir(path)?;
+
+            let children = {
+                let search_path = path.iter().collect::<Vec<_>>();
+                let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
+
+                trie.dir_entries(&search_path).unwrap_or_default()
+            };
+            for child in children {
+                copy_up_tree(&child.iter().collect::<PathBuf>())?;
+            }
+
+            Ok(())
+        }
+        Some(false) if !is_host_file(path) => copy_up(path),
+        Some(false) => Ok(()),
+        None => Err(libc::ENOENT),
+    }
+}
+
+// makes room for a new entry at `path`: its directory must exist, and gets mirrored in the
+// overlay directory, and an earlier deletion of the embedded entry is forgotten.
+fn prepare_create(path: &Path) -> Result<(), i32> {
+    let dir = path.parent().ok_or(libc::EEXIST)?;
+    match util::kompo_path_kind(dir) {
+        Some(true) => {}
+        Some(false) => return Err(libc::ENOTDIR),
+        None => return Err(libc::ENOENT),
+    }
+
+    ensure_host_dir(dir)?;
+    if let Some(whiteout) = whiteout_path(path) {
+        match std::fs::remove_file(whiteout) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(io_errno(err)),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+// records that the embedded entry at `path` is gone, if there is one.
+fn whiteout(path: &Path) -> Result<(), i32> {
+    if in_archive(path).is_none() {
+        return Ok(());
+    }
+
+    ensure_host_dir(path.parent().ok_or(libc::EBUSY)?)?;
+    let whiteout = whiteout_path(path).ok_or(libc::EBUSY)?;
+    std::fs::File::create(whiteout).map_err(io_errno)?;
+
+    Ok(())
+}
+
+// opens `path` from the overlay directory if it lives there, or has to from now on because
+// it's opened for writing. None leaves it to the archive.
+pub fn open(path: &Path, oflag: libc::c_int, mode: libc::mode_t) -> Option<libc::c_int> {
+    if !is_enabled() {
+        return None;
+    }
+
+    let mut created = false;
+    if !is_host_file(path) {
+        if !util::is_write_open(path, oflag) {
+            return None;
+        }
+
+        let ret = match util::kompo_path_kind(path) {
+            #[cfg(target_os = "linux")]
+            Some(true) if oflag & libc::O_TMPFILE == libc::O_TMPFILE => ensure_host_dir(path),
+            Some(true) => Err(libc::EISDIR),
+            Some(false)
+                if oflag & (libc::O_CREAT | libc::O_EXCL) == libc::O_CREAT | libc::O_EXCL =>
+            {
+                Err(libc::EEXIST)
+            }
+            Some(false) => copy_up(path),
+            None if oflag & libc::O_CREAT == 0 => Err(libc::ENOENT),
+            None => {
+                created = true;
+                prepare_create(path)
+            }
+        };
+        if let Err(err) = ret {
+            return Some(fail(err));
+        }
+    }
+
+    let host = host_cstring(path);
+    let fd = unsafe { kompo_wrap::OPEN_HANDLE(host.as_ptr(), oflag, mode) };
+    if fd != -1 && created {
+        if let Err(err) = refresh(path) {
+            unsafe { libc::close(fd) };
+            return Some(fail(err));
+        }
+    }
+
+    Some(fd)
+}
+
+pub fn mkdir(path: &Path, mode: libc::mode_t) -> libc::c_int {
+    if util::kompo_path_kind(path).is_some() {
+        return fail(libc::EEXIST);
+    }
+    if let Err(err) = prepare_create(path) {
+        return fail(err);
+    }
+
+    let host = host_cstring(path);
+    if unsafe { kompo_wrap::MKDIR_HANDLE(host.as_ptr(), mode) } == -1 {
+        return -1;
+    }
+    // a directory that replaces a deleted embedded one starts out empty.
+    if in_archive(path) == Some(true) {
+        if let Err(err) = std::fs::File::create(host_path(path).join(OPAQUE_MARKER)) {
+            return fail(io_errno(err));
+        }
+    }
+    report(refresh(path))
+}
+
+pub fn unlink(path: &Path) -> libc::c_int {
+    let ret = (|| {
+        match util::kompo_path_kind(path) {
+            Some(true) => return Err(libc::EISDIR),
+            Some(false) => {}
+            None => return Err(libc::ENOENT),
+        }
+
+        if is_host_file(path) {
+            std::fs::remove_file(host_path(path)).map_err(io_errno)?;
+        }
+        whiteout(path)
+    })();
+    let refreshed = refresh(path);
+
+    report(ret.and(refreshed))
+}
+
+pub fn rmdir(path: &Path) -> libc::c_int {
+    let ret = (|| {
+        match util::kompo_path_kind(path) {
+            Some(true) => {}
+            Some(false) => return Err(libc::ENOTDIR),
+            None => return Err(libc::ENOENT),
+        }
+        if path.parent().is_none() {
+            return Err(libc::EBUSY);
+        }
+        if !is_empty_dir(path) {
+            return Err(libc::ENOTEMPTY);
+        }
+
+        // all that can be left on the host is whiteouts of embedded entries.
+        let host = host_path(path);
+        if host.is_dir() {
+            std::fs::remove_dir_all(host).map_err(io_errno)?;
+        }
+        whiteout(path)
+    })();
+    let refreshed = refresh(path);
+
+    report(ret.and(refreshed))
+}
+
+pub fn rename(old: &Path, new: &Path) -> libc::c_int {
+    let ret = (|| {
+        let old_kind = util::kompo_path_kind(old).ok_or(libc::ENOENT)?;
+        if new.starts_with(old) && new != old {
+            return Err(libc::EINVAL);
+        }
+        match util::kompo_path_kind(new.parent().ok_or(libc::EBUSY)?) {
+            Some(true) => {}
+            Some(false) => return Err(libc::ENOTDIR),
+            None => return Err(libc::ENOENT),
+        }
+        match (old_kind, util::kompo_path_kind(new)) {
+            (true, Some(false)) => return Err(libc::ENOTDIR),
+            (false, Some(true)) => return Err(libc::EISDIR),
+            (true, Some(true)) if !is_empty_dir(new) => return Err(libc::ENOTEMPTY),
+            _ => {}
+        }
+        if old == new {
+            return Ok(());
+        }
+
+        copy_up_tree(old)?;
+        prepare_create(new)?;
+        let new_host = host_path(new);
+        if new_host.is_dir() {
+            std::fs::remove_dir_all(&new_host).map_err(io_errno)?;
+        }
+        std::fs::rename(host_path(old), &new_host).map_err(io_errno)?;
+        if old_kind && in_archive(new).is_some() {
+            std::fs::File::create(new_host.join(OPAQUE_MARKER)).map_err(io_errno)?;
+        }
+
+        whiteout(old)
+    })();
+    let refreshed = refresh(old).and(refresh(new));
+
+    report(ret.and(refreshed))
+}
+
+// copies `path` up so a change to it sticks. directories are only mirrored; they keep
+// reporting their embedded metadata.
+fn materialize(path: &Path) -> Result<CString, i32> {
+    match util::kompo_path_kind(path) {
+        Some(true) => ensure_host_dir(path)?,
+        Some(false) if !is_host_file(path) => copy_up(path)?,
+        Some(false) => {}
+        None => return Err(libc::ENOENT),
+    }
+
+    Ok(host_cstring(path))
+}
+
+pub fn truncate(path: &Path, length: libc::off_t) -> libc::c_int {
+    if util::kompo_path_kind(path) == Some(true) {
+        return fail(libc::EISDIR);
+    }
+
+    match materialize(path) {
+        Ok(host) => unsafe { kompo_wrap::TRUNCATE_HANDLE(host.as_ptr(), length) },
+        Err(err) => fail(err),
+    }
+}
+
+pub fn chmod(path: &Path, mode: libc::mode_t) -> libc::c_int {
+    match materialize(path) {
+        Ok(host) => unsafe { kompo_wrap::CHMOD_HANDLE(host.as_ptr(), mode) },
+        Err(err) => fail(err),
+    }
+}
+
+pub fn chown(path: &Path, owner: libc::uid_t, group: libc::gid_t) -> libc::c_int {
+    match materialize(path) {
+        Ok(host) => unsafe { kompo_wrap::CHOWN_HANDLE(host.as_ptr(), owner, group) },
+        Err(err) => fail(err),
+    }
+}
+
+pub fn utimensat(path: &Path, times: *const libc::timespec, flags: libc::c_int) -> libc::c_int {
+    match materialize(path) {
+        Ok(host) => unsafe {
+            kompo_wrap::UTIMENSAT_HANDLE(libc::AT_FDCWD, host.as_ptr(), times, flags)
+        },
+        Err(err) => fail(err),
+    }
+}
@@ -0,0 +1,29 @@
+// the archive a unit test build links against, in place of the fs.c kompo generates. it's
+// served from the same virtual root a real build uses.
+
+const fn bytes<const N: usize>(source: &[u8]) -> [u8; N] {
+    let mut bytes = [0; N];
+    let mut i = 0;
+    while i < N {
+        bytes[i] = source[i];
+        i += 1;
+    }
+
+    bytes
+}
+
+const PATH_LIST: &[u8] = b"/__kompo__/lib/hello.rb\0/__kompo__/lib/dir/a.rb\0/__kompo__/bin/tool\0";
+const FILE_LIST: &[u8] = b"puts 'hello'\naa#!/usr/bin/env ruby\n";
+
+#[no_mangle]
+static PATHS: [u8; PATH_LIST.len()] = bytes(PATH_LIST);
+#[no_mangle]
+static PATHS_SIZE: libc::c_int = PATH_LIST.len() as libc::c_int;
+#[no_mangle]
+static FILES: [u8; FILE_LIST.len()] = bytes(FILE_LIST);
+#[no_mangle]
+static FILES_SIZE: libc::c_int = FILE_LIST.len() as libc::c_int;
+#[no_mangle]
+static FILES_SIZES: [libc::c_ulonglong; 4] = [0, 13, 15, FILE_LIST.len() as libc::c_ulonglong];
+#[no_mangle]
+static WD: [u8; 11] = *b"/__kompo__\0";
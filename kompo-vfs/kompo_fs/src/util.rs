@@ -1,110 +1,249 @@
 use std::{
-    env,
-    ffi::{CStr, CString},
-    hash::{DefaultHasher, Hash, Hasher},
+    borrow::Cow,
+    ffi::{CStr, CString, OsStr},
     os::unix::ffi::OsStrExt,
     path::{Path, PathBuf},
-    str::FromStr,
 };
 
-use crate::{TRIE, WD, WORKING_DIR};
+use crate::{initialize_trie, mount, TRIE, WORKING_DIR};
 
-pub fn is_under_kompo_working_dir(other_path: *const libc::c_char) -> bool {
-    let wd = unsafe { CStr::from_ptr(&WD) };
-    let other_path = unsafe { CStr::from_ptr(other_path) };
-
-    other_path.to_bytes().starts_with(wd.to_bytes())
+// where a path passed to a hooked function is served from.
+pub enum Route<'a> {
+    // the resolved absolute path in the VFS.
+    Vfs(CString),
+    // what to hand the host: the path as given, or resolved against the virtual working dir.
+    Host(Cow<'a, CStr>),
+    // a VFS path the kernel would refuse to resolve, with the errno it would report.
+    Error(libc::c_int),
 }
 
-pub fn canonicalize_path(base: &mut PathBuf, join_path: &PathBuf) {
-    for comp in join_path.components() {
-        match comp {
-            std::path::Component::Normal(comp) => {
-                base.push(comp);
-            }
-            std::path::Component::ParentDir => {
-                base.pop();
-            }
-            std::path::Component::RootDir => {
-                // do nothing
-            }
-            std::path::Component::Prefix(_) => todo!(),
-            std::path::Component::CurDir => {
-                // do nothing
+// relative paths are resolved against the virtual working dir, if there is one, and every
+// path is normalized before it's matched against the mounts. paths in the VFS are then
+// walked component by component, like the kernel does.
+pub fn route<'a>(path: *const libc::c_char) -> Route<'a> {
+    if path.is_null() {
+        return Route::Error(libc::EFAULT);
+    }
+    let raw = unsafe { CStr::from_ptr(path) };
+    let is_relative = raw.to_bytes().first().is_some_and(|b| *b != b'/');
+
+    let base = match WORKING_DIR.get() {
+        Some(working_dir) if is_relative => working_dir,
+        // the host's own working dir, or an empty path the host rejects.
+        _ if is_relative || raw.is_empty() => return Route::Host(Cow::Borrowed(raw)),
+        _ => PathBuf::from("/"),
+    };
+
+    let normalized = kompo_storage::normalize(&base, Path::new(OsStr::from_bytes(raw.to_bytes())));
+    if mount::is_mounted(&normalized) {
+        return match resolve(&base, raw) {
+            Ok(resolved) => {
+                Route::Vfs(CString::new(resolved.as_os_str().as_bytes()).expect("invalid path"))
             }
-        }
+            Err(err) => Route::Error(err),
+        };
+    }
+
+    if is_relative {
+        Route::Host(Cow::Owned(
+            CString::new(normalized.as_os_str().as_bytes()).expect("invalid path"),
+        ))
+    } else {
+        Route::Host(Cow::Borrowed(raw))
     }
 }
 
-pub fn expand_kompo_path(raw_path: *const libc::c_char) -> *const libc::c_char {
-    let path = unsafe { CStr::from_ptr(raw_path) };
-    let path = PathBuf::from_str(path.to_str().expect("invalid path")).expect("invalid path");
+// the VFS path `path` names relative to the directory `base`, resolved by kompo_storage.
+pub fn resolve(base: &Path, path: &CStr) -> Result<PathBuf, libc::c_int> {
+    let mut search_path = if path.to_bytes().starts_with(b"/") {
+        vec![]
+    } else {
+        base.iter().collect::<Vec<_>>()
+    };
+    search_path.extend(kompo_storage::split(OsStr::from_bytes(path.to_bytes())));
 
-    if path.is_absolute() {
-        let path = CString::new(path.to_str().expect("invalid path"))
-            .expect("invalid path")
-            .into_boxed_c_str();
-        let path = Box::into_raw(path);
+    let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
+    let resolved = trie.resolve(&search_path)?;
 
-        return path as *const libc::c_char;
-    }
+    Ok(resolved.iter().collect())
+}
+
+// takes no lock, so any hook can ask it about any fd.
+pub fn is_fd_exists_in_kompo(fd: i32) -> bool {
+    let Some(trie) = TRIE.get() else {
+        return false;
+    };
+
+    trie.is_fd_exists(fd)
+}
 
-    let wd = unsafe { WORKING_DIR.clone().take().unwrap().into_owned() };
-    let mut wd = PathBuf::from(wd);
+pub fn is_dir_exists_in_kompo(dir: *mut libc::DIR) -> bool {
+    let Some(trie) = TRIE.get() else {
+        return false;
+    };
 
-    canonicalize_path(&mut wd, &path);
+    let dir = unsafe { Box::from_raw(dir as *mut kompo_storage::FsDir) };
 
-    let wd = CString::new(wd.to_str().expect("invalid path"))
-        .expect("invalid path")
-        .into_boxed_c_str();
-    let wd = Box::into_raw(wd);
+    let bool = trie.is_dir_exists(&dir);
 
-    wd as *const libc::c_char
+    let _ = Box::into_raw(dir);
+    bool
 }
 
-pub fn current_dir_hash() -> u64 {
-    let mut hasher = DefaultHasher::new();
-    unsafe { WORKING_DIR.take().unwrap().hash(&mut hasher) };
-    hasher.finish()
+pub fn is_dir_fd_exists_in_kompo(fd: i32) -> bool {
+    let Some(trie) = TRIE.get() else {
+        return false;
+    };
+
+    trie.is_dir_fd(fd)
 }
 
-pub fn is_under_kompo_tmp_dir(other_path: *const libc::c_char) -> bool {
-    let mut tmpdir = env::temp_dir();
-    tmpdir.push(format!("{}", current_dir_hash()));
-    let other_path = unsafe { CStr::from_ptr(other_path) };
+pub fn get_path_from_kompo_fd(fd: i32) -> Option<PathBuf> {
+    let trie = TRIE.get()?;
 
-    other_path
-        .to_bytes()
-        .starts_with(tmpdir.as_os_str().as_bytes())
+    trie.path_from_fd(fd)
+        .map(|path| path.iter().collect::<PathBuf>())
 }
 
-pub fn is_fd_exists_in_kompo(fd: i32) -> bool {
-    if TRIE.get().is_none() {
-        return false;
+// the absolute VFS path that `path` names relative to `dirfd` (AT_FDCWD or a kompo dir fd),
+// or None if it refers to the host.
+pub fn kompo_path_at(
+    dirfd: libc::c_int,
+    path: *const libc::c_char,
+) -> Result<Option<PathBuf>, libc::c_int> {
+    if path.is_null() || unsafe { *path } == 0 {
+        return Ok(None);
+    }
+
+    let raw = unsafe { CStr::from_ptr(path) };
+    if raw.to_bytes().starts_with(b"/") || dirfd == libc::AT_FDCWD {
+        return match route(path) {
+            Route::Vfs(path) => Ok(Some(PathBuf::from(OsStr::from_bytes(path.to_bytes())))),
+            Route::Host(_) => Ok(None),
+            Route::Error(err) => Err(err),
+        };
+    }
+
+    if !is_dir_fd_exists_in_kompo(dirfd) {
+        return Ok(None);
+    }
+    let Some(base) = get_path_from_kompo_fd(dirfd) else {
+        return Ok(None);
+    };
+
+    resolve(&base, raw).map(Some)
+}
+
+// a `:`-separated list of fnmatch(3) globs read from the environment variable `name`, as
+// KOMPO_MEMFD, KOMPO_EXTRACT and KOMPO_HOST_ALLOW take them.
+pub struct GlobList {
+    patterns: Vec<CString>,
+    flags: libc::c_int,
+}
+
+impl GlobList {
+    pub fn from_env(name: &str, flags: libc::c_int) -> Self {
+        let patterns = std::env::var_os(name).unwrap_or_default();
+
+        GlobList::new(patterns.as_bytes(), flags)
+    }
+
+    pub fn new(patterns: &[u8], flags: libc::c_int) -> Self {
+        let patterns = patterns
+            .split(|b| *b == b':')
+            .filter(|pattern| !pattern.is_empty())
+            .filter_map(|pattern| CString::new(pattern).ok())
+            .collect();
+
+        GlobList { patterns, flags }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
     }
 
-    let trie = std::sync::Arc::clone(&TRIE.get().unwrap());
-    {
-        let trie = trie.lock().unwrap();
+    pub fn matches(&self, path: &Path) -> bool {
+        let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+            return false;
+        };
 
-        trie.is_fd_exists(fd)
+        self.patterns.iter().any(|pattern| unsafe {
+            libc::fnmatch(pattern.as_ptr(), path.as_ptr(), self.flags) == 0
+        })
     }
 }
 
-pub fn is_dir_exists_in_kompo(dir: *mut libc::DIR) -> bool {
-    if TRIE.get().is_none() {
-        return false;
+// sets errno for a failed hook, and returns `ret`.
+pub fn fail<T>(err: libc::c_int, ret: T) -> T {
+    errno::set_errno(errno::Errno(err));
+
+    ret
+}
+
+// whether open() with `oflag` may change anything at the VFS path `path`. O_CREAT alone only
+// does when there's no file there yet, so opening an existing one with it is just a read.
+pub fn is_write_open(path: &Path, oflag: libc::c_int) -> bool {
+    #[cfg(target_os = "linux")]
+    if oflag & libc::O_TMPFILE == libc::O_TMPFILE {
+        return true;
     }
 
-    let dir = unsafe { Box::from_raw(dir as *mut kompo_storage::FsDir) };
+    if oflag & libc::O_ACCMODE != libc::O_RDONLY || oflag & libc::O_TRUNC != 0 {
+        return true;
+    }
 
-    let trie = std::sync::Arc::clone(&TRIE.get().unwrap());
-    let bool = {
-        let trie = trie.lock().unwrap();
+    oflag & libc::O_CREAT != 0
+        && (oflag & libc::O_EXCL != 0 || kompo_path_kind(path) != Some(false))
+}
 
-        trie.is_dir_exists(&dir)
-    };
+// Some(true) for an embedded directory, Some(false) for a file, None if it doesn't exist.
+pub fn kompo_path_kind(path: &Path) -> Option<bool> {
+    let search_path = path.iter().collect::<Vec<_>>();
+    let trie = std::sync::Arc::clone(&TRIE.get_or_init(initialize_trie));
 
-    let _ = Box::into_raw(dir);
-    bool
+    trie.is_dir_from_path(&search_path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_route() {
+        let route = |path: &CStr| match route(path.as_ptr()) {
+            Route::Vfs(path) => Ok(Some(path.into_bytes())),
+            Route::Host(_) => Ok(None),
+            Route::Error(err) => Err(err),
+        };
+        let vfs = |path: &str| Ok(Some(path.as_bytes().to_vec()));
+
+        assert_eq!(
+            route(c"/__kompo__/lib/hello.rb"),
+            vfs("/__kompo__/lib/hello.rb")
+        );
+        assert_eq!(
+            route(c"//__kompo__//lib/./hello.rb"),
+            vfs("/__kompo__/lib/hello.rb")
+        );
+        assert_eq!(
+            route(c"/__kompo__/lib/../bin/tool"),
+            vfs("/__kompo__/bin/tool")
+        );
+        assert_eq!(route(c"/etc/../__kompo__/lib"), vfs("/__kompo__/lib"));
+        assert_eq!(
+            route(c"/__kompo__/lib/new.rb"),
+            vfs("/__kompo__/lib/new.rb")
+        );
+        assert_eq!(route(c"/__kompo__x/lib"), Ok(None));
+        assert_eq!(route(c"/__kompo__/../etc/passwd"), Ok(None));
+        assert_eq!(route(c"/__kompo"), Ok(None));
+        assert_eq!(route(c""), Ok(None));
+        assert_eq!(route(c"/__kompo__/lib/hello.rb/x"), Err(libc::ENOTDIR));
+        assert_eq!(route(c"/__kompo__/lib/hello.rb/"), Err(libc::ENOTDIR));
+        assert_eq!(route(c"/__kompo__/missing/x"), Err(libc::ENOENT));
+        assert!(matches!(
+            super::route(std::ptr::null()),
+            Route::Error(libc::EFAULT)
+        ));
+    }
 }
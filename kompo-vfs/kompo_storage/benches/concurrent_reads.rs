@@ -0,0 +1,75 @@
+// reads, seeks and stats from many threads at once, the way a multi-threaded Ruby process
+// loading its embedded files hits the VFS. run with `cargo bench -p kompo_storage`.
+
+use std::ffi::OsStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use kompo_storage::Fs;
+use trie_rs::map::TrieBuilder;
+
+const FILES: usize = 64;
+const RUN_FOR: Duration = Duration::from_secs(1);
+
+fn build_fs() -> (Fs<'static>, Vec<Vec<&'static OsStr>>) {
+    let mut builder: TrieBuilder<&OsStr, &[u8]> = TrieBuilder::new();
+    let data: &'static [u8] = Box::leak(vec![0x2a; 64 * 1024].into_boxed_slice());
+
+    let paths = (0..FILES)
+        .map(|i| {
+            let name: &'static str = Box::leak(format!("file{i}.rb").into_boxed_str());
+            ["app", "lib", name].map(OsStr::new).to_vec()
+        })
+        .collect::<Vec<_>>();
+    for path in &paths {
+        builder.push(path, data);
+    }
+
+    (Fs::new(builder), paths)
+}
+
+fn run(fs: &Fs<'static>, paths: &[Vec<&'static OsStr>], threads: usize) -> u64 {
+    let stop = AtomicBool::new(false);
+    let ops = AtomicU64::new(0);
+
+    std::thread::scope(|scope| {
+        for thread in 0..threads {
+            let (stop, ops) = (&stop, &ops);
+            let path = &paths[thread % paths.len()];
+            scope.spawn(move || {
+                let fd = fs.open(path).unwrap();
+                let mut buf = [0; 4096];
+                let mut stat = unsafe { std::mem::zeroed::<libc::stat>() };
+                let mut count = 0;
+
+                while !stop.load(Ordering::Relaxed) {
+                    if fs.read(fd, &mut buf) == Ok(0) {
+                        let _ = fs.lseek(fd, 0, libc::SEEK_SET);
+                    }
+                    fs.stat(path, &mut stat);
+                    count += 1;
+                }
+
+                ops.fetch_add(count, Ordering::Relaxed);
+                fs.close(fd);
+                unsafe { libc::close(fd) };
+            });
+        }
+
+        std::thread::sleep(RUN_FOR);
+        stop.store(true, Ordering::Relaxed);
+    });
+
+    ops.load(Ordering::Relaxed)
+}
+
+fn main() {
+    let (fs, paths) = build_fs();
+
+    for threads in [1, 2, 4, 8, 16] {
+        let start = Instant::now();
+        let ops = run(&fs, &paths, threads);
+        let ops_per_sec = ops as f64 / start.elapsed().as_secs_f64();
+        println!("{threads:>2} threads: {ops_per_sec:>12.0} ops/s");
+    }
+}
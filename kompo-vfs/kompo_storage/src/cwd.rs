@@ -0,0 +1,91 @@
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::sync::{OnceLock, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+// the process's virtual working directory: Some while it's a directory in the VFS, None while
+// the kernel's own cwd is in effect. changes on either side are made under the same lock, so
+// every thread sees them in a single order, as it would with chdir(2).
+#[derive(Debug, Default)]
+pub struct WorkingDir {
+    dir: RwLock<Option<PathBuf>>,
+}
+
+impl WorkingDir {
+    pub const fn new() -> Self {
+        WorkingDir {
+            dir: RwLock::new(None),
+        }
+    }
+
+    // a panic elsewhere can't leave an Option<PathBuf> half-written, so poisoning is ignored.
+    fn read(&self) -> RwLockReadGuard<'_, Option<PathBuf>> {
+        self.dir.read().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Option<PathBuf>> {
+        self.dir.write().unwrap_or_else(|err| err.into_inner())
+    }
+
+    pub fn get(&self) -> Option<PathBuf> {
+        self.read().clone()
+    }
+
+    pub fn is_virtual(&self) -> bool {
+        self.read().is_some()
+    }
+
+    // runs `f` with the current directory, which stays in effect until `f` returns. this is
+    // how the host gets asked for its own cwd without another thread leaving it meanwhile.
+    pub fn with<T>(&self, f: impl FnOnce(Option<&Path>) -> T) -> T {
+        f(self.read().as_deref())
+    }
+
+    // moves into the VFS directory `dir`, which the caller has checked exists.
+    pub fn enter(&self, dir: PathBuf) {
+        *self.write() = Some(dir);
+    }
+
+    // runs the host's chdir(2) or fchdir(2) as `f`, and leaves the VFS if it succeeds.
+    pub fn change_on_host(&self, f: impl FnOnce() -> libc::c_int) -> libc::c_int {
+        let mut dir = self.write();
+        let ret = f();
+        if ret == 0 {
+            *dir = None;
+        }
+
+        ret
+    }
+
+    // must not be called while holding the lock across a fork(), as with `register_atfork`.
+    pub fn register_atfork(&'static self) {
+        if FORK_CWD.set(self).is_ok() {
+            unsafe {
+                libc::pthread_atfork(
+                    Some(lock_before_fork),
+                    Some(unlock_after_fork),
+                    Some(unlock_after_fork),
+                )
+            };
+        }
+    }
+}
+
+// a chdir() in another thread at the moment of a fork() would leave the lock held for good in
+// the child, so it's taken around the fork like the `Fs` one.
+static FORK_CWD: OnceLock<&'static WorkingDir> = OnceLock::new();
+
+thread_local! {
+    static FORK_GUARD: RefCell<Option<RwLockWriteGuard<'static, Option<PathBuf>>>> =
+        const { RefCell::new(None) };
+}
+
+extern "C" fn lock_before_fork() {
+    if let Some(cwd) = FORK_CWD.get() {
+        let guard = cwd.write();
+        FORK_GUARD.with(|fork_guard| *fork_guard.borrow_mut() = Some(guard));
+    }
+}
+
+extern "C" fn unlock_after_fork() {
+    FORK_GUARD.with(|fork_guard| fork_guard.borrow_mut().take());
+}
@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+const SHARDS: usize = 16;
+// fd numbers the bitmap covers when RLIMIT_NOFILE doesn't bound them lower: the kernel's
+// default fs.nr_open.
+const MAX_FDS: u64 = 1 << 20;
+
+type Shard<T> = HashMap<i32, Arc<T>>;
+
+// the fds open in the VFS, each pointing at its open file description. an fd is looked up under
+// its own shard's lock only, and just long enough to clone the `Arc`, so threads opening,
+// closing and reading different fds rarely meet.
+#[derive(Debug)]
+pub(crate) struct FdTable<T> {
+    shards: [Mutex<Shard<T>>; SHARDS],
+    // one bit per fd number, set while the fd is in the table. `contains()` reads only this,
+    // so telling a VFS fd from a host one takes no lock at all: hooks like write() run for
+    // every fd in the process, from signal handlers too. fds past the end are never stored.
+    bits: Box<[AtomicU64]>,
+}
+
+impl<T> FdTable<T> {
+    pub fn new() -> Self {
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        let max_fds = if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } == 0 {
+            limit.rlim_max.min(MAX_FDS)
+        } else {
+            MAX_FDS
+        };
+
+        FdTable {
+            shards: std::array::from_fn(|_| Mutex::new(HashMap::new())),
+            bits: (0..max_fds.div_ceil(64))
+                .map(|_| AtomicU64::new(0))
+                .collect(),
+        }
+    }
+
+    fn bit(&self, fd: i32) -> Option<(&AtomicU64, u64)> {
+        let fd = usize::try_from(fd).ok()?;
+
+        Some((self.bits.get(fd / 64)?, 1 << (fd % 64)))
+    }
+
+    // whether `fd` can be stored at all.
+    pub fn fits(&self, fd: i32) -> bool {
+        self.bit(fd).is_some()
+    }
+
+    // a panic elsewhere can't leave a HashMap half-written, so poisoning is ignored.
+    fn shard(&self, fd: i32) -> MutexGuard<'_, Shard<T>> {
+        self.shards[fd.rem_euclid(SHARDS as i32) as usize]
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+    }
+
+    pub fn get(&self, fd: i32) -> Option<Arc<T>> {
+        self.shard(fd).get(&fd).cloned()
+    }
+
+    pub fn contains(&self, fd: i32) -> bool {
+        self.bit(fd)
+            .is_some_and(|(word, mask)| word.load(Ordering::Acquire) & mask != 0)
+    }
+
+    // returns whatever `fd` pointed at before, for the caller to release. `fd` must fit.
+    pub fn insert(&self, fd: i32, file: Arc<T>) -> Option<Arc<T>> {
+        let (word, mask) = self.bit(fd).expect("fd out of the table's range");
+        let mut shard = self.shard(fd);
+        let previous = shard.insert(fd, file);
+        word.fetch_or(mask, Ordering::Release);
+
+        previous
+    }
+
+    pub fn remove(&self, fd: i32) -> Option<Arc<T>> {
+        let (word, mask) = self.bit(fd)?;
+        let mut shard = self.shard(fd);
+        word.fetch_and(!mask, Ordering::Release);
+
+        shard.remove(&fd)
+    }
+
+    pub fn fds(&self) -> Vec<i32> {
+        (0..SHARDS as i32)
+            .flat_map(|shard| self.shard(shard).keys().copied().collect::<Vec<_>>())
+            .collect()
+    }
+
+    // every shard, locked in order, so nothing in the table changes until they're dropped.
+    pub fn lock_all(&self) -> Vec<MutexGuard<'_, Shard<T>>> {
+        (0..SHARDS as i32).map(|shard| self.shard(shard)).collect()
+    }
+}
@@ -1,26 +1,76 @@
+mod cwd;
+mod fd_table;
+mod mount;
+mod path;
+mod upper;
+
+use fd_table::FdTable;
 use fxhash::FxHasher;
+use std::borrow::Cow;
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::ffi::OsStr;
 use std::ffi::OsString;
 use std::hash::Hash;
 use std::hash::Hasher;
-use std::io::Read;
 use std::os::unix::ffi::OsStrExt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, MutexGuard, OnceLock, RwLock, RwLockReadGuard, RwLockWriteGuard};
 use trie_rs::map::Trie;
 use trie_rs::map::TrieBuilder;
+use upper::{Entry, Upper};
+
+pub use cwd::WorkingDir;
+pub use mount::MountTable;
+pub use path::{normalize, split};
 
 #[derive(Debug, PartialEq)]
 enum FileType<'a> {
     File {
         file: &'a [u8],
-        offset: u64,
         inode: u64,
     },
     Directory {
         inode: u64,
         entries: Vec<Vec<OsString>>,
     },
+    // a file in an on-disk overlay. it's opened and stat()ed on the host, not here.
+    HostFile {
+        inode: u64,
+    },
+    // a file in the writable layer; `id` is its inode there.
+    UpperFile {
+        id: u64,
+        inode: u64,
+        writable: bool,
+        append: bool,
+    },
+}
+
+// an open file description, shared through its `Arc` by every fd dup()ed from the same open()
+// like the kernel's `struct file`, so offsets move together. the offset is atomic so that
+// read() and lseek() never lock anything for it.
+#[derive(Debug)]
+struct OpenFile<'a> {
+    file_type: FileType<'a>,
+    path: Vec<OsString>,
+    offset: AtomicU64,
+}
+
+// how an on-disk overlay directory shadows a path, as recorded with `Fs::record()`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LayerEntry {
+    File,
+    Directory { opaque: bool },
+    Whiteout,
+}
+
+// what rename() carries over to the new path.
+enum MovedNode {
+    Directory,
+    UpperFile(u64),
+    File(Vec<u8>),
 }
 
 #[derive(Debug)]
@@ -29,10 +79,16 @@ pub struct FsDir {
     offset: u64,
 }
 
+// shared by every thread as it is: the archive never changes, so looking something up in it
+// takes no lock. only the fd table and the writable layer change, and each has its own.
 #[derive(Debug)]
 pub struct Fs<'a> {
     trie: Trie<&'a OsStr, &'a [u8]>,
-    fd_map: HashMap<i32, FileType<'a>>,
+    fds: FdTable<OpenFile<'a>>,
+    upper: Option<RwLock<Upper>>,
+    // empty means paths are looked up in the archive as they are. set up before the `Fs` is
+    // shared, so it isn't locked either.
+    mounts: MountTable,
 }
 
 #[cfg(all(target_os = "linux", target_arch = "aarch64"))]
@@ -62,51 +118,238 @@ impl<'a> Fs<'a> {
     pub fn new(builder: TrieBuilder<&'static OsStr, &'static [u8]>) -> Self {
         Self {
             trie: builder.build(),
-            fd_map: HashMap::new(),
+            fds: FdTable::new(),
+            upper: None,
+            mounts: MountTable::new(),
         }
     }
 
-    pub fn entries(&self) {
-        let hoge: Vec<(OsString, &&[u8])> = self.trie.iter().collect();
-        dbg!(hoge);
-        ()
+    // serves the archive's `target` subtree at `prefix`. once anything is mounted, paths
+    // outside every mount don't exist here.
+    pub fn mount(&mut self, prefix: &Vec<&OsStr>, target: &Vec<&OsStr>) {
+        self.mounts.add(
+            &prefix.iter().collect::<std::path::PathBuf>(),
+            &target.iter().collect::<std::path::PathBuf>(),
+        );
     }
 
-    fn get_inode_from_path(&self, path: &Vec<&OsStr>) -> u64 {
+    pub fn set_mounts(&mut self, mounts: MountTable) {
+        self.mounts = mounts;
+    }
+
+    // where `path` lives in the archive, after the longest matching mount.
+    fn archive_path<'p>(&'p self, path: &[&'p OsStr]) -> Option<Vec<&'p OsStr>> {
+        if self.mounts.is_empty() {
+            return Some(path.to_vec());
+        }
+
+        self.mounts.archive_path(path)
+    }
+
+    // the writable layer is keyed by archive path, so a file reached through two mounts is
+    // one file there too. None outside every mount, where nothing exists.
+    fn layer_key(&self, path: &[&OsStr]) -> Option<Vec<OsString>> {
+        let archive_path = self.archive_path(path)?;
+
+        Some(archive_path.iter().map(|s| s.to_os_string()).collect())
+    }
+
+    // lays an empty in-memory writable layer over the archive. until then every mutation
+    // fails with EROFS.
+    pub fn enable_upper(&mut self) {
+        self.upper.get_or_insert_with(Default::default);
+    }
+
+    // these run under hooks, where a panic aborts the host, so poisoning is ignored: every
+    // mutation leaves the layer consistent before it can fail.
+    fn read_layer(&self) -> Option<RwLockReadGuard<'_, Upper>> {
+        self.upper
+            .as_ref()
+            .map(|upper| upper.read().unwrap_or_else(|err| err.into_inner()))
+    }
+
+    fn write_layer(&self) -> Option<RwLockWriteGuard<'_, Upper>> {
+        self.upper
+            .as_ref()
+            .map(|upper| upper.write().unwrap_or_else(|err| err.into_inner()))
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.upper.is_some()
+    }
+
+    // every kompo fd is backed by a real, empty kernel object: the number stays reserved, and
+    // anything that bypasses our hooks (raw syscalls, poll, a child process) sees an inert file
+    // at EOF instead of whatever fd 0 happens to be. it needs no stdin, unlike dup(0), and is
+    // close-on-exec so it never leaks into another program. the memfd is sealed empty, so a
+    // raw write() can't put anything in it either.
+    fn alloc_fd() -> Option<i32> {
+        #[cfg(target_os = "linux")]
+        {
+            let fd = unsafe {
+                libc::memfd_create(
+                    c"kompo_fs".as_ptr(),
+                    libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING,
+                )
+            };
+            if fd != -1 {
+                let seals = libc::F_SEAL_SEAL
+                    | libc::F_SEAL_SHRINK
+                    | libc::F_SEAL_GROW
+                    | libc::F_SEAL_WRITE;
+                // raw syscalls: fcntl() and close() are hooked, and the caller may hold the Fs
+                // lock.
+                if unsafe { libc::syscall(libc::SYS_fcntl, fd, libc::F_ADD_SEALS, seals) } != -1 {
+                    return Some(fd);
+                }
+                unsafe { libc::syscall(libc::SYS_close, fd) };
+            }
+        }
+
+        // an absolute host path, so kompo_wrap's open() passes it straight through.
+        let fd = unsafe { libc::open(c"/dev/null".as_ptr(), libc::O_RDONLY | libc::O_CLOEXEC) };
+
+        (fd != -1).then_some(fd)
+    }
+
+    // an fd the table can hold. the rare one past its end is given back.
+    fn new_fd(&self) -> Option<i32> {
+        let fd = Self::alloc_fd()?;
+        if self.fds.fits(fd) {
+            return Some(fd);
+        }
+
+        unsafe { libc::close(fd) };
+        None
+    }
+
+    fn inode_of<S: Hash>(archive_path: &[S]) -> u64 {
         let mut hasher = FxHasher::default();
-        path.hash(&mut hasher);
+        archive_path.hash(&mut hasher);
 
         hasher.finish()
     }
 
+    // inodes come from the archive path, so every mount reaching a file agrees on it.
+    fn get_inode_from_path(&self, path: &Vec<&OsStr>) -> u64 {
+        match self.archive_path(path) {
+            Some(archive_path) => Self::inode_of(&archive_path),
+            None => Self::inode_of(path),
+        }
+    }
+
     fn get_file_type_from_path(&self, search_path: &Vec<&OsStr>) -> Option<FileType<'a>> {
-        if let Some(file) = self.trie.exact_match(&search_path) {
-            let inode = self.get_inode_from_path(search_path);
+        self.file_type_in(self.read_layer().as_deref(), search_path)
+    }
 
-            return Some(FileType::File {
-                file,
-                offset: 0,
+    // lookups take the writable layer as it is under a lock the caller already holds, if
+    // there is one.
+    fn file_type_in(
+        &self,
+        upper: Option<&Upper>,
+        search_path: &Vec<&OsStr>,
+    ) -> Option<FileType<'a>> {
+        let Some(upper) = upper else {
+            return self.lower_file_type_from_path(search_path);
+        };
+
+        let key = self.layer_key(search_path)?;
+        let inode = Self::inode_of(&key);
+        match upper.entry(&key) {
+            Some(Entry::File(id)) => Some(FileType::UpperFile {
+                id: *id,
                 inode,
-            });
+                writable: false,
+                append: false,
+            }),
+            Some(Entry::HostFile) => Some(FileType::HostFile { inode }),
+            Some(Entry::Directory { .. }) => Some(FileType::Directory {
+                inode,
+                entries: self.merged_entries(upper, search_path, &key),
+            }),
+            Some(Entry::Whiteout) => None,
+            None if !upper.is_lower_visible(&key) => None,
+            None => match self.lower_file_type_from_path(search_path)? {
+                FileType::Directory { inode, .. } => Some(FileType::Directory {
+                    inode,
+                    entries: self.merged_entries(upper, search_path, &key),
+                }),
+                file_type => Some(file_type),
+            },
+        }
+    }
+
+    // a directory's entries with the writable layer applied: the archive's, minus whiteouts,
+    // plus whatever was created on top. an opaque directory hides the archive's entirely.
+    // entries are named under `search_path`; `key` is where it lives in the writable layer.
+    fn merged_entries(
+        &self,
+        upper: &Upper,
+        search_path: &Vec<&OsStr>,
+        key: &[OsString],
+    ) -> Vec<Vec<OsString>> {
+        let is_opaque = matches!(upper.entry(key), Some(Entry::Directory { opaque: true }));
+        let entry_path = |name: &OsStr| {
+            search_path
+                .iter()
+                .map(|s| s.to_os_string())
+                .chain(std::iter::once(name.to_os_string()))
+                .collect::<Vec<_>>()
+        };
+
+        let mut entries = Vec::new();
+        if !is_opaque && upper.is_lower_visible(key) {
+            if let Some(FileType::Directory {
+                entries: lower_entries,
+                ..
+            }) = self.lower_file_type_from_path(search_path)
+            {
+                entries.extend(lower_entries.into_iter().filter(|entry| {
+                    let name = entry.last().expect("empty entry");
+                    let child = key.iter().chain(std::iter::once(name)).collect::<Vec<_>>();
+                    upper.entry(&child).is_none()
+                }));
+            }
         }
 
-        let depth = search_path.len() + 1;
+        let mut created = upper
+            .children(key)
+            .filter(|(_, entry)| **entry != Entry::Whiteout)
+            .map(|(path, _)| entry_path(path.last().expect("empty entry")))
+            .collect::<Vec<_>>();
+        created.sort();
+        entries.extend(created);
+
+        entries
+    }
+
+    fn lower_file_type_from_path(&self, search_path: &Vec<&OsStr>) -> Option<FileType<'a>> {
+        let archive_path = self.archive_path(search_path)?;
+        if let Some(file) = self.trie.exact_match(&archive_path) {
+            let inode = Self::inode_of(&archive_path);
+
+            return Some(FileType::File { file, inode });
+        }
+
+        let depth = archive_path.len() + 1;
         let mut uniq_file = HashSet::new();
 
         let entries: Vec<_> = self
             .trie
-            .predictive_search(&search_path)
+            .predictive_search(&archive_path)
             .filter_map(|(path, _): (Vec<&OsStr>, _)| {
                 if path.len() >= depth {
-                    let id = self.get_inode_from_path(&path);
+                    // dedupe on the child itself, not on each file below it.
+                    let id = Self::inode_of(&path[..depth]);
 
                     if uniq_file.contains(&id) {
                         None
                     } else {
                         uniq_file.insert(id);
-                        let next_depth_path = path
+                        // entries are named by the path they were looked up with.
+                        let next_depth_path = search_path
                             .iter()
-                            .take(depth)
+                            .chain(path.get(depth - 1))
                             .map(|&s| s.to_os_string())
                             .collect::<Vec<OsString>>();
                         Some(next_depth_path)
@@ -119,7 +362,7 @@ impl<'a> Fs<'a> {
 
         if entries.len() > 0 {
             // dbg!(&search_path);
-            let inode = self.get_inode_from_path(search_path);
+            let inode = Self::inode_of(&archive_path);
 
             return Some(FileType::Directory { inode, entries });
         }
@@ -127,8 +370,161 @@ impl<'a> Fs<'a> {
         None
     }
 
+    // cheaper than get_file_type_from_path(): doesn't collect directory entries.
+    // Some(true) for a directory, Some(false) for a file, None if it doesn't exist.
+    pub fn is_dir_from_path(&self, search_path: &Vec<&OsStr>) -> Option<bool> {
+        self.is_dir_in(self.read_layer().as_deref(), search_path)
+    }
+
+    fn is_dir_in(&self, upper: Option<&Upper>, search_path: &Vec<&OsStr>) -> Option<bool> {
+        if let Some(upper) = upper {
+            let key = self.layer_key(search_path)?;
+            match upper.entry(&key) {
+                Some(Entry::File(_) | Entry::HostFile) => return Some(false),
+                Some(Entry::Directory { .. }) => return Some(true),
+                Some(Entry::Whiteout) => return None,
+                None if !upper.is_lower_visible(&key) => return None,
+                None => {}
+            }
+        }
+
+        self.lower_is_dir_from_path(search_path)
+    }
+
+    fn lower_is_dir_from_path(&self, search_path: &Vec<&OsStr>) -> Option<bool> {
+        let archive_path = self.archive_path(search_path)?;
+        if self.trie.exact_match(&archive_path).is_some() {
+            return Some(false);
+        }
+
+        self.trie
+            .predictive_search(&archive_path)
+            .any(|(path, _): (Vec<&OsStr>, _)| path.len() > archive_path.len())
+            .then_some(true)
+    }
+
+    // resolves `.` and `..` component by component like the kernel does: a missing or
+    // non-directory component is an error as soon as anything follows it, but the last one
+    // needn't exist, so the result can also name a file about to be created. directories
+    // outside every mount belong to the host and are taken as they are. the archive has no
+    // symlinks, so every existing component resolves to itself.
+    pub fn resolve(&self, path: &[&OsStr]) -> Result<Vec<OsString>, i32> {
+        let upper = self.read_layer();
+        let mut resolved: Vec<&OsStr> = vec![OsStr::new("/")];
+        // Some(is_dir) for the last resolved component, None if it doesn't exist.
+        let mut kind = Some(true);
+
+        for comp in path.iter().skip_while(|comp| comp.as_bytes() == b"/") {
+            match (comp.as_bytes(), kind) {
+                (_, Some(false)) => return Err(libc::ENOTDIR),
+                (b"" | b".", _) => {}
+                (_, None) => return Err(libc::ENOENT),
+                (b"..", _) => {
+                    if resolved.len() > 1 {
+                        resolved.pop();
+                    }
+                }
+                _ => {
+                    resolved.push(comp);
+                    kind = if self.archive_path(&resolved).is_none() {
+                        Some(true)
+                    } else {
+                        self.is_dir_in(upper.as_deref(), &resolved)
+                    };
+                }
+            }
+        }
+
+        Ok(resolved.into_iter().map(|s| s.to_os_string()).collect())
+    }
+
+    // like `resolve`, but the last component has to exist as well.
+    pub fn realpath(&self, path: &Vec<&OsStr>) -> Result<Vec<OsString>, i32> {
+        let resolved = self.resolve(path)?;
+        let search_path = resolved.iter().map(|s| s.as_os_str()).collect::<Vec<_>>();
+        self.is_dir_from_path(&search_path).ok_or(libc::ENOENT)?;
+
+        Ok(resolved)
+    }
+
+    // lock-free, so it's safe wherever the fd may well not be ours.
     pub fn is_fd_exists(&self, fd: i32) -> bool {
-        self.fd_map.contains_key(&fd)
+        self.fds.contains(fd)
+    }
+
+    pub fn is_dir_fd(&self, fd: i32) -> bool {
+        self.fds
+            .get(fd)
+            .is_some_and(|open_file| matches!(open_file.file_type, FileType::Directory { .. }))
+    }
+
+    pub fn path_from_fd(&self, fd: i32) -> Option<Vec<OsString>> {
+        self.fds.get(fd).map(|open_file| open_file.path.clone())
+    }
+
+    // a file in the writable layer stays alive while it's open, even once it's unlinked. only
+    // that needs the layer's write lock; everything else is opened under the read lock.
+    fn file_type_to_open(&self, path: &Vec<&OsStr>) -> Option<FileType<'a>> {
+        let file_type = self.get_file_type_from_path(path)?;
+        if !matches!(file_type, FileType::UpperFile { .. }) {
+            return Some(file_type);
+        }
+
+        let mut upper = self.write_layer()?;
+        let file_type = self.file_type_in(Some(&upper), path)?;
+        if let FileType::UpperFile { id, .. } = &file_type {
+            upper.open(*id);
+        }
+
+        Some(file_type)
+    }
+
+    // a writable-layer file must have been opened in the layer already. the caller must not
+    // hold the layer's lock, as whatever `fd` referred to before gets released.
+    fn insert_fd(&self, fd: i32, file_type: FileType<'a>, path: &Vec<&OsStr>) {
+        let open_file = Arc::new(OpenFile {
+            file_type,
+            path: path.iter().map(|s| s.to_os_string()).collect(),
+            offset: AtomicU64::new(0),
+        });
+
+        if let Some(previous) = self.fds.insert(fd, open_file) {
+            self.release(previous);
+        }
+    }
+
+    // drops one fd's reference to an open file description, and the description itself with
+    // the last one.
+    fn release(&self, open_file: Arc<OpenFile<'a>>) {
+        let Some(open_file) = Arc::into_inner(open_file) else {
+            return;
+        };
+
+        if let (FileType::UpperFile { id, .. }, Some(mut upper)) =
+            (open_file.file_type, self.write_layer())
+        {
+            upper.release(id);
+        }
+    }
+
+    // `new_fd` has already been dup()ed from `fd` by the kernel; make it share `fd`'s open file
+    // description. whatever `new_fd` referred to before is released, as dup2() does.
+    pub fn dup(&self, fd: i32, new_fd: i32) -> bool {
+        let Some(open_file) = self.fds.get(fd) else {
+            return false;
+        };
+        if fd == new_fd {
+            return true;
+        }
+        if !self.fds.fits(new_fd) {
+            return false;
+        }
+
+        if let Some(previous) = self.fds.insert(new_fd, open_file) {
+            self.release(previous);
+        }
+
+        true
     }
 
     pub fn is_dir_exists(&self, dir: &Box<FsDir>) -> bool {
@@ -146,7 +542,7 @@ impl<'a> Fs<'a> {
         }
     }
 
-    fn get_stat_from_file_type(&self, file_type: &FileType) -> libc::stat {
+    fn get_stat_from_file_type(upper: Option<&Upper>, file_type: &FileType) -> libc::stat {
         let mut stat = std::mem::MaybeUninit::<libc::stat>::uninit();
         let stat_ptr = stat.as_mut_ptr();
 
@@ -199,189 +595,767 @@ impl<'a> Fs<'a> {
                     (*stat_ptr).st_ctime = 0;
                     (*stat_ptr).st_ctime_nsec = 0;
 
+                    stat.assume_init()
+                }
+                FileType::HostFile { inode } => {
+                    (*stat_ptr).st_dev = Self::DEV;
+                    (*stat_ptr).st_ino = *inode;
+                    (*stat_ptr).st_mode = libc::S_IFREG // 644
+                                    | libc::S_IWUSR
+                                    | libc::S_IRUSR
+                                    | libc::S_IRGRP
+                                    | libc::S_IROTH;
+                    (*stat_ptr).st_nlink = 1;
+                    (*stat_ptr).st_uid = libc::getuid();
+                    (*stat_ptr).st_gid = libc::getgid();
+                    (*stat_ptr).st_rdev = 0;
+                    (*stat_ptr).st_size = 0;
+                    (*stat_ptr).st_blksize = 4096;
+                    (*stat_ptr).st_blocks = 0;
+                    (*stat_ptr).st_atime = 0;
+                    (*stat_ptr).st_atime_nsec = 0;
+                    (*stat_ptr).st_mtime = 0;
+                    (*stat_ptr).st_mtime_nsec = 0;
+                    (*stat_ptr).st_ctime = 0;
+                    (*stat_ptr).st_ctime_nsec = 0;
+
+                    stat.assume_init()
+                }
+                FileType::UpperFile { id, inode, .. } => {
+                    let size = upper
+                        .and_then(|upper| upper.inodes.get(id))
+                        .map_or(0, |inode| inode.data.len());
+
+                    (*stat_ptr).st_dev = Self::DEV;
+                    (*stat_ptr).st_ino = *inode;
+                    (*stat_ptr).st_mode = libc::S_IFREG // 644
+                                    | libc::S_IWUSR
+                                    | libc::S_IRUSR
+                                    | libc::S_IRGRP
+                                    | libc::S_IROTH;
+                    (*stat_ptr).st_nlink = 1;
+                    (*stat_ptr).st_uid = libc::getuid();
+                    (*stat_ptr).st_gid = libc::getgid();
+                    (*stat_ptr).st_rdev = 0;
+                    (*stat_ptr).st_size = size as _;
+                    (*stat_ptr).st_blksize = 4096;
+                    (*stat_ptr).st_blocks = (size.div_ceil(512).div_ceil(8) * 8) as i64;
+                    (*stat_ptr).st_atime = 0;
+                    (*stat_ptr).st_atime_nsec = 0;
+                    (*stat_ptr).st_mtime = 0;
+                    (*stat_ptr).st_mtime_nsec = 0;
+                    (*stat_ptr).st_ctime = 0;
+                    (*stat_ptr).st_ctime_nsec = 0;
+
                     stat.assume_init()
                 }
             }
         }
     }
 
-    pub fn open(&mut self, path: &Vec<&OsStr>) -> Option<i32> {
-        match self.get_file_type_from_path(path) {
-            Some(file_type) => {
-                let fd = unsafe { libc::dup(0) };
+    pub fn open(&self, path: &Vec<&OsStr>) -> Option<i32> {
+        let file_type = self.file_type_to_open(path)?;
+        let Some(fd) = self.new_fd() else {
+            if let FileType::UpperFile { id, .. } = file_type {
+                self.write_layer()?.release(id);
+            }
+            return None;
+        };
+
+        self.insert_fd(fd, file_type, path);
 
-                self.fd_map.insert(fd, file_type);
+        Some(fd)
+    }
+
+    pub fn open_at(&self, path: &Vec<&OsStr>) -> Option<i32> {
+        self.open(path)
+    }
+
+    fn open_file(&self, fd: i32) -> Result<Arc<OpenFile<'a>>, i32> {
+        self.fds.get(fd).ok_or(libc::EBADF)
+    }
 
-                Some(fd)
+    // runs `f` on the contents read() and lseek() see through `open_file`. the writable layer
+    // is only locked for a file that lives there.
+    fn with_data<T>(
+        &self,
+        open_file: &OpenFile<'a>,
+        f: impl FnOnce(&[u8]) -> Result<T, i32>,
+    ) -> Result<T, i32> {
+        match &open_file.file_type {
+            FileType::File { file, .. } => f(file),
+            FileType::UpperFile { id, .. } => {
+                let upper = self.read_layer().ok_or(libc::EBADF)?;
+                let inode = upper.inodes.get(id).ok_or(libc::EBADF)?;
+
+                f(&inode.data)
             }
-            None => None,
+            FileType::Directory { .. } => Err(libc::EISDIR),
+            FileType::HostFile { .. } => Err(libc::EBADF),
         }
     }
 
-    pub fn open_at(&mut self, path: &Vec<&OsStr>) -> Option<i32> {
-        match self.get_file_type_from_path(path) {
-            Some(file_type) => {
-                let fd = unsafe { libc::dup(0) };
+    // threads reading through different fds, or the same one, don't wait on each other: the
+    // bytes are copied first, and the offset only moves if nobody else moved it meanwhile.
+    pub fn read(&self, fd: i32, buf: &mut [u8]) -> Result<isize, i32> {
+        let open_file = self.open_file(fd)?;
 
-                self.fd_map.insert(fd, file_type);
+        self.with_data(&open_file, |file| {
+            let mut offset = open_file.offset.load(Ordering::Acquire);
+            loop {
+                if offset >= file.len() as u64 {
+                    return Ok(0);
+                }
 
-                Some(fd)
+                let start = offset as usize;
+                let read_size = (file.len() - start).min(buf.len());
+                buf[..read_size].copy_from_slice(&file[start..start + read_size]);
+
+                match open_file.offset.compare_exchange_weak(
+                    offset,
+                    offset + read_size as u64,
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                ) {
+                    Ok(_) => return Ok(read_size as isize),
+                    Err(current) => offset = current,
+                }
             }
-            None => None,
-        }
+        })
     }
 
-    pub fn read(&mut self, fd: i32, buf: &mut [u8]) -> Option<isize> {
-        match self.fd_map.get_mut(&fd) {
-            Some(file_type) => match file_type {
-                FileType::File { file, offset, .. } => {
-                    if *offset == file.len() as u64 {
-                        return Some(0);
-                    }
+    // a directory seeks like an empty file, so rewinding one works as with the kernel.
+    pub fn lseek(&self, fd: i32, offset: i64, whence: i32) -> Result<i64, i32> {
+        let open_file = self.open_file(fd)?;
+        let len = match self.with_data(&open_file, |data| Ok(data.len() as i64)) {
+            Ok(len) => len,
+            Err(libc::EISDIR) => 0,
+            Err(err) => return Err(err),
+        };
+
+        let mut new_offset = 0;
+        open_file
+            .offset
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+                let base = match whence {
+                    libc::SEEK_SET => 0,
+                    libc::SEEK_CUR => current as i64,
+                    libc::SEEK_END => len,
+                    _ => return None,
+                };
+                new_offset = base.checked_add(offset).filter(|o| *o >= 0)?;
 
-                    let read_size = (file.len() - *offset as usize).min(buf.len());
-                    buf[..read_size]
-                        .copy_from_slice(&file[*offset as usize..*offset as usize + read_size]);
+                Some(new_offset as u64)
+            })
+            .map_err(|_| libc::EINVAL)?;
 
-                    *offset += read_size as u64;
+        Ok(new_offset)
+    }
 
-                    Some(read_size as isize)
-                }
-                FileType::Directory { .. } => todo!(),
-            },
-            None => None,
+    pub fn close(&self, fd: i32) -> i32 {
+        if let Some(open_file) = self.fds.remove(fd) {
+            self.release(open_file);
         }
+
+        0
     }
 
-    pub fn close(&mut self, fd: i32) -> i32 {
-        self.fd_map.remove(&fd);
+    fn check_parent(&self, upper: Option<&Upper>, path: &[&OsStr]) -> Result<(), i32> {
+        if path.len() <= 1 {
+            return Err(libc::EEXIST);
+        }
 
-        0
+        match self.is_dir_in(upper, &path[..path.len() - 1].to_vec()) {
+            Some(true) => Ok(()),
+            Some(false) => Err(libc::ENOTDIR),
+            None => Err(libc::ENOENT),
+        }
     }
 
-    pub fn stat(&self, path: &Vec<&OsStr>, stat: *mut libc::stat) -> Option<i32> {
-        match self.get_file_type_from_path(path) {
-            Some(ref file_type) => {
-                unsafe { *stat = self.get_stat_from_file_type(file_type) };
+    // the writable layer's inode for the file at `path`, copying an embedded one up first.
+    fn copy_up(&self, upper: &mut Upper, path: &Vec<&OsStr>) -> Result<u64, i32> {
+        let key = self.layer_key(path).ok_or(libc::ENOENT)?;
+        if let Some(Entry::File(id)) = upper.entry(&key) {
+            return Ok(*id);
+        }
 
-                Some(0)
+        let archive_path = key.iter().map(|s| s.as_os_str()).collect::<Vec<_>>();
+        let data = self
+            .trie
+            .exact_match(&archive_path)
+            .ok_or(libc::ENOENT)?
+            .to_vec();
+
+        Ok(upper.create_file(&key, data))
+    }
+
+    // removes `path` from the merged view, leaving a whiteout if the archive has it.
+    fn remove_path(&self, upper: &mut Upper, path: &Vec<&OsStr>) -> Result<(), i32> {
+        let in_archive = self.lower_is_dir_from_path(path).is_some();
+        let key = self.layer_key(path).ok_or(libc::ENOENT)?;
+
+        upper.remove_tree(&key);
+        if in_archive {
+            upper.whiteout(&key);
+        }
+
+        Ok(())
+    }
+
+    // open(2) for writing: O_CREAT, O_EXCL, O_TRUNC and O_APPEND are honored, and an embedded
+    // file is copied up into the writable layer first. errors are errno values.
+    pub fn open_with_flags(&self, path: &Vec<&OsStr>, oflag: i32) -> Result<i32, i32> {
+        let write = oflag & libc::O_ACCMODE != libc::O_RDONLY;
+        let truncate = oflag & libc::O_TRUNC == libc::O_TRUNC;
+        // mutations hold the layer's write lock from the first lookup, so nothing changes
+        // under them. without a layer there's nothing to lock, and nothing to mutate.
+        let mut guard = self.write_layer();
+
+        match self.is_dir_in(guard.as_deref(), path) {
+            None => {
+                if oflag & libc::O_CREAT == 0 {
+                    return Err(libc::ENOENT);
+                }
+
+                self.check_parent(guard.as_deref(), path)?;
+                let key = self.layer_key(path).ok_or(libc::ENOENT)?;
+                let upper = guard.as_deref_mut().ok_or(libc::EROFS)?;
+                upper.create_file(&key, vec![]);
+            }
+            Some(true) => {
+                if write || oflag & libc::O_CREAT == libc::O_CREAT {
+                    return Err(libc::EISDIR);
+                }
+            }
+            Some(false) => {
+                if oflag & (libc::O_CREAT | libc::O_EXCL) == libc::O_CREAT | libc::O_EXCL {
+                    return Err(libc::EEXIST);
+                }
+
+                if write || truncate {
+                    let upper = guard.as_deref_mut().ok_or(libc::EROFS)?;
+                    let id = self.copy_up(upper, path)?;
+                    if truncate {
+                        upper.inodes.get_mut(&id).ok_or(libc::EIO)?.data.clear();
+                    }
+                }
+            }
+        }
+
+        let mut file_type = self
+            .file_type_in(guard.as_deref(), path)
+            .ok_or(libc::ENOENT)?;
+        if let FileType::UpperFile {
+            id,
+            writable,
+            append,
+            ..
+        } = &mut file_type
+        {
+            *writable = write;
+            *append = oflag & libc::O_APPEND == libc::O_APPEND;
+            if let Some(upper) = guard.as_deref_mut() {
+                upper.open(*id);
             }
-            None => None,
         }
+
+        let Some(fd) = self.new_fd() else {
+            if let (FileType::UpperFile { id, .. }, Some(upper)) = (file_type, guard.as_deref_mut())
+            {
+                upper.release(id);
+            }
+            return Err(libc::EMFILE);
+        };
+        drop(guard);
+        self.insert_fd(fd, file_type, path);
+
+        Ok(fd)
     }
 
-    pub fn lstat(&self, path: &Vec<&OsStr>, stat: *mut libc::stat) -> Option<i32> {
-        self.stat(path, stat)
+    pub fn write(&self, fd: i32, buf: &[u8]) -> Result<isize, i32> {
+        let open_file = self.open_file(fd)?;
+        let FileType::UpperFile {
+            id,
+            writable: true,
+            append,
+            ..
+        } = &open_file.file_type
+        else {
+            return Err(libc::EBADF);
+        };
+
+        let mut upper = self.write_layer().ok_or(libc::EBADF)?;
+        let data = &mut upper.inodes.get_mut(id).ok_or(libc::EBADF)?.data;
+        let start = if *append {
+            data.len()
+        } else {
+            open_file.offset.load(Ordering::Acquire) as usize
+        };
+
+        let end = start + buf.len();
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+        data[start..end].copy_from_slice(buf);
+        open_file.offset.store(end as u64, Ordering::Release);
+
+        Ok(buf.len() as isize)
     }
 
-    pub fn fstat(&self, fd: i32, stat: *mut libc::stat) -> Option<i32> {
-        match self.fd_map.get(&fd) {
-            Some(file_type) => {
-                unsafe { *stat = self.get_stat_from_file_type(file_type) };
+    pub fn ftruncate(&self, fd: i32, length: i64) -> Result<(), i32> {
+        let open_file = self.open_file(fd)?;
+        let length = usize::try_from(length).map_err(|_| libc::EINVAL)?;
 
-                Some(0)
+        match &open_file.file_type {
+            FileType::UpperFile {
+                id, writable: true, ..
+            } => {
+                let mut upper = self.write_layer().ok_or(libc::EBADF)?;
+                let data = &mut upper.inodes.get_mut(id).ok_or(libc::EBADF)?.data;
+                data.resize(length, 0);
+
+                Ok(())
             }
-            None => None,
+            FileType::Directory { .. } => Err(libc::EINVAL),
+            _ => Err(libc::EBADF),
         }
     }
 
-    pub fn file_read(&self, path: &Vec<&OsStr>) -> Option<*const u8> {
-        let file_type = self
-            .get_file_type_from_path(path)
-            .expect(format!("not found path: {:?}", path).as_str());
-
-        match file_type {
-            FileType::File { file, .. } => Some(file.as_ptr()),
-            _ => None,
+    pub fn truncate(&self, path: &Vec<&OsStr>, length: i64) -> Result<(), i32> {
+        let length = usize::try_from(length).map_err(|_| libc::EINVAL)?;
+        let mut guard = self.write_layer();
+
+        match self.is_dir_in(guard.as_deref(), path) {
+            None => Err(libc::ENOENT),
+            Some(true) => Err(libc::EISDIR),
+            Some(false) => {
+                let upper = guard.as_deref_mut().ok_or(libc::EROFS)?;
+                let id = self.copy_up(upper, path)?;
+                upper
+                    .inodes
+                    .get_mut(&id)
+                    .ok_or(libc::EIO)?
+                    .data
+                    .resize(length, 0);
+
+                Ok(())
+            }
         }
     }
 
-    pub fn fdopendir(&self, fd: i32) -> Option<FsDir> {
-        match self.fd_map.get(&fd) {
-            Some(FileType::Directory { .. }) => Some(FsDir { fd, offset: 0 }),
-            _ => None,
+    pub fn unlink(&self, path: &Vec<&OsStr>) -> Result<(), i32> {
+        let mut guard = self.write_layer();
+
+        match self.is_dir_in(guard.as_deref(), path) {
+            None => Err(libc::ENOENT),
+            Some(true) => Err(libc::EISDIR),
+            Some(false) => self.remove_path(guard.as_deref_mut().ok_or(libc::EROFS)?, path),
         }
     }
 
-    pub fn readdir(&self, dir: &mut FsDir) -> Option<*mut libc::dirent> {
-        match self.fd_map.get(&dir.fd) {
+    pub fn rmdir(&self, path: &Vec<&OsStr>) -> Result<(), i32> {
+        let mut guard = self.write_layer();
+
+        match self.file_type_in(guard.as_deref(), path) {
+            None => Err(libc::ENOENT),
             Some(FileType::Directory { entries, .. }) => {
-                if dir.offset >= entries.len() as u64 {
-                    return Some(std::ptr::null_mut());
+                if path.len() <= 1 {
+                    Err(libc::EBUSY)
+                } else if !entries.is_empty() {
+                    Err(libc::ENOTEMPTY)
+                } else {
+                    self.remove_path(guard.as_deref_mut().ok_or(libc::EROFS)?, path)
                 }
-                let full_path = &entries[dir.offset as usize];
-                let full_path = full_path
-                    .iter()
-                    .map(|s| s.as_os_str())
-                    .collect::<Vec<&OsStr>>();
-
-                let file_type = match self.get_file_type_from_path(&full_path) {
-                    Some(t) => match t {
-                        FileType::File { .. } => libc::DT_REG,
-                        FileType::Directory { .. } => libc::DT_DIR,
-                    },
-                    None => unreachable!(),
-                };
-                let inode = self.get_inode_from_path(&full_path);
-                let mut buf: DirEntryName = [0; 256];
-                full_path
-                    .last()
-                    .unwrap()
-                    .as_bytes()
-                    .iter()
-                    .take(255)
-                    .enumerate()
-                    .for_each(|(i, &b)| buf[i] = convert_byte(b));
-
-                dir.offset += 1;
-
-                let dirent = libc::dirent {
-                    d_ino: inode,
-                    d_off: 0,    // TODO
-                    d_reclen: 0, // TODO
-                    d_type: file_type,
-                    d_name: buf,
-                };
-
-                let dirent = Box::new(dirent);
-                Some(Box::into_raw(dirent) as *mut libc::dirent)
             }
-            _ => None,
+            Some(_) => Err(libc::ENOTDIR),
         }
     }
 
-    pub fn closedir(&mut self, dir: &FsDir) -> i32 {
-        self.close(dir.fd)
+    pub fn mkdir(&self, path: &Vec<&OsStr>) -> Result<(), i32> {
+        let mut guard = self.write_layer();
+        if self.is_dir_in(guard.as_deref(), path).is_some() {
+            return Err(libc::EEXIST);
+        }
+        self.check_parent(guard.as_deref(), path)?;
+
+        // recreating a removed embedded directory must not bring its old entries back.
+        let opaque = self.lower_is_dir_from_path(path).is_some();
+        let key = self.layer_key(path).ok_or(libc::ENOENT)?;
+        guard.as_deref_mut().ok_or(libc::EROFS)?.mkdir(&key, opaque);
+
+        Ok(())
     }
 
-    pub fn opendir(&mut self, path: &Vec<&OsStr>) -> Option<FsDir> {
-        match self.get_file_type_from_path(path) {
-            Some(file_type @ FileType::Directory { .. }) => {
-                let fd = unsafe { libc::dup(0) };
-                self.fd_map.insert(fd, file_type);
+    pub fn rename(&self, old: &Vec<&OsStr>, new: &Vec<&OsStr>) -> Result<(), i32> {
+        let mut guard = self.write_layer();
+        let old_is_dir = self.is_dir_in(guard.as_deref(), old).ok_or(libc::ENOENT)?;
+        self.check_parent(guard.as_deref(), new)?;
+        // compared in the archive, where two mounts of the same file are the same path.
+        let old_key = self.layer_key(old).ok_or(libc::ENOENT)?;
+        let new_key = self.layer_key(new).ok_or(libc::ENOENT)?;
+        if old_key == new_key {
+            return Ok(());
+        }
+        if new_key.len() > old_key.len() && new_key.starts_with(&old_key) {
+            return Err(libc::EINVAL);
+        }
 
-                Some(FsDir { fd, offset: 0 })
+        let new_file_type = self.file_type_in(guard.as_deref(), new);
+        match (old_is_dir, &new_file_type) {
+            (false, Some(FileType::Directory { .. })) => return Err(libc::EISDIR),
+            (
+                true,
+                Some(
+                    FileType::File { .. } | FileType::UpperFile { .. } | FileType::HostFile { .. },
+                ),
+            ) => return Err(libc::ENOTDIR),
+            (true, Some(FileType::Directory { entries, .. })) if !entries.is_empty() => {
+                return Err(libc::ENOTEMPTY)
             }
-            _ => None,
+            _ => {}
         }
-    }
+        let upper = guard.as_deref_mut().ok_or(libc::EROFS)?;
 
-    pub fn rewinddir(&mut self, dir: &mut FsDir) {
-        dir.offset = 0;
-    }
-}
+        let mut tree = Vec::new();
+        self.collect_tree(upper, old, vec![], &mut tree);
 
-impl<'a> Drop for Fs<'a> {
-    fn drop(&mut self) {
-        for fd in self.fd_map.keys() {
-            unsafe { libc::close(*fd) };
+        if new_file_type.is_some() {
+            self.remove_path(upper, new)?;
+        }
+
+        for (relative, node) in tree {
+            let path = new_key.iter().chain(&relative).collect::<Vec<_>>();
+
+            match node {
+                MovedNode::Directory => upper.mkdir(&path, true),
+                MovedNode::UpperFile(id) => upper.link(&path, id),
+                MovedNode::File(data) => {
+                    upper.create_file(&path, data);
+                }
+            }
         }
+
+        self.remove_path(upper, old)
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
+    // `path` and everything below it in the merged view, parents before their children.
+    fn collect_tree(
+        &self,
+        upper: &Upper,
+        path: &Vec<&OsStr>,
+        relative: Vec<OsString>,
+        tree: &mut Vec<(Vec<OsString>, MovedNode)>,
+    ) {
+        match self.file_type_in(Some(upper), path) {
+            Some(FileType::File { file, .. }) => {
+                tree.push((relative, MovedNode::File(file.to_vec())))
+            }
+            Some(FileType::UpperFile { id, .. }) => tree.push((relative, MovedNode::UpperFile(id))),
+            Some(FileType::HostFile { .. }) => {}
+            Some(FileType::Directory { entries, .. }) => {
+                tree.push((relative.clone(), MovedNode::Directory));
+
+                for entry in entries {
+                    let child = entry.iter().map(|s| s.as_os_str()).collect::<Vec<_>>();
+                    let mut relative = relative.clone();
+                    relative.push(entry.last().expect("empty entry").clone());
+
+                    self.collect_tree(upper, &child, relative, tree);
+                }
+            }
+            None => {}
+        }
+    }
+
+    // the on-disk overlay is indexed in the writable layer, so lookups and listings merge it
+    // with the archive like the in-memory files. kompo_fs does the host I/O and records the
+    // result here; the files themselves are never read through kompo_storage. `path` is an
+    // archive path, as the overlay directory mirrors the archive rather than the mounts. the
+    // layer has to be enabled with `enable_upper()` first, or this fails with EROFS.
+    pub fn record(&self, path: &Vec<&OsStr>, entry: LayerEntry) -> Result<(), i32> {
+        let mut upper = self.write_layer().ok_or(libc::EROFS)?;
+        Self::record_in(&mut upper, path, entry);
+
+        Ok(())
+    }
+
+    fn record_in(upper: &mut Upper, path: &Vec<&OsStr>, entry: LayerEntry) {
+        match entry {
+            LayerEntry::File => upper.host_file(path),
+            LayerEntry::Directory { opaque } => upper.mkdir(path, opaque),
+            LayerEntry::Whiteout => upper.whiteout(path),
+        }
+    }
+
+    // drops whatever was recorded at and below the archive path `path`, so the archive shows
+    // through again.
+    pub fn forget(&self, path: &Vec<&OsStr>) {
+        if let Some(mut upper) = self.write_layer() {
+            upper.remove_tree(path);
+        }
+    }
+
+    // `forget()` and then `record()` for each of `entries`, all at once, so no lookup sees the
+    // tree half rebuilt.
+    pub fn record_tree(
+        &self,
+        path: &Vec<&OsStr>,
+        entries: &[(Vec<&OsStr>, LayerEntry)],
+    ) -> Result<(), i32> {
+        let mut upper = self.write_layer().ok_or(libc::EROFS)?;
+
+        upper.remove_tree(path);
+        for (entry_path, entry) in entries {
+            Self::record_in(&mut upper, entry_path, *entry);
+        }
+
+        Ok(())
+    }
+
+    pub fn is_host_file(&self, path: &Vec<&OsStr>) -> bool {
+        let Some(key) = self.layer_key(path) else {
+            return false;
+        };
+
+        self.read_layer()
+            .is_some_and(|upper| upper.entry(&key) == Some(&Entry::HostFile))
+    }
+
+    // whether `path` is a file in the in-memory layer, whose size changes with every write.
+    pub fn is_upper_file(&self, path: &Vec<&OsStr>) -> bool {
+        let Some(key) = self.layer_key(path) else {
+            return false;
+        };
+
+        self.read_layer()
+            .is_some_and(|upper| matches!(upper.entry(&key), Some(Entry::File(_))))
+    }
+
+    // the archive alone, ignoring any layer: Some(true) for a directory, Some(false) for a file.
+    pub fn in_archive(&self, path: &Vec<&OsStr>) -> Option<bool> {
+        self.lower_is_dir_from_path(path)
+    }
+
+    pub fn dir_entries(&self, path: &Vec<&OsStr>) -> Option<Vec<Vec<OsString>>> {
+        match self.get_file_type_from_path(path)? {
+            FileType::Directory { entries, .. } => Some(entries),
+            _ => None,
+        }
+    }
+
+    // chmod() and utime() on the writable layer: metadata isn't stored, so they only check
+    // that the path exists.
+    pub fn set_metadata(&self, path: &Vec<&OsStr>) -> Result<(), i32> {
+        self.is_dir_from_path(path).ok_or(libc::ENOENT)?;
+        if self.upper.is_none() {
+            return Err(libc::EROFS);
+        }
+
+        Ok(())
+    }
+
+    pub fn stat(&self, path: &Vec<&OsStr>, stat: *mut libc::stat) -> Option<i32> {
+        let upper = self.read_layer();
+        match self.file_type_in(upper.as_deref(), path) {
+            Some(ref file_type) => {
+                unsafe { *stat = Self::get_stat_from_file_type(upper.as_deref(), file_type) };
+
+                Some(0)
+            }
+            None => None,
+        }
+    }
+
+    pub fn lstat(&self, path: &Vec<&OsStr>, stat: *mut libc::stat) -> Option<i32> {
+        self.stat(path, stat)
+    }
+
+    pub fn fstat(&self, fd: i32, stat: *mut libc::stat) -> Option<i32> {
+        match self.fds.get(fd) {
+            Some(open_file) => {
+                let upper = self.read_layer();
+                unsafe {
+                    *stat = Self::get_stat_from_file_type(upper.as_deref(), &open_file.file_type)
+                };
+
+                Some(0)
+            }
+            None => None,
+        }
+    }
+
+    pub fn file_contents(&self, path: &Vec<&OsStr>) -> Option<Cow<'a, [u8]>> {
+        match self.get_file_type_from_path(path)? {
+            FileType::File { file, .. } => Some(Cow::Borrowed(file)),
+            FileType::UpperFile { id, .. } => {
+                let upper = self.read_layer()?;
+
+                Some(Cow::Owned(upper.inodes.get(&id)?.data.clone()))
+            }
+            FileType::Directory { .. } | FileType::HostFile { .. } => None,
+        }
+    }
+
+    pub fn file_read(&self, path: &Vec<&OsStr>) -> Option<*const u8> {
+        match self.get_file_type_from_path(path)? {
+            FileType::File { file, .. } => Some(file.as_ptr()),
+            _ => None,
+        }
+    }
+
+    pub fn fdopendir(&self, fd: i32) -> Result<FsDir, i32> {
+        match self.open_file(fd)?.file_type {
+            FileType::Directory { .. } => Ok(FsDir { fd, offset: 0 }),
+            _ => Err(libc::ENOTDIR),
+        }
+    }
+
+    pub fn readdir(&self, dir: &mut FsDir) -> Option<*mut libc::dirent> {
+        let open_file = self.fds.get(dir.fd)?;
+        let FileType::Directory { entries, .. } = &open_file.file_type else {
+            return None;
+        };
+        let upper = self.read_layer();
+
+        // entries removed since the directory was opened are skipped, as the kernel would by
+        // then.
+        let (full_path, file_type) = loop {
+            let Some(full_path) = entries.get(dir.offset as usize) else {
+                return Some(std::ptr::null_mut());
+            };
+            dir.offset += 1;
+
+            let full_path = full_path
+                .iter()
+                .map(|s| s.as_os_str())
+                .collect::<Vec<&OsStr>>();
+            match self.file_type_in(upper.as_deref(), &full_path) {
+                Some(
+                    FileType::File { .. } | FileType::UpperFile { .. } | FileType::HostFile { .. },
+                ) => break (full_path, libc::DT_REG),
+                Some(FileType::Directory { .. }) => break (full_path, libc::DT_DIR),
+                None => continue,
+            }
+        };
+        let inode = self.get_inode_from_path(&full_path);
+        let mut buf: DirEntryName = [0; 256];
+        full_path
+            .last()
+            .map(|name| name.as_bytes())
+            .unwrap_or_default()
+            .iter()
+            .take(255)
+            .enumerate()
+            .for_each(|(i, &b)| buf[i] = convert_byte(b));
+
+        let dirent = libc::dirent {
+            d_ino: inode,
+            d_off: 0,    // TODO
+            d_reclen: 0, // TODO
+            d_type: file_type,
+            d_name: buf,
+        };
+
+        let dirent = Box::new(dirent);
+        Some(Box::into_raw(dirent) as *mut libc::dirent)
+    }
+
+    pub fn closedir(&self, dir: &FsDir) -> i32 {
+        self.close(dir.fd)
+    }
+
+    pub fn opendir(&self, path: &Vec<&OsStr>) -> Result<FsDir, i32> {
+        match self.get_file_type_from_path(path) {
+            Some(file_type @ FileType::Directory { .. }) => {
+                let fd = self.new_fd().ok_or(libc::EMFILE)?;
+                self.insert_fd(fd, file_type, path);
+
+                Ok(FsDir { fd, offset: 0 })
+            }
+            Some(_) => Err(libc::ENOTDIR),
+            None => Err(libc::ENOENT),
+        }
+    }
+
+    pub fn rewinddir(&self, dir: &mut FsDir) {
+        dir.offset = 0;
+    }
+}
+
+impl<'a> Drop for Fs<'a> {
+    fn drop(&mut self) {
+        for fd in self.fds.fds() {
+            unsafe { libc::close(fd) };
+        }
+    }
+}
+
+// pthread_atfork(3) handlers for the process-wide `Fs`. fork() copies only the calling thread,
+// so a lock held by any other thread at that moment would stay locked for good in the child.
+// prepare takes every lock the `Fs` has, the fd table's shards and then the writable layer's,
+// in the order the rest of the code takes them, and both sides release them once the fork is
+// done. the child keeps the parent's fds, so the fd table stays valid as is.
+static FORK_FS: OnceLock<&'static Fs<'static>> = OnceLock::new();
+
+type ForkGuard = (
+    Vec<MutexGuard<'static, HashMap<i32, Arc<OpenFile<'static>>>>>,
+    Option<RwLockWriteGuard<'static, Upper>>,
+);
+
+thread_local! {
+    static FORK_GUARD: RefCell<Option<ForkGuard>> = const { RefCell::new(None) };
+}
+
+extern "C" fn lock_before_fork() {
+    if let Some(fs) = FORK_FS.get() {
+        let fds = fs.fds.lock_all();
+        let upper = fs
+            .upper
+            .as_ref()
+            .map(|upper| upper.write().unwrap_or_else(|err| err.into_inner()));
+        FORK_GUARD.with(|fork_guard| *fork_guard.borrow_mut() = Some((fds, upper)));
+    }
+}
+
+extern "C" fn unlock_after_fork() {
+    FORK_GUARD.with(|fork_guard| fork_guard.borrow_mut().take());
+}
+
+// must not be called while holding one of `fs`'s locks across a fork(), or prepare deadlocks.
+pub fn register_atfork(fs: &'static Fs<'static>) {
+    if FORK_FS.set(fs).is_ok() {
+        unsafe {
+            libc::pthread_atfork(
+                Some(lock_before_fork),
+                Some(unlock_after_fork),
+                Some(unlock_after_fork),
+            )
+        };
+    }
+}
+
+// for tests forking while other threads hold the locks: runs `check` in a fork()ed child
+// and asserts that it returned true. a deadlocked child is killed instead of hanging the test.
+#[cfg(any(test, feature = "test-util"))]
+pub fn fork_and_check(check: impl FnOnce() -> bool) {
+    let pid = unsafe { libc::fork() };
+    if pid == 0 {
+        unsafe { libc::alarm(5) };
+
+        let ok = check();
+        unsafe { libc::_exit(if ok { 0 } else { 1 }) };
+    }
+
+    let mut status = 0;
+    assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+    assert!(libc::WIFEXITED(status), "child status: {}", status);
+    assert_eq!(libc::WEXITSTATUS(status), 0);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::Path;
 
     #[test]
     fn test_storage() {
@@ -417,7 +1391,6 @@ mod test {
             fs.get_file_type_from_path(&ls),
             Some(FileType::File {
                 file: &[1, 2, 3],
-                offset: 0,
                 inode: hasher.finish()
             })
         );
@@ -456,7 +1429,7 @@ mod test {
             .map(OsStr::new)
             .collect::<Vec<&OsStr>>();
         let mut hasher = FxHasher::default();
-        vec!["usr", "bin", "cat"]
+        ["usr", "bin", "cat"]
             .iter()
             .map(OsStr::new)
             .collect::<Vec<_>>()
@@ -466,9 +1439,948 @@ mod test {
             fs.get_file_type_from_path(&search_path),
             Some(FileType::File {
                 file: &[4, 5, 6],
-                offset: 0,
                 inode: hasher.finish()
             })
         );
     }
+
+    #[test]
+    fn test_lseek() {
+        let mut builder: TrieBuilder<&OsStr, &[u8]> = TrieBuilder::new();
+        let ls = vec!["usr", "bin", "ls"]
+            .into_iter()
+            .map(OsStr::new)
+            .collect::<Vec<_>>();
+
+        builder.push(&ls, &[1, 2, 3, 4, 5]);
+
+        let fs = Fs::new(builder);
+        let fd = fs.open(&ls).unwrap();
+        let mut buf = [0; 2];
+
+        assert_eq!(fs.lseek(fd, 3, libc::SEEK_SET), Ok(3));
+        assert_eq!(fs.read(fd, &mut buf), Ok(2));
+        assert_eq!(buf, [4, 5]);
+
+        assert_eq!(fs.lseek(fd, -4, libc::SEEK_CUR), Ok(1));
+        assert_eq!(fs.read(fd, &mut buf), Ok(2));
+        assert_eq!(buf, [2, 3]);
+
+        assert_eq!(fs.lseek(fd, 2, libc::SEEK_END), Ok(7));
+        assert_eq!(fs.read(fd, &mut buf), Ok(0));
+
+        assert_eq!(fs.lseek(fd, -8, libc::SEEK_END), Err(libc::EINVAL));
+    }
+
+    #[test]
+    fn test_path_from_fd() {
+        let mut builder: TrieBuilder<&OsStr, &[u8]> = TrieBuilder::new();
+        let ls = vec!["/", "usr", "bin", "ls"]
+            .into_iter()
+            .map(OsStr::new)
+            .collect::<Vec<_>>();
+        let bin = vec!["/", "usr", "bin"]
+            .into_iter()
+            .map(OsStr::new)
+            .collect::<Vec<_>>();
+
+        builder.push(&ls, &[1, 2, 3]);
+
+        let fs = Fs::new(builder);
+        let dir_fd = fs.open(&bin).unwrap();
+        let file_fd = fs.open(&ls).unwrap();
+
+        assert!(fs.is_dir_fd(dir_fd));
+        assert!(!fs.is_dir_fd(file_fd));
+        assert_eq!(
+            fs.path_from_fd(dir_fd),
+            Some(bin.iter().map(|s| s.to_os_string()).collect())
+        );
+
+        fs.close(dir_fd);
+        assert_eq!(fs.path_from_fd(dir_fd), None);
+    }
+
+    #[test]
+    fn test_realpath() {
+        let mut builder: TrieBuilder<&OsStr, &[u8]> = TrieBuilder::new();
+        let ls = vec!["/", "usr", "bin", "ls"]
+            .into_iter()
+            .map(OsStr::new)
+            .collect::<Vec<_>>();
+
+        builder.push(&ls, &[1, 2, 3]);
+
+        let fs = Fs::new(builder);
+        let realpath = |path: &str| {
+            let path = std::path::Path::new(path).iter().collect::<Vec<_>>();
+            fs.realpath(&path)
+                .map(|path| path.iter().collect::<std::path::PathBuf>().into_os_string())
+        };
+
+        assert_eq!(realpath("/usr/bin/ls"), Ok(OsString::from("/usr/bin/ls")));
+        assert_eq!(
+            realpath("/usr/./bin/../bin/ls"),
+            Ok(OsString::from("/usr/bin/ls"))
+        );
+        assert_eq!(realpath("/../../usr"), Ok(OsString::from("/usr")));
+        assert_eq!(realpath("/usr/bin/.."), Ok(OsString::from("/usr")));
+        assert_eq!(realpath("/usr/none/../bin"), Err(libc::ENOENT));
+        assert_eq!(realpath("/usr/bin/ls/.."), Err(libc::ENOTDIR));
+        assert_eq!(realpath("/usr/bin/ls/ls"), Err(libc::ENOTDIR));
+        assert_eq!(realpath("/usr/bi"), Err(libc::ENOENT));
+
+        let resolve = |path: &str| {
+            let path = split(OsStr::new(path)).collect::<Vec<_>>();
+            fs.resolve(&path)
+                .map(|path| path.iter().collect::<std::path::PathBuf>().into_os_string())
+        };
+
+        assert_eq!(resolve("/usr/bin/new"), Ok(OsString::from("/usr/bin/new")));
+        assert_eq!(
+            resolve("/usr/bin/./new/"),
+            Ok(OsString::from("/usr/bin/new"))
+        );
+        assert_eq!(resolve("/usr/new/ls"), Err(libc::ENOENT));
+        assert_eq!(resolve("/usr/bin/ls/"), Err(libc::ENOTDIR));
+        assert_eq!(resolve("/usr/bin/ls/."), Err(libc::ENOTDIR));
+        assert_eq!(
+            resolve("/../.././usr//bin/"),
+            Ok(OsString::from("/usr/bin"))
+        );
+    }
+
+    #[test]
+    fn test_dup() {
+        let mut builder: TrieBuilder<&OsStr, &[u8]> = TrieBuilder::new();
+        let ls = vec!["usr", "bin", "ls"]
+            .into_iter()
+            .map(OsStr::new)
+            .collect::<Vec<_>>();
+
+        builder.push(&ls, &[1, 2, 3, 4]);
+
+        let fs = Fs::new(builder);
+        let fd = fs.open(&ls).unwrap();
+        let new_fd = unsafe { libc::dup(fd) };
+        let mut buf = [0; 2];
+
+        assert!(fs.dup(fd, new_fd));
+        assert_eq!(fs.read(fd, &mut buf), Ok(2));
+        assert_eq!(fs.lseek(new_fd, 0, libc::SEEK_CUR), Ok(2));
+        assert_eq!(fs.read(new_fd, &mut buf), Ok(2));
+        assert_eq!(buf, [3, 4]);
+
+        fs.close(fd);
+        assert!(!fs.is_fd_exists(fd));
+        assert_eq!(fs.lseek(new_fd, 0, libc::SEEK_CUR), Ok(4));
+
+        fs.close(new_fd);
+        assert!(fs.fds.fds().is_empty());
+        assert!(!fs.is_fd_exists(new_fd));
+        assert!(!fs.dup(fd, new_fd));
+
+        // fds past the table's bitmap are never stored, so it alone answers is_fd_exists().
+        let fd = fs.open(&ls).unwrap();
+        assert!(!fs.dup(fd, i32::MAX));
+        assert!(!fs.dup(fd, -1));
+        assert!(!fs.is_fd_exists(i32::MAX));
+        assert!(!fs.is_fd_exists(-1));
+        fs.close(fd);
+        unsafe { libc::close(fd) };
+    }
+
+    #[test]
+    fn test_fd_is_inert() {
+        let mut builder: TrieBuilder<&OsStr, &[u8]> = TrieBuilder::new();
+        let ls = vec!["usr", "bin", "ls"]
+            .into_iter()
+            .map(OsStr::new)
+            .collect::<Vec<_>>();
+
+        builder.push(&ls, &[1, 2, 3]);
+
+        let fs = Fs::new(builder);
+        let fd = fs.open(&ls).unwrap();
+        let mut buf = [0; 3];
+
+        // bypassing the hooks reaches an empty file, not stdin.
+        assert_ne!(unsafe { libc::fcntl(fd, libc::F_GETFD) }, -1);
+        assert_eq!(
+            unsafe { libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) },
+            0
+        );
+        assert_eq!(fs.read(fd, &mut buf), Ok(3));
+
+        // nor can anything be written into it.
+        #[cfg(target_os = "linux")]
+        {
+            assert_eq!(
+                unsafe { libc::write(fd, buf.as_ptr() as *const libc::c_void, buf.len()) },
+                -1
+            );
+            assert_eq!(
+                std::io::Error::last_os_error().raw_os_error(),
+                Some(libc::EPERM)
+            );
+            assert_eq!(unsafe { libc::ftruncate(fd, 10) }, -1);
+        }
+    }
+
+    #[test]
+    fn test_fork_while_reading() {
+        let mut builder: TrieBuilder<&OsStr, &[u8]> = TrieBuilder::new();
+        let ls: &'static Vec<&OsStr> = Box::leak(Box::new(
+            vec!["usr", "bin", "ls"]
+                .into_iter()
+                .map(OsStr::new)
+                .collect::<Vec<_>>(),
+        ));
+
+        builder.push(ls, &[1, 2, 3]);
+
+        let mut fs = Fs::new(builder);
+        fs.enable_upper();
+        let fs: &'static Fs<'static> = Box::leak(Box::new(fs));
+        register_atfork(fs);
+
+        let fd = fs.open(ls).unwrap();
+        assert_eq!(
+            unsafe { libc::fcntl(fd, libc::F_GETFD) } & libc::FD_CLOEXEC,
+            libc::FD_CLOEXEC
+        );
+
+        let stop = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let readers = (0..4)
+            .map(|_| {
+                let stop = std::sync::Arc::clone(&stop);
+                std::thread::spawn(move || {
+                    while !stop.load(std::sync::atomic::Ordering::Relaxed) {
+                        let fd = fs.open(ls).unwrap();
+                        let mut buf = [0; 3];
+                        assert_eq!(fs.read(fd, &mut buf), Ok(3));
+                        fs.close(fd);
+                        unsafe { libc::close(fd) };
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for _ in 0..50 {
+            fork_and_check(|| {
+                let mut buf = [0; 3];
+
+                fs.lseek(fd, 0, libc::SEEK_SET) == Ok(0)
+                    && fs.read(fd, &mut buf) == Ok(3)
+                    && buf == [1, 2, 3]
+            });
+        }
+
+        stop.store(true, std::sync::atomic::Ordering::Relaxed);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_upper() {
+        let mut builder: TrieBuilder<&OsStr, &[u8]> = TrieBuilder::new();
+        let path = |p: &'static str| Path::new(p).iter().collect::<Vec<_>>();
+        let names = |fs: &Fs, p: &'static str| match fs.get_file_type_from_path(&path(p)) {
+            Some(FileType::Directory { entries, .. }) => entries
+                .into_iter()
+                .map(|entry| entry.last().unwrap().clone())
+                .collect::<Vec<_>>(),
+            _ => panic!("not a directory: {}", p),
+        };
+
+        builder.push(path("/usr/bin/ls"), &[1, 2, 3]);
+        builder.push(path("/usr/bin/cat"), &[4, 5, 6]);
+
+        let mut fs = Fs::new(builder);
+        let create = libc::O_WRONLY | libc::O_CREAT;
+
+        // read-only until the layer is enabled.
+        assert_eq!(
+            fs.open_with_flags(&path("/usr/bin/new"), create),
+            Err(libc::EROFS)
+        );
+        assert_eq!(
+            fs.open_with_flags(&path("/usr/bin/ls"), libc::O_RDWR),
+            Err(libc::EROFS)
+        );
+        assert_eq!(
+            fs.open_with_flags(&path("/usr/no/new"), create),
+            Err(libc::ENOENT)
+        );
+        assert_eq!(fs.mkdir(&path("/usr/bin")), Err(libc::EEXIST));
+        assert_eq!(fs.unlink(&path("/usr/bin/ls")), Err(libc::EROFS));
+
+        fs.enable_upper();
+
+        let fd = fs.open_with_flags(&path("/usr/bin/new"), create).unwrap();
+        assert_eq!(fs.write(fd, b"abc"), Ok(3));
+        let reader = fs.open(&path("/usr/bin/new")).unwrap();
+        assert_eq!(fs.write(reader, b"x"), Err(libc::EBADF));
+        let mut buf = [0; 3];
+        assert_eq!(fs.read(reader, &mut buf), Ok(3));
+        assert_eq!(&buf, b"abc");
+        assert!(fs.is_upper_file(&path("/usr/bin/new")));
+        assert!(!fs.is_upper_file(&path("/usr/bin/ls")));
+        assert!(!fs.is_upper_file(&path("/usr/bin")));
+
+        // embedded files are copied up; the archive itself is untouched.
+        let fd = fs
+            .open_with_flags(&path("/usr/bin/ls"), libc::O_WRONLY | libc::O_APPEND)
+            .unwrap();
+        assert_eq!(fs.write(fd, &[4]), Ok(1));
+        assert!(fs.is_upper_file(&path("/usr/bin/ls")));
+        assert_eq!(
+            fs.file_contents(&path("/usr/bin/ls")).unwrap().as_ref(),
+            &[1, 2, 3, 4]
+        );
+        assert_eq!(
+            fs.trie.exact_match(&path("/usr/bin/ls")).copied(),
+            Some(&[1, 2, 3][..])
+        );
+        assert_eq!(fs.truncate(&path("/usr/bin/ls"), 2), Ok(()));
+        assert_eq!(
+            fs.file_contents(&path("/usr/bin/ls")).unwrap().as_ref(),
+            &[1, 2]
+        );
+
+        // unlinked files are whited out of the listing, but stay readable through open fds.
+        let cat = fs.open(&path("/usr/bin/cat")).unwrap();
+        assert_eq!(fs.unlink(&path("/usr/bin/cat")), Ok(()));
+        assert_eq!(fs.unlink(&path("/usr/bin/new")), Ok(()));
+        assert_eq!(fs.is_dir_from_path(&path("/usr/bin/cat")), None);
+        assert_eq!(names(&fs, "/usr/bin"), vec![OsString::from("ls")]);
+        assert_eq!(fs.read(cat, &mut buf), Ok(3));
+        assert_eq!(fs.lseek(reader, 0, libc::SEEK_SET), Ok(0));
+        assert_eq!(fs.read(reader, &mut buf), Ok(3));
+
+        assert_eq!(fs.mkdir(&path("/usr/lib")), Ok(()));
+        assert_eq!(fs.mkdir(&path("/usr/lib/x/y")), Err(libc::ENOENT));
+        assert_eq!(
+            names(&fs, "/usr"),
+            vec![OsString::from("bin"), OsString::from("lib")]
+        );
+        assert_eq!(fs.rmdir(&path("/usr/bin")), Err(libc::ENOTEMPTY));
+
+        // renaming a directory moves the merged tree.
+        assert_eq!(fs.rename(&path("/usr/bin"), &path("/usr/lib/bin")), Ok(()));
+        assert_eq!(
+            fs.rename(&path("/usr/lib"), &path("/usr/lib/bin/lib")),
+            Err(libc::EINVAL)
+        );
+        assert_eq!(fs.is_dir_from_path(&path("/usr/bin")), None);
+        assert_eq!(names(&fs, "/usr/lib/bin"), vec![OsString::from("ls")]);
+        assert_eq!(
+            fs.file_contents(&path("/usr/lib/bin/ls")).unwrap().as_ref(),
+            &[1, 2]
+        );
+
+        // a recreated directory doesn't bring back what was removed.
+        assert_eq!(fs.mkdir(&path("/usr/bin")), Ok(()));
+        assert!(names(&fs, "/usr/bin").is_empty());
+        assert_eq!(fs.rmdir(&path("/usr/bin")), Ok(()));
+        assert_eq!(fs.is_dir_from_path(&path("/usr/bin")), None);
+    }
+
+    #[test]
+    fn test_overlay_index() {
+        let mut builder: TrieBuilder<&OsStr, &[u8]> = TrieBuilder::new();
+        let path = |p: &'static str| Path::new(p).iter().collect::<Vec<_>>();
+        let names = |fs: &Fs, p: &'static str| {
+            let mut names = fs
+                .dir_entries(&path(p))
+                .expect("not a directory")
+                .into_iter()
+                .map(|entry| entry.last().unwrap().clone())
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+
+        builder.push(path("/app/lib/a.rb"), &[1]);
+        builder.push(path("/app/lib/b.rb"), &[2]);
+        builder.push(path("/app/data/c.txt"), &[3]);
+
+        let mut fs = Fs::new(builder);
+        fs.enable_upper();
+
+        // a copied-up file, a new one, and a deleted one.
+        fs.record(&path("/app/lib"), LayerEntry::Directory { opaque: false })
+            .unwrap();
+        fs.record(&path("/app/lib/a.rb"), LayerEntry::File).unwrap();
+        fs.record(&path("/app/lib/new.rb"), LayerEntry::File)
+            .unwrap();
+        fs.record(&path("/app/lib/b.rb"), LayerEntry::Whiteout)
+            .unwrap();
+
+        assert!(fs.is_host_file(&path("/app/lib/a.rb")));
+        assert!(fs.is_host_file(&path("/app/lib/new.rb")));
+        assert!(!fs.is_host_file(&path("/app/data/c.txt")));
+        assert_eq!(names(&fs, "/app/lib"), ["a.rb", "new.rb"]);
+        assert_eq!(fs.is_dir_from_path(&path("/app/lib/b.rb")), None);
+        assert_eq!(fs.in_archive(&path("/app/lib/b.rb")), Some(false));
+
+        // a directory recreated after its embedded one was removed starts out empty.
+        fs.record(&path("/app/data"), LayerEntry::Directory { opaque: true })
+            .unwrap();
+        assert!(names(&fs, "/app/data").is_empty());
+        assert_eq!(fs.is_dir_from_path(&path("/app/data/c.txt")), None);
+
+        fs.forget(&path("/app/data"));
+        fs.forget(&path("/app/lib"));
+        assert_eq!(names(&fs, "/app/lib"), ["a.rb", "b.rb"]);
+        assert_eq!(names(&fs, "/app/data"), ["c.txt"]);
+        assert!(!fs.is_host_file(&path("/app/lib/a.rb")));
+    }
+
+    #[test]
+    fn test_record_needs_upper() {
+        let mut builder: TrieBuilder<&OsStr, &[u8]> = TrieBuilder::new();
+        builder.push(Path::new("/app/a.rb").iter().collect::<Vec<_>>(), &[1]);
+
+        let fs = Fs::new(builder);
+        let b = Path::new("/app/b.rb").iter().collect::<Vec<_>>();
+        assert_eq!(fs.record(&b, LayerEntry::File), Err(libc::EROFS));
+        assert_eq!(
+            fs.record_tree(&b, &[(b.clone(), LayerEntry::File)]),
+            Err(libc::EROFS)
+        );
+        assert!(!fs.is_host_file(&b));
+    }
+
+    #[test]
+    fn test_mounts() {
+        let mut builder: TrieBuilder<&OsStr, &[u8]> = TrieBuilder::new();
+        let path = |p: &'static str| Path::new(p).iter().collect::<Vec<_>>();
+        let names = |fs: &Fs, p: &'static str| {
+            fs.dir_entries(&path(p))
+                .expect("not a directory")
+                .into_iter()
+                .map(|entry| entry.iter().collect::<std::path::PathBuf>())
+                .collect::<Vec<_>>()
+        };
+
+        builder.push(path("/work/src/main.rb"), &[1]);
+        builder.push(path("/work/bundle/gems/rack/rack.rb"), &[2]);
+
+        let mut fs = Fs::new(builder);
+        fs.mount(&path("/work"), &path("/work"));
+        fs.mount(&path("/app"), &path("/work/src"));
+        fs.mount(&path("/gems"), &path("/work/bundle/gems"));
+
+        assert_eq!(fs.is_dir_from_path(&path("/app")), Some(true));
+        assert_eq!(fs.is_dir_from_path(&path("/app/main.rb")), Some(false));
+        assert_eq!(
+            fs.file_contents(&path("/gems/rack/rack.rb"))
+                .unwrap()
+                .as_ref(),
+            &[2]
+        );
+        // the archive stays reachable under its own paths, and nothing else is.
+        assert_eq!(fs.is_dir_from_path(&path("/work/src/main.rb")), Some(false));
+        assert_eq!(fs.is_dir_from_path(&path("/src/main.rb")), None);
+        assert_eq!(fs.is_dir_from_path(&path("/application")), None);
+
+        // listings are named after the mount, not the archive.
+        assert_eq!(names(&fs, "/gems"), [Path::new("/gems/rack")]);
+        let fd = fs.open(&path("/app/main.rb")).unwrap();
+        assert_eq!(
+            fs.path_from_fd(fd).unwrap(),
+            path("/app/main.rb")
+                .iter()
+                .map(|s| s.to_os_string())
+                .collect::<Vec<_>>()
+        );
+        fs.close(fd);
+        unsafe { libc::close(fd) };
+
+        // the longest prefix wins.
+        fs.mount(&path("/app/lib"), &path("/work/bundle"));
+        assert_eq!(fs.is_dir_from_path(&path("/app/lib/gems")), Some(true));
+        assert_eq!(fs.is_dir_from_path(&path("/app/main.rb")), Some(false));
+    }
+
+    #[test]
+    fn test_mount_aliases() {
+        let mut builder: TrieBuilder<&OsStr, &[u8]> = TrieBuilder::new();
+        let path = |p: &'static str| Path::new(p).iter().collect::<Vec<_>>();
+        let inode = |fs: &Fs, p: &'static str| {
+            let mut stat = unsafe { std::mem::zeroed::<libc::stat>() };
+            fs.stat(&path(p), &mut stat).expect("not found");
+            stat.st_ino
+        };
+
+        builder.push(path("/work/src/main.rb"), &[1]);
+        builder.push(path("/work/src/lib/util.rb"), &[2]);
+
+        let mut fs = Fs::new(builder);
+        fs.mount(&path("/work"), &path("/work"));
+        fs.mount(&path("/app"), &path("/work/src"));
+        fs.enable_upper();
+
+        assert_eq!(inode(&fs, "/app/main.rb"), inode(&fs, "/work/src/main.rb"));
+        assert_eq!(inode(&fs, "/app/lib"), inode(&fs, "/work/src/lib"));
+
+        // a write through one mount is seen through the other, on the same inode.
+        let fd = fs
+            .open_with_flags(&path("/app/main.rb"), libc::O_WRONLY | libc::O_TRUNC)
+            .unwrap();
+        assert_eq!(fs.write(fd, b"new"), Ok(3));
+        fs.close(fd);
+        unsafe { libc::close(fd) };
+        assert_eq!(
+            fs.file_contents(&path("/work/src/main.rb"))
+                .unwrap()
+                .as_ref(),
+            b"new"
+        );
+        assert_eq!(inode(&fs, "/app/main.rb"), inode(&fs, "/work/src/main.rb"));
+
+        // a file created through one shows up in the other's listing, named after it.
+        let fd = fs
+            .open_with_flags(
+                &path("/work/src/lib/new.rb"),
+                libc::O_WRONLY | libc::O_CREAT,
+            )
+            .unwrap();
+        fs.close(fd);
+        unsafe { libc::close(fd) };
+        let mut names = fs.dir_entries(&path("/app/lib")).unwrap();
+        names.sort();
+        assert_eq!(
+            names,
+            [path("/app/lib/new.rb"), path("/app/lib/util.rb")]
+                .map(|p| p.iter().map(|s| s.to_os_string()).collect::<Vec<_>>())
+        );
+
+        // unlinking through one hides it from both, and renaming onto the other alias is a no-op.
+        assert_eq!(fs.unlink(&path("/work/src/lib/util.rb")), Ok(()));
+        assert_eq!(fs.is_dir_from_path(&path("/app/lib/util.rb")), None);
+        assert_eq!(
+            fs.rename(&path("/app/main.rb"), &path("/work/src/main.rb")),
+            Ok(())
+        );
+        assert_eq!(fs.is_dir_from_path(&path("/app/main.rb")), Some(false));
+        assert_eq!(
+            fs.rename(&path("/app/lib"), &path("/work/src/lib/sub")),
+            Err(libc::EINVAL)
+        );
+    }
+
+    #[test]
+    fn test_path_routing() {
+        let cases: &[(&[u8], &[u8], bool)] = &[
+            (b"/app", b"/app", true),
+            (b"/app", b"/app/", true),
+            (b"/app", b"/app/lib/a.rb", true),
+            (b"/app", b"/application", false),
+            (b"/app", b"/application/lib/a.rb", false),
+            (b"/app", b"/ap", false),
+            (b"/app", b"/app/../etc/passwd", false),
+            (b"/app", b"/app/..", false),
+            (b"/app", b"/app/.", true),
+            (b"/app", b"//app//lib///a.rb", true),
+            (b"/app", b"/./app/./lib", true),
+            (b"/app", b"/etc/../app/lib", true),
+            (b"/app", b"/../../app/lib", true),
+            (b"/app", b"/app/lib/../../app/a.rb", true),
+            (b"/app", b"/app/lib/../../apps/a.rb", false),
+            (b"/app/", b"/app/lib", true),
+            (b"//app/./", b"/app/lib", true),
+            (b"/app/lib", b"/app", false),
+            (b"/app/lib", b"/app/lib2", false),
+            (b"/", b"/anything/at/all", true),
+            (b"/a\xffb", b"/a\xffb/c", true),
+        ];
+
+        // the same steps util::route takes: normalize, then look the path up in the mounts.
+        for (prefix, path, expected) in cases {
+            let prefix = Path::new(OsStr::from_bytes(prefix));
+            let path = Path::new(OsStr::from_bytes(path));
+            let mut mounts = MountTable::new();
+            mounts.add(prefix, Path::new("/work"));
+
+            assert_eq!(
+                mounts.contains(&normalize(Path::new("/"), path)),
+                *expected,
+                "{:?} under {:?}",
+                path,
+                prefix
+            );
+        }
+
+        let normalize_cases: &[(&str, &str, &str)] = &[
+            ("/app", "lib/a.rb", "/app/lib/a.rb"),
+            ("/app", "./lib//a.rb", "/app/lib/a.rb"),
+            ("/app/lib", "../../etc/passwd", "/etc/passwd"),
+            ("/app", "../../../..", "/"),
+            ("/app", "/etc/./hosts", "/etc/hosts"),
+            ("/app", "", "/app"),
+            ("/", "..", "/"),
+            ("/app/lib/", "a.rb/", "/app/lib/a.rb"),
+        ];
+
+        for (base, path, expected) in normalize_cases {
+            assert_eq!(
+                normalize(Path::new(base), Path::new(path)),
+                Path::new(expected),
+                "{:?} from {:?}",
+                path,
+                base
+            );
+        }
+    }
+
+    #[test]
+    fn test_non_utf8_paths() {
+        let path = |p: &'static [u8]| Path::new(OsStr::from_bytes(p)).iter().collect::<Vec<_>>();
+
+        let mut builder: TrieBuilder<&OsStr, &[u8]> = TrieBuilder::new();
+        builder.push(path(b"/app/caf\xe9.rb"), b"latin-1".as_slice());
+        builder.push(path(b"/app/\xff\xfe/\x80.so"), b"bytes".as_slice());
+        let fs = Fs::new(builder);
+
+        let fd = fs.open(&path(b"/app/caf\xe9.rb")).unwrap();
+        let mut buf = [0; 7];
+        assert_eq!(fs.read(fd, &mut buf), Ok(7));
+        assert_eq!(&buf, b"latin-1");
+        fs.close(fd);
+
+        let mut stat = unsafe { std::mem::zeroed::<libc::stat>() };
+        assert_eq!(fs.stat(&path(b"/app/\xff\xfe/\x80.so"), &mut stat), Some(0));
+        assert_eq!(stat.st_size, 5);
+        assert_eq!(fs.is_dir_from_path(&path(b"/app/\xff\xfe")), Some(true));
+        assert_eq!(fs.stat(&path(b"/app/cafe.rb"), &mut stat), None);
+
+        let mut dir = fs.opendir(&path(b"/app")).unwrap();
+        let mut names = vec![];
+        loop {
+            let dirent = fs.readdir(&mut dir).unwrap();
+            if dirent.is_null() {
+                break;
+            }
+            let dirent = unsafe { Box::from_raw(dirent) };
+            let name = unsafe { std::ffi::CStr::from_ptr(dirent.d_name.as_ptr()) };
+            names.push(name.to_bytes().to_vec());
+        }
+        fs.closedir(&dir);
+        names.sort();
+        assert_eq!(names, vec![b"caf\xe9.rb".to_vec(), b"\xff\xfe".to_vec()]);
+    }
+
+    // xorshift64 for the randomized tests, so a failure reproduces from its seed.
+    fn xorshift(mut seed: u64) -> impl FnMut() -> u64 {
+        move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        }
+    }
+
+    // arbitrary bytes, as a caller could pass them to open(2), never make a lookup panic.
+    #[test]
+    fn test_fuzz_byte_paths() {
+        let path = |p: &'static [u8]| Path::new(OsStr::from_bytes(p)).iter().collect::<Vec<_>>();
+
+        let mut builder: TrieBuilder<&OsStr, &[u8]> = TrieBuilder::new();
+        builder.push(path(b"/app/main.rb"), b"puts 1".as_slice());
+        builder.push(path(b"/app/\xe9/\xff"), b"x".as_slice());
+        let mut fs = Fs::new(builder);
+        fs.mount(&path(b"/app"), &path(b"/app"));
+        fs.mount(&path(b"/\xc3\x28"), &path(b"/app"));
+
+        let mut next = xorshift(0x9e37_79b9_7f4a_7c15);
+        let alphabet: &[u8] = b"/.a\xe9\xff\x80\xc3\x28";
+
+        for _ in 0..10_000 {
+            let len = next() % 24;
+            let raw = (0..len)
+                .map(|_| match next() % 4 {
+                    0 => alphabet[(next() % alphabet.len() as u64) as usize],
+                    // anything but NUL, which can't be part of a C path.
+                    _ => (next() % 255 + 1) as u8,
+                })
+                .collect::<Vec<_>>();
+
+            let normalized = normalize(Path::new("/app"), Path::new(OsStr::from_bytes(&raw)));
+            assert!(normalized.is_absolute(), "{:?}", raw);
+            let search_path = normalized.iter().collect::<Vec<_>>();
+
+            let mut stat = unsafe { std::mem::zeroed::<libc::stat>() };
+            let is_found = fs.stat(&search_path, &mut stat).is_some();
+            assert_eq!(fs.is_dir_from_path(&search_path).is_some(), is_found);
+
+            if let Some(fd) = fs.open(&search_path) {
+                assert!(is_found, "{:?}", raw);
+                fs.close(fd);
+            }
+            if let Ok(dir) = fs.opendir(&search_path) {
+                assert_eq!(stat.st_mode & libc::S_IFMT, libc::S_IFDIR, "{:?}", raw);
+                fs.closedir(&dir);
+            }
+        }
+
+        for (raw, expected) in [
+            (b"\xe9/\xff".as_slice(), true),
+            (b"/\xc3\x28/main.rb", true),
+            (b"./\xe9/../main.rb", true),
+            (b"\xe9/\xfe", false),
+        ] {
+            let normalized = normalize(Path::new("/app"), Path::new(OsStr::from_bytes(raw)));
+            let search_path = normalized.iter().collect::<Vec<_>>();
+            let mut stat = unsafe { std::mem::zeroed::<libc::stat>() };
+
+            assert_eq!(
+                fs.stat(&search_path, &mut stat).is_some(),
+                expected,
+                "{:?}",
+                raw
+            );
+        }
+    }
+
+    // resolve() against the kernel, on a host directory mirroring the archive.
+    #[test]
+    fn test_resolve_matches_kernel() {
+        let mirror = std::env::temp_dir().join(format!("kompo-mirror-{}", std::process::id()));
+        let files: &[&str] = &["f", "a/f", "a/b/f", "a/b/c/f"];
+
+        let _ = std::fs::remove_dir_all(&mirror);
+        // the archive's labels have to outlive it.
+        let paths = Vec::leak(files.iter().map(|file| mirror.join(file)).collect());
+        let mut builder: TrieBuilder<&OsStr, &[u8]> = TrieBuilder::new();
+        for path in paths.iter() {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, b"").unwrap();
+            builder.push(path.iter().collect::<Vec<_>>(), b"".as_slice());
+        }
+        let mut fs = Fs::new(builder);
+        let mirror_path = mirror.iter().collect::<Vec<_>>();
+        fs.mount(&mirror_path, &mirror_path);
+
+        // Ok(is_dir) or the errno, as stat(2) reports it.
+        let kernel = |path: &[u8]| {
+            let path = std::ffi::CString::new(path).unwrap();
+            let mut stat = unsafe { std::mem::zeroed::<libc::stat>() };
+            if unsafe { libc::stat(path.as_ptr(), &mut stat) } == 0 {
+                Ok(stat.st_mode & libc::S_IFMT == libc::S_IFDIR)
+            } else {
+                Err(std::io::Error::last_os_error().raw_os_error().unwrap())
+            }
+        };
+        let vfs = |path: &[u8]| {
+            let search_path = split(OsStr::from_bytes(path)).collect::<Vec<_>>();
+            let resolved = fs.resolve(&search_path)?;
+            let resolved = resolved.iter().map(|s| s.as_os_str()).collect::<Vec<_>>();
+
+            fs.is_dir_from_path(&resolved).ok_or(libc::ENOENT)
+        };
+
+        let mut next = xorshift(0x2545_f491_4f6c_dd1d);
+        let names: &[&[u8]] = &[b"a", b"b", b"c", b"f", b"x", b".", b"..", b""];
+
+        for _ in 0..5_000 {
+            let mut path = mirror.as_os_str().as_bytes().to_vec();
+            // `..` never climbs out of the mirror, where the host's own directories would be.
+            let mut depth = 0;
+            for _ in 0..(next() % 8 + 1) {
+                let name = names[(next() % names.len() as u64) as usize];
+                match name {
+                    b".." if depth == 0 => continue,
+                    b".." => depth -= 1,
+                    b"" | b"." => {}
+                    _ => depth += 1,
+                }
+                path.push(b'/');
+                path.extend_from_slice(name);
+            }
+            if next().is_multiple_of(4) {
+                path.push(b'/');
+            }
+
+            assert_eq!(vfs(&path), kernel(&path), "{:?}", OsStr::from_bytes(&path));
+        }
+
+        std::fs::remove_dir_all(&mirror).unwrap();
+    }
+
+    // threads moving between VFS and host directories, while others check that the kernel's
+    // cwd is the last host one entered whenever the VFS isn't in effect.
+    #[test]
+    fn test_working_dir_threads() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let cwd: &'static WorkingDir = Box::leak(Box::new(WorkingDir::new()));
+        cwd.register_atfork();
+
+        let root = std::env::temp_dir().join(format!("kompo-cwd-{}", std::process::id()));
+        let host_dirs: &'static [std::path::PathBuf] = Vec::leak(
+            (0..4)
+                .map(|i| root.join(i.to_string()))
+                .inspect(|dir| std::fs::create_dir_all(dir).unwrap())
+                .map(|dir| dir.canonicalize().unwrap())
+                .collect(),
+        );
+        let original = std::env::current_dir().unwrap();
+        let last_host = &*Box::leak(Box::new(AtomicUsize::new(0)));
+        assert_eq!(
+            cwd.change_on_host(|| {
+                last_host.store(0, Ordering::SeqCst);
+                std::env::set_current_dir(&host_dirs[0]).map_or(-1, |_| 0)
+            }),
+            0
+        );
+
+        let movers = (0..4)
+            .map(|i| {
+                std::thread::spawn(move || {
+                    for n in 0..2_000 {
+                        if (i + n) % 3 == 0 {
+                            cwd.enter(std::path::PathBuf::from(format!("/app/{}", n % 4)));
+                        } else {
+                            let dir = (i + n) % host_dirs.len();
+                            let ret = cwd.change_on_host(|| {
+                                last_host.store(dir, Ordering::SeqCst);
+                                std::env::set_current_dir(&host_dirs[dir]).map_or(-1, |_| 0)
+                            });
+                            assert_eq!(ret, 0);
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        let checkers = (0..4)
+            .map(|_| {
+                std::thread::spawn(move || {
+                    for _ in 0..2_000 {
+                        cwd.with(|dir| match dir {
+                            Some(dir) => assert!(dir.starts_with("/app")),
+                            None => assert_eq!(
+                                std::env::current_dir().unwrap(),
+                                host_dirs[last_host.load(Ordering::SeqCst)]
+                            ),
+                        });
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // a child forked in the middle of it all still gets at the directory.
+        for _ in 0..20 {
+            fork_and_check(|| {
+                let _ = cwd.get();
+                cwd.enter(std::path::PathBuf::from("/child"));
+
+                cwd.is_virtual()
+            });
+        }
+
+        for thread in movers.into_iter().chain(checkers) {
+            thread.join().unwrap();
+        }
+
+        cwd.change_on_host(|| std::env::set_current_dir(&original).map_or(-1, |_| 0));
+        assert_eq!(cwd.get(), None);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_shared_reads() {
+        let mut builder: TrieBuilder<&OsStr, &[u8]> = TrieBuilder::new();
+        let data: &'static [u8] = Box::leak((0..=255).collect::<Vec<u8>>().into_boxed_slice());
+        let path = vec![OsStr::new("data")];
+        builder.push(&path, data);
+
+        let fs = Fs::new(builder);
+        let fd = fs.open(&path).unwrap();
+
+        // readers sharing one fd each get their own bytes, as they would
+        // from the kernel's file offset.
+        let seen = std::thread::scope(|scope| {
+            let readers = (0..8)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut seen = Vec::new();
+                        let mut buf = [0; 1];
+                        while fs.read(fd, &mut buf) == Ok(1) {
+                            seen.push(buf[0]);
+                        }
+                        seen
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            readers
+                .into_iter()
+                .flat_map(|reader| reader.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+
+        let mut seen = seen;
+        seen.sort();
+        assert_eq!(seen, data);
+        assert_eq!(fs.lseek(fd, 0, libc::SEEK_CUR), Ok(256));
+
+        fs.close(fd);
+        unsafe { libc::close(fd) };
+    }
+
+    #[test]
+    fn test_error_paths() {
+        let mut builder: TrieBuilder<&OsStr, &[u8]> = TrieBuilder::new();
+        let path = |p: &'static str| Path::new(p).iter().collect::<Vec<_>>();
+
+        builder.push(path("/usr/bin/ls"), &[1, 2, 3]);
+        builder.push(path("/usr/bin/cat"), &[4, 5, 6]);
+
+        let mut fs = Fs::new(builder);
+        fs.enable_upper();
+        let mut buf = [0; 3];
+        let mut stat = unsafe { std::mem::zeroed::<libc::stat>() };
+
+        // a directory fd can't be read, but seeks like an empty file.
+        let dir_fd = fs.open(&path("/usr/bin")).unwrap();
+        assert_eq!(fs.read(dir_fd, &mut buf), Err(libc::EISDIR));
+        assert_eq!(fs.lseek(dir_fd, 0, libc::SEEK_SET), Ok(0));
+        assert_eq!(fs.lseek(dir_fd, -1, libc::SEEK_SET), Err(libc::EINVAL));
+        fs.close(dir_fd);
+        unsafe { libc::close(dir_fd) };
+
+        // a closed fd, or one another thread closed meanwhile.
+        let fd = fs.open(&path("/usr/bin/ls")).unwrap();
+        assert_eq!(fs.lseek(fd, 0, 42), Err(libc::EINVAL));
+        fs.close(fd);
+        unsafe { libc::close(fd) };
+        assert_eq!(fs.read(fd, &mut buf), Err(libc::EBADF));
+        assert_eq!(fs.lseek(fd, 0, libc::SEEK_SET), Err(libc::EBADF));
+        assert_eq!(fs.fstat(fd, &mut stat), None);
+        assert_eq!(fs.fdopendir(fd).err(), Some(libc::EBADF));
+        assert_eq!(fs.readdir(&mut FsDir { fd, offset: 0 }), None);
+
+        assert_eq!(fs.file_read(&path("/usr/bin/missing")), None);
+        assert_eq!(fs.file_read(&path("/usr/bin")), None);
+        assert_eq!(fs.opendir(&path("/usr/bin/ls")).err(), Some(libc::ENOTDIR));
+        assert_eq!(
+            fs.opendir(&path("/usr/bin/missing")).err(),
+            Some(libc::ENOENT)
+        );
+        let fd = fs.open(&path("/usr/bin/ls")).unwrap();
+        assert_eq!(fs.fdopendir(fd).err(), Some(libc::ENOTDIR));
+        fs.close(fd);
+        unsafe { libc::close(fd) };
+
+        // entries unlinked after opendir() are skipped rather than returned half-resolved.
+        let mut dir = fs.opendir(&path("/usr/bin")).unwrap();
+        assert_eq!(fs.unlink(&path("/usr/bin/cat")), Ok(()));
+        assert_eq!(fs.unlink(&path("/usr/bin/ls")), Ok(()));
+        assert_eq!(fs.readdir(&mut dir), Some(std::ptr::null_mut()));
+        fs.closedir(&dir);
+        unsafe { libc::close(dir.fd) };
+    }
 }
@@ -0,0 +1,63 @@
+use std::ffi::{OsStr, OsString};
+use std::path::Path;
+
+use crate::path::normalize;
+
+// absolute path prefixes served from subtrees of the archive. prefixes are normalized and
+// matched component by component, so `/app` covers neither `/application` nor, once the
+// caller has normalized it, `/app/../etc`. the longest matching prefix wins.
+#[derive(Debug, Default, Clone)]
+pub struct MountTable {
+    mounts: Vec<(Vec<OsString>, Vec<OsString>)>,
+}
+
+fn components(path: &Path) -> Vec<OsString> {
+    normalize(Path::new("/"), path)
+        .iter()
+        .map(|s| s.to_os_string())
+        .collect()
+}
+
+impl MountTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // serves the archive's `target` subtree at `prefix`, replacing any earlier mount there.
+    pub fn add(&mut self, prefix: &Path, target: &Path) {
+        let prefix = components(prefix);
+
+        self.mounts.retain(|(mounted, _)| *mounted != prefix);
+        self.mounts.push((prefix, components(target)));
+        self.mounts
+            .sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mounts.is_empty()
+    }
+
+    fn find(&self, path: &[&OsStr]) -> Option<&(Vec<OsString>, Vec<OsString>)> {
+        self.mounts.iter().find(|(prefix, _)| {
+            prefix.len() <= path.len() && prefix.iter().zip(path.iter()).all(|(a, b)| a == b)
+        })
+    }
+
+    // whether the already normalized `path` is under one of the mounts.
+    pub fn contains(&self, path: &Path) -> bool {
+        self.find(&path.iter().collect::<Vec<_>>()).is_some()
+    }
+
+    // where `path` lives in the archive, after the longest matching mount.
+    pub fn archive_path<'p>(&'p self, path: &[&'p OsStr]) -> Option<Vec<&'p OsStr>> {
+        let (prefix, target) = self.find(path)?;
+
+        Some(
+            target
+                .iter()
+                .map(|s| s.as_os_str())
+                .chain(path[prefix.len()..].iter().copied())
+                .collect(),
+        )
+    }
+}
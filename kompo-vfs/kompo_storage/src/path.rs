@@ -0,0 +1,34 @@
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path, PathBuf};
+
+// resolves `path` against the absolute `base` without touching any file system: empty and
+// `.` components are dropped, and `..` removes the one before it, stopping at the root like
+// the kernel does. an absolute `path` ignores `base`.
+pub fn normalize(base: &Path, path: &Path) -> PathBuf {
+    let base = (!path.has_root()).then_some(base);
+
+    let mut normalized = PathBuf::from("/");
+    for comp in base
+        .into_iter()
+        .flat_map(|base| base.components())
+        .chain(path.components())
+    {
+        match comp {
+            Component::Normal(comp) => normalized.push(comp),
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::RootDir | Component::CurDir | Component::Prefix(_) => {}
+        }
+    }
+
+    normalized
+}
+
+// the components of `path` for `Fs::resolve`. unlike `Path::components`, the empty and `.`
+// ones a trailing or inner slash makes are kept, since the kernel checks that whatever
+// precedes them is a directory.
+pub fn split(path: &OsStr) -> impl Iterator<Item = &OsStr> {
+    path.as_bytes().split(|b| *b == b'/').map(OsStr::from_bytes)
+}
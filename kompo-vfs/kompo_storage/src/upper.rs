@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+
+// a path in the writable layer. anything not listed here falls through to the archive.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Entry {
+    File(u64),
+    // a file kept on the host, in an on-disk overlay directory.
+    HostFile,
+    // an opaque directory replaced an embedded one, so nothing below it falls through.
+    Directory { opaque: bool },
+    // the embedded file or directory at this path was removed.
+    Whiteout,
+}
+
+#[derive(Debug)]
+pub(crate) struct Inode {
+    pub data: Vec<u8>,
+    links: usize,
+    opens: usize,
+}
+
+// the in-memory writable layer over the archive. files live in inodes rather than in the
+// entries, so an fd keeps working after its file is unlinked or renamed, as with tmpfs.
+#[derive(Debug, Default)]
+pub(crate) struct Upper {
+    entries: HashMap<Vec<OsString>, Entry>,
+    pub inodes: HashMap<u64, Inode>,
+    next_inode: u64,
+}
+
+// paths here are archive paths, so every mount that reaches a file sees the same entry.
+fn key<S: AsRef<OsStr>>(path: &[S]) -> Vec<OsString> {
+    path.iter().map(|s| s.as_ref().to_os_string()).collect()
+}
+
+fn is_below<S: AsRef<OsStr>>(entry_path: &[OsString], path: &[S]) -> bool {
+    entry_path
+        .iter()
+        .zip(path.iter())
+        .all(|(a, b)| a == b.as_ref())
+}
+
+impl Upper {
+    pub fn entry<S: AsRef<OsStr>>(&self, path: &[S]) -> Option<&Entry> {
+        self.entries.get(&key(path))
+    }
+
+    // whether the archive still shows through at `path`: no whiteout on the way down, and no
+    // opaque directory or file above it.
+    pub fn is_lower_visible<S: AsRef<OsStr>>(&self, path: &[S]) -> bool {
+        (1..=path.len()).all(|len| match self.entry(&path[..len]) {
+            Some(Entry::Whiteout) => false,
+            Some(Entry::Directory { opaque: true } | Entry::File(_) | Entry::HostFile) => {
+                len == path.len()
+            }
+            _ => true,
+        })
+    }
+
+    // the direct children of `path` in this layer, whiteouts included.
+    pub fn children<'b, S: AsRef<OsStr>>(
+        &'b self,
+        path: &'b [S],
+    ) -> impl Iterator<Item = (&'b Vec<OsString>, &'b Entry)> + 'b {
+        self.entries.iter().filter(move |(entry_path, _)| {
+            entry_path.len() == path.len() + 1 && is_below(entry_path, path)
+        })
+    }
+
+    pub fn create_file<S: AsRef<OsStr>>(&mut self, path: &[S], data: Vec<u8>) -> u64 {
+        let id = self.next_inode;
+        self.next_inode += 1;
+
+        self.inodes.insert(
+            id,
+            Inode {
+                data,
+                links: 0,
+                opens: 0,
+            },
+        );
+        self.link(path, id);
+
+        id
+    }
+
+    pub fn link<S: AsRef<OsStr>>(&mut self, path: &[S], id: u64) {
+        self.inodes.get_mut(&id).expect("not found inode").links += 1;
+        self.insert(path, Entry::File(id));
+    }
+
+    pub fn mkdir<S: AsRef<OsStr>>(&mut self, path: &[S], opaque: bool) {
+        self.insert(path, Entry::Directory { opaque });
+    }
+
+    pub fn host_file<S: AsRef<OsStr>>(&mut self, path: &[S]) {
+        self.insert(path, Entry::HostFile);
+    }
+
+    pub fn whiteout<S: AsRef<OsStr>>(&mut self, path: &[S]) {
+        self.insert(path, Entry::Whiteout);
+    }
+
+    fn insert<S: AsRef<OsStr>>(&mut self, path: &[S], entry: Entry) {
+        self.remove(path);
+        self.entries.insert(key(path), entry);
+    }
+
+    pub fn remove<S: AsRef<OsStr>>(&mut self, path: &[S]) {
+        if let Some(Entry::File(id)) = self.entries.remove(&key(path)) {
+            let inode = self.inodes.get_mut(&id).expect("not found inode");
+            inode.links -= 1;
+            self.collect(id);
+        }
+    }
+
+    // drops `path` and everything recorded below it.
+    pub fn remove_tree<S: AsRef<OsStr>>(&mut self, path: &[S]) {
+        let below = self
+            .entries
+            .keys()
+            .filter(|entry_path| entry_path.len() > path.len() && is_below(entry_path, path))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for entry_path in below {
+            self.remove(&entry_path);
+        }
+        self.remove(path);
+    }
+
+    pub fn open(&mut self, id: u64) {
+        self.inodes.get_mut(&id).expect("not found inode").opens += 1;
+    }
+
+    pub fn release(&mut self, id: u64) {
+        if let Some(inode) = self.inodes.get_mut(&id) {
+            inode.opens -= 1;
+            self.collect(id);
+        }
+    }
+
+    fn collect(&mut self, id: u64) {
+        if self
+            .inodes
+            .get(&id)
+            .is_some_and(|inode| inode.links == 0 && inode.opens == 0)
+        {
+            self.inodes.remove(&id);
+        }
+    }
+}
@@ -220,6 +220,71 @@ unsafe extern "C-unwind" fn read(
     read_from_fs(fd, buf, count)
 }
 
+//write
+pub static WRITE_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(
+        fd: libc::c_int,
+        buf: *const libc::c_void,
+        count: libc::size_t,
+    ) -> libc::ssize_t,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"write\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(
+            fd: libc::c_int,
+            buf: *const libc::c_void,
+            count: libc::size_t,
+        ) -> libc::ssize_t,
+    >(handle)
+});
+
+extern "C" {
+    fn write_from_fs(
+        fd: libc::c_int,
+        buf: *const libc::c_void,
+        count: libc::size_t,
+    ) -> libc::ssize_t;
+}
+
+// only a writable layer has VFS fds worth writing to, so kompo_fs turns the hook on when it
+// enables one. until then write() goes straight to libc, as it's called for every fd in the
+// process, from signal handlers too.
+pub static WRITE_HOOKED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+#[no_mangle]
+unsafe extern "C-unwind" fn write(
+    fd: libc::c_int,
+    buf: *const libc::c_void,
+    count: libc::size_t,
+) -> libc::ssize_t {
+    if WRITE_HOOKED.load(std::sync::atomic::Ordering::Acquire) {
+        write_from_fs(fd, buf, count)
+    } else {
+        WRITE_HANDLE(fd, buf, count)
+    }
+}
+
+//ftruncate
+pub static FTRUNCATE_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(fd: libc::c_int, length: libc::off_t) -> libc::c_int,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"ftruncate\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(fd: libc::c_int, length: libc::off_t) -> libc::c_int,
+    >(handle)
+});
+
+extern "C" {
+    fn ftruncate_from_fs(fd: libc::c_int, length: libc::off_t) -> libc::c_int;
+}
+
+#[no_mangle]
+unsafe extern "C-unwind" fn ftruncate(fd: libc::c_int, length: libc::off_t) -> libc::c_int {
+    ftruncate_from_fs(fd, length)
+}
+
 // readv
 // pub static READV_HANDLE: std::sync::LazyLock<
 //     unsafe extern "C-unwind" fn(libc::c_int, *const libc::iovec, libc::c_int) -> libc::ssize_t,
@@ -276,24 +341,120 @@ unsafe extern "C-unwind" fn read(
 // }
 
 //lseek
-// pub static LSEEK_HANDLE: std::sync::LazyLock<
-//     unsafe extern "C-unwind" fn(libc::c_int, libc::off_t, libc::c_int) -> libc::off_t,
-// > = std::sync::LazyLock::new(|| unsafe {
-//     let handle = libc::dlsym(libc::RTLD_NEXT, b"lseek\0".as_ptr() as _);
-//     std::mem::transmute::<
-//         *mut libc::c_void,
-//         unsafe extern "C-unwind" fn(libc::c_int, libc::off_t, libc::c_int) -> libc::off_t,
-//     >(handle)
-// });
+pub static LSEEK_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(libc::c_int, libc::off_t, libc::c_int) -> libc::off_t,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"lseek\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(libc::c_int, libc::off_t, libc::c_int) -> libc::off_t,
+    >(handle)
+});
 
-// #[no_mangle]
-// unsafe extern "C-unwind" fn lseek(
-//     fildes: libc::c_int,
-//     offset: libc::off_t,
-//     whence: libc::c_int,
-// ) -> libc::off_t {
-//     LSEEK_HANDLE(fildes, offset, whence)
-// }
+extern "C" {
+    fn lseek_from_fs(fildes: libc::c_int, offset: libc::off_t, whence: libc::c_int) -> libc::off_t;
+}
+
+#[no_mangle]
+unsafe extern "C-unwind" fn lseek(
+    fildes: libc::c_int,
+    offset: libc::off_t,
+    whence: libc::c_int,
+) -> libc::off_t {
+    lseek_from_fs(fildes, offset, whence)
+}
+
+//dup
+pub static DUP_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(libc::c_int) -> libc::c_int,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"dup\0".as_ptr() as _);
+    std::mem::transmute::<*mut libc::c_void, unsafe extern "C-unwind" fn(libc::c_int) -> libc::c_int>(
+        handle,
+    )
+});
+
+extern "C" {
+    fn dup_from_fs(fd: libc::c_int) -> libc::c_int;
+}
+
+#[no_mangle]
+unsafe extern "C-unwind" fn dup(fd: libc::c_int) -> libc::c_int {
+    dup_from_fs(fd)
+}
+
+//dup2
+pub static DUP2_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(libc::c_int, libc::c_int) -> libc::c_int,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"dup2\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(libc::c_int, libc::c_int) -> libc::c_int,
+    >(handle)
+});
+
+extern "C" {
+    fn dup2_from_fs(fd: libc::c_int, new_fd: libc::c_int) -> libc::c_int;
+}
+
+#[no_mangle]
+unsafe extern "C-unwind" fn dup2(fd: libc::c_int, new_fd: libc::c_int) -> libc::c_int {
+    dup2_from_fs(fd, new_fd)
+}
+
+//dup3
+#[cfg(target_os = "linux")]
+pub static DUP3_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(libc::c_int, libc::c_int, libc::c_int) -> libc::c_int,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"dup3\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(libc::c_int, libc::c_int, libc::c_int) -> libc::c_int,
+    >(handle)
+});
+
+#[cfg(target_os = "linux")]
+extern "C" {
+    fn dup3_from_fs(fd: libc::c_int, new_fd: libc::c_int, flags: libc::c_int) -> libc::c_int;
+}
+
+#[cfg(target_os = "linux")]
+#[no_mangle]
+unsafe extern "C-unwind" fn dup3(
+    fd: libc::c_int,
+    new_fd: libc::c_int,
+    flags: libc::c_int,
+) -> libc::c_int {
+    dup3_from_fs(fd, new_fd, flags)
+}
+
+//fcntl
+pub static FCNTL_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(libc::c_int, libc::c_int, libc::c_long) -> libc::c_int,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"fcntl\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(libc::c_int, libc::c_int, libc::c_long) -> libc::c_int,
+    >(handle)
+});
+
+extern "C" {
+    fn fcntl_from_fs(fd: libc::c_int, cmd: libc::c_int, arg: libc::c_long) -> libc::c_int;
+}
+
+// fcntl() is variadic; like open()'s mode, the optional argument is taken as one register-sized
+// value, which covers both the int and the pointer forms.
+#[no_mangle]
+unsafe extern "C-unwind" fn fcntl(
+    fd: libc::c_int,
+    cmd: libc::c_int,
+    arg: libc::c_long,
+) -> libc::c_int {
+    fcntl_from_fs(fd, cmd, arg)
+}
 
 //stat
 pub static STAT_HANDLE: std::sync::LazyLock<
@@ -414,6 +575,132 @@ unsafe extern "C-unwind" fn close(d: libc::c_int) -> libc::c_int {
     close_from_fs(d)
 }
 
+//fopen
+#[cfg(target_os = "linux")]
+pub static FOPEN_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(
+        path: *const libc::c_char,
+        mode: *const libc::c_char,
+    ) -> *mut libc::FILE,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"fopen\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(
+            path: *const libc::c_char,
+            mode: *const libc::c_char,
+        ) -> *mut libc::FILE,
+    >(handle)
+});
+
+#[cfg(target_os = "linux")]
+extern "C" {
+    fn fopen_from_fs(path: *const libc::c_char, mode: *const libc::c_char) -> *mut libc::FILE;
+}
+
+#[cfg(target_os = "linux")]
+#[no_mangle]
+unsafe extern "C-unwind" fn fopen(
+    path: *const libc::c_char,
+    mode: *const libc::c_char,
+) -> *mut libc::FILE {
+    fopen_from_fs(path, mode)
+}
+
+//fopen64
+#[cfg(target_os = "linux")]
+pub static FOPEN64_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(
+        path: *const libc::c_char,
+        mode: *const libc::c_char,
+    ) -> *mut libc::FILE,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"fopen64\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(
+            path: *const libc::c_char,
+            mode: *const libc::c_char,
+        ) -> *mut libc::FILE,
+    >(handle)
+});
+
+#[cfg(target_os = "linux")]
+extern "C" {
+    fn fopen64_from_fs(path: *const libc::c_char, mode: *const libc::c_char) -> *mut libc::FILE;
+}
+
+#[cfg(target_os = "linux")]
+#[no_mangle]
+unsafe extern "C-unwind" fn fopen64(
+    path: *const libc::c_char,
+    mode: *const libc::c_char,
+) -> *mut libc::FILE {
+    fopen64_from_fs(path, mode)
+}
+
+//freopen
+#[cfg(target_os = "linux")]
+pub static FREOPEN_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(
+        path: *const libc::c_char,
+        mode: *const libc::c_char,
+        stream: *mut libc::FILE,
+    ) -> *mut libc::FILE,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"freopen\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(
+            path: *const libc::c_char,
+            mode: *const libc::c_char,
+            stream: *mut libc::FILE,
+        ) -> *mut libc::FILE,
+    >(handle)
+});
+
+#[cfg(target_os = "linux")]
+extern "C" {
+    fn freopen_from_fs(
+        path: *const libc::c_char,
+        mode: *const libc::c_char,
+        stream: *mut libc::FILE,
+    ) -> *mut libc::FILE;
+}
+
+#[cfg(target_os = "linux")]
+#[no_mangle]
+unsafe extern "C-unwind" fn freopen(
+    path: *const libc::c_char,
+    mode: *const libc::c_char,
+    stream: *mut libc::FILE,
+) -> *mut libc::FILE {
+    freopen_from_fs(path, mode, stream)
+}
+
+//fdopen
+#[cfg(target_os = "linux")]
+pub static FDOPEN_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(fd: libc::c_int, mode: *const libc::c_char) -> *mut libc::FILE,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"fdopen\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(fd: libc::c_int, mode: *const libc::c_char) -> *mut libc::FILE,
+    >(handle)
+});
+
+#[cfg(target_os = "linux")]
+extern "C" {
+    fn fdopen_from_fs(fd: libc::c_int, mode: *const libc::c_char) -> *mut libc::FILE;
+}
+
+#[cfg(target_os = "linux")]
+#[no_mangle]
+unsafe extern "C-unwind" fn fdopen(fd: libc::c_int, mode: *const libc::c_char) -> *mut libc::FILE {
+    fdopen_from_fs(fd, mode)
+}
+
 //getcwd
 pub static GETCWD_HANDLE: std::sync::LazyLock<
     unsafe extern "C-unwind" fn(
@@ -445,6 +732,28 @@ unsafe extern "C-unwind" fn getcwd(
     getcwd_from_fs(buf, length)
 }
 
+//get_current_dir_name
+#[cfg(target_os = "linux")]
+pub static GET_CURRENT_DIR_NAME_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn() -> *mut libc::c_char,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"get_current_dir_name\0".as_ptr() as _);
+    std::mem::transmute::<*mut libc::c_void, unsafe extern "C-unwind" fn() -> *mut libc::c_char>(
+        handle,
+    )
+});
+
+#[cfg(target_os = "linux")]
+extern "C" {
+    fn get_current_dir_name_from_fs() -> *mut libc::c_char;
+}
+
+#[cfg(target_os = "linux")]
+#[no_mangle]
+unsafe extern "C-unwind" fn get_current_dir_name() -> *mut libc::c_char {
+    get_current_dir_name_from_fs()
+}
+
 //getwd
 // pub static GETWD_HANDLE: std::sync::LazyLock<
 //     unsafe extern "C-unwind" fn(path_name: *const libc::c_char) -> *const libc::c_char,
@@ -463,208 +772,1129 @@ unsafe extern "C-unwind" fn getcwd(
 //     GETWD_HANDLE(path_name)
 // }
 
-//execv
-// pub static EXECV_HANDLE: std::sync::LazyLock<
-//     unsafe extern "C-unwind" fn(
-//         prog: *const libc::c_char,
-//         argv: *const *const libc::c_char,
-//     ) -> libc::c_int,
-// > = std::sync::LazyLock::new(|| unsafe {
-//     let handle = libc::dlsym(libc::RTLD_NEXT, b"execv\0".as_ptr() as _);
-//     std::mem::transmute::<
-//         *mut libc::c_void,
-//         unsafe extern "C-unwind" fn(
-//             prog: *const libc::c_char,
-//             argv: *const *const libc::c_char,
-//         ) -> libc::c_int,
-//     >(handle)
-// });
-
-// #[no_mangle]
-// unsafe extern "C-unwind" fn execv(
-//     prog: *const libc::c_char,
-//     argv: *const *const libc::c_char,
-// ) -> libc::c_int {
-//     println!("rust execv");
-
-//     EXECV_HANDLE(prog, argv)
-// }
-
-//access
-// pub static ACCSESS_HANDLE: std::sync::LazyLock<
-//     unsafe extern "C-unwind" fn(path: *const libc::c_char, amode: libc::c_int) -> libc::c_int,
-// > = std::sync::LazyLock::new(|| unsafe {
-//     let handle = libc::dlsym(libc::RTLD_NEXT, b"access\0".as_ptr() as _);
-//     std::mem::transmute::<
-//         *mut libc::c_void,
-//         unsafe extern "C-unwind" fn(path: *const libc::c_char, amode: libc::c_int) -> libc::c_int,
-//     >(handle)
-// });
-
-// #[no_mangle]
-// unsafe extern "C-unwind" fn access(path: *const libc::c_char, amode: libc::c_int) -> libc::c_int {
-//     println!("rust access");
-
-//     ACCSESS_HANDLE(path, amode)
-// }
-
-//opendir
-pub static OPENDIR_HANDLE: std::sync::LazyLock<
-    unsafe extern "C-unwind" fn(dirname: *const libc::c_char) -> *mut libc::DIR,
+//execve
+#[cfg(target_os = "linux")]
+pub static EXECVE_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(
+        path: *const libc::c_char,
+        argv: *const *const libc::c_char,
+        envp: *const *const libc::c_char,
+    ) -> libc::c_int,
 > = std::sync::LazyLock::new(|| unsafe {
-    let handle = libc::dlsym(libc::RTLD_NEXT, b"opendir\0".as_ptr() as _);
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"execve\0".as_ptr() as _);
     std::mem::transmute::<
         *mut libc::c_void,
-        unsafe extern "C-unwind" fn(dirname: *const libc::c_char) -> *mut libc::DIR,
+        unsafe extern "C-unwind" fn(
+            path: *const libc::c_char,
+            argv: *const *const libc::c_char,
+            envp: *const *const libc::c_char,
+        ) -> libc::c_int,
     >(handle)
 });
 
+#[cfg(target_os = "linux")]
 extern "C" {
-    fn opendir_from_fs(dirname: *const libc::c_char) -> *mut libc::DIR;
+    fn execve_from_fs(
+        path: *const libc::c_char,
+        argv: *const *const libc::c_char,
+        envp: *const *const libc::c_char,
+    ) -> libc::c_int;
 }
 
+#[cfg(target_os = "linux")]
 #[no_mangle]
-unsafe extern "C-unwind" fn opendir(dirname: *const libc::c_char) -> *mut libc::DIR {
-    opendir_from_fs(dirname)
+unsafe extern "C-unwind" fn execve(
+    path: *const libc::c_char,
+    argv: *const *const libc::c_char,
+    envp: *const *const libc::c_char,
+) -> libc::c_int {
+    execve_from_fs(path, argv, envp)
 }
 
-//fdopendir
-pub static FDOPENDIR_HANDLE: std::sync::LazyLock<
-    unsafe extern "C-unwind" fn(fd: libc::c_int) -> *mut libc::DIR,
+//execv
+#[cfg(target_os = "linux")]
+pub static EXECV_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(
+        path: *const libc::c_char,
+        argv: *const *const libc::c_char,
+    ) -> libc::c_int,
 > = std::sync::LazyLock::new(|| unsafe {
-    let handle = libc::dlsym(libc::RTLD_NEXT, b"fdopendir\0".as_ptr() as _);
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"execv\0".as_ptr() as _);
     std::mem::transmute::<
         *mut libc::c_void,
-        unsafe extern "C-unwind" fn(fd: libc::c_int) -> *mut libc::DIR,
+        unsafe extern "C-unwind" fn(
+            path: *const libc::c_char,
+            argv: *const *const libc::c_char,
+        ) -> libc::c_int,
     >(handle)
 });
 
+#[cfg(target_os = "linux")]
 extern "C" {
-    fn fdopendir_from_fs(fd: libc::c_int) -> *mut libc::DIR;
+    fn execv_from_fs(path: *const libc::c_char, argv: *const *const libc::c_char) -> libc::c_int;
 }
 
+#[cfg(target_os = "linux")]
 #[no_mangle]
-unsafe extern "C-unwind" fn fdopendir(fd: libc::c_int) -> *mut libc::DIR {
-    // println!("rust fdopendir: {:?}", fd);
-
-    fdopendir_from_fs(fd)
+unsafe extern "C-unwind" fn execv(
+    path: *const libc::c_char,
+    argv: *const *const libc::c_char,
+) -> libc::c_int {
+    execv_from_fs(path, argv)
 }
 
-//readdir
-pub static READDIR_HANDLE: std::sync::LazyLock<
+//execvp
+#[cfg(target_os = "linux")]
+pub static EXECVP_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(
+        file: *const libc::c_char,
+        argv: *const *const libc::c_char,
+    ) -> libc::c_int,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"execvp\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(
+            file: *const libc::c_char,
+            argv: *const *const libc::c_char,
+        ) -> libc::c_int,
+    >(handle)
+});
+
+#[cfg(target_os = "linux")]
+extern "C" {
+    fn execvp_from_fs(file: *const libc::c_char, argv: *const *const libc::c_char) -> libc::c_int;
+}
+
+#[cfg(target_os = "linux")]
+#[no_mangle]
+unsafe extern "C-unwind" fn execvp(
+    file: *const libc::c_char,
+    argv: *const *const libc::c_char,
+) -> libc::c_int {
+    execvp_from_fs(file, argv)
+}
+
+//execvpe
+#[cfg(target_os = "linux")]
+pub static EXECVPE_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(
+        file: *const libc::c_char,
+        argv: *const *const libc::c_char,
+        envp: *const *const libc::c_char,
+    ) -> libc::c_int,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"execvpe\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(
+            file: *const libc::c_char,
+            argv: *const *const libc::c_char,
+            envp: *const *const libc::c_char,
+        ) -> libc::c_int,
+    >(handle)
+});
+
+#[cfg(target_os = "linux")]
+extern "C" {
+    fn execvpe_from_fs(
+        file: *const libc::c_char,
+        argv: *const *const libc::c_char,
+        envp: *const *const libc::c_char,
+    ) -> libc::c_int;
+}
+
+#[cfg(target_os = "linux")]
+#[no_mangle]
+unsafe extern "C-unwind" fn execvpe(
+    file: *const libc::c_char,
+    argv: *const *const libc::c_char,
+    envp: *const *const libc::c_char,
+) -> libc::c_int {
+    execvpe_from_fs(file, argv, envp)
+}
+
+//fexecve
+#[cfg(target_os = "linux")]
+pub static FEXECVE_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(
+        fd: libc::c_int,
+        argv: *const *const libc::c_char,
+        envp: *const *const libc::c_char,
+    ) -> libc::c_int,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"fexecve\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(
+            fd: libc::c_int,
+            argv: *const *const libc::c_char,
+            envp: *const *const libc::c_char,
+        ) -> libc::c_int,
+    >(handle)
+});
+
+#[cfg(target_os = "linux")]
+extern "C" {
+    fn fexecve_from_fs(
+        fd: libc::c_int,
+        argv: *const *const libc::c_char,
+        envp: *const *const libc::c_char,
+    ) -> libc::c_int;
+}
+
+#[cfg(target_os = "linux")]
+#[no_mangle]
+unsafe extern "C-unwind" fn fexecve(
+    fd: libc::c_int,
+    argv: *const *const libc::c_char,
+    envp: *const *const libc::c_char,
+) -> libc::c_int {
+    fexecve_from_fs(fd, argv, envp)
+}
+
+//vfork
+#[cfg(target_os = "linux")]
+extern "C" {
+    fn vfork_from_fs() -> libc::pid_t;
+}
+
+// a vfork() child that execs goes through our exec hooks, so this hands out a fork() instead:
+// see vfork_from_fs.
+#[cfg(target_os = "linux")]
+#[no_mangle]
+unsafe extern "C-unwind" fn vfork() -> libc::pid_t {
+    vfork_from_fs()
+}
+
+//posix_spawn
+#[cfg(target_os = "linux")]
+pub static POSIX_SPAWN_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(
+        pid: *mut libc::pid_t,
+        path: *const libc::c_char,
+        file_actions: *const libc::posix_spawn_file_actions_t,
+        attrp: *const libc::posix_spawnattr_t,
+        argv: *const *mut libc::c_char,
+        envp: *const *mut libc::c_char,
+    ) -> libc::c_int,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"posix_spawn\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(
+            pid: *mut libc::pid_t,
+            path: *const libc::c_char,
+            file_actions: *const libc::posix_spawn_file_actions_t,
+            attrp: *const libc::posix_spawnattr_t,
+            argv: *const *mut libc::c_char,
+            envp: *const *mut libc::c_char,
+        ) -> libc::c_int,
+    >(handle)
+});
+
+#[cfg(target_os = "linux")]
+extern "C" {
+    fn posix_spawn_from_fs(
+        pid: *mut libc::pid_t,
+        path: *const libc::c_char,
+        file_actions: *const libc::posix_spawn_file_actions_t,
+        attrp: *const libc::posix_spawnattr_t,
+        argv: *const *mut libc::c_char,
+        envp: *const *mut libc::c_char,
+    ) -> libc::c_int;
+}
+
+#[cfg(target_os = "linux")]
+#[no_mangle]
+unsafe extern "C-unwind" fn posix_spawn(
+    pid: *mut libc::pid_t,
+    path: *const libc::c_char,
+    file_actions: *const libc::posix_spawn_file_actions_t,
+    attrp: *const libc::posix_spawnattr_t,
+    argv: *const *mut libc::c_char,
+    envp: *const *mut libc::c_char,
+) -> libc::c_int {
+    posix_spawn_from_fs(pid, path, file_actions, attrp, argv, envp)
+}
+
+//posix_spawnp
+#[cfg(target_os = "linux")]
+pub static POSIX_SPAWNP_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(
+        pid: *mut libc::pid_t,
+        file: *const libc::c_char,
+        file_actions: *const libc::posix_spawn_file_actions_t,
+        attrp: *const libc::posix_spawnattr_t,
+        argv: *const *mut libc::c_char,
+        envp: *const *mut libc::c_char,
+    ) -> libc::c_int,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"posix_spawnp\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(
+            pid: *mut libc::pid_t,
+            file: *const libc::c_char,
+            file_actions: *const libc::posix_spawn_file_actions_t,
+            attrp: *const libc::posix_spawnattr_t,
+            argv: *const *mut libc::c_char,
+            envp: *const *mut libc::c_char,
+        ) -> libc::c_int,
+    >(handle)
+});
+
+#[cfg(target_os = "linux")]
+extern "C" {
+    fn posix_spawnp_from_fs(
+        pid: *mut libc::pid_t,
+        file: *const libc::c_char,
+        file_actions: *const libc::posix_spawn_file_actions_t,
+        attrp: *const libc::posix_spawnattr_t,
+        argv: *const *mut libc::c_char,
+        envp: *const *mut libc::c_char,
+    ) -> libc::c_int;
+}
+
+#[cfg(target_os = "linux")]
+#[no_mangle]
+unsafe extern "C-unwind" fn posix_spawnp(
+    pid: *mut libc::pid_t,
+    file: *const libc::c_char,
+    file_actions: *const libc::posix_spawn_file_actions_t,
+    attrp: *const libc::posix_spawnattr_t,
+    argv: *const *mut libc::c_char,
+    envp: *const *mut libc::c_char,
+) -> libc::c_int {
+    posix_spawnp_from_fs(pid, file, file_actions, attrp, argv, envp)
+}
+
+//access
+// pub static ACCSESS_HANDLE: std::sync::LazyLock<
+//     unsafe extern "C-unwind" fn(path: *const libc::c_char, amode: libc::c_int) -> libc::c_int,
+// > = std::sync::LazyLock::new(|| unsafe {
+//     let handle = libc::dlsym(libc::RTLD_NEXT, b"access\0".as_ptr() as _);
+//     std::mem::transmute::<
+//         *mut libc::c_void,
+//         unsafe extern "C-unwind" fn(path: *const libc::c_char, amode: libc::c_int) -> libc::c_int,
+//     >(handle)
+// });
+
+// #[no_mangle]
+// unsafe extern "C-unwind" fn access(path: *const libc::c_char, amode: libc::c_int) -> libc::c_int {
+//     println!("rust access");
+
+//     ACCSESS_HANDLE(path, amode)
+// }
+
+//opendir
+pub static OPENDIR_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(dirname: *const libc::c_char) -> *mut libc::DIR,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"opendir\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(dirname: *const libc::c_char) -> *mut libc::DIR,
+    >(handle)
+});
+
+extern "C" {
+    fn opendir_from_fs(dirname: *const libc::c_char) -> *mut libc::DIR;
+}
+
+#[no_mangle]
+unsafe extern "C-unwind" fn opendir(dirname: *const libc::c_char) -> *mut libc::DIR {
+    opendir_from_fs(dirname)
+}
+
+//fdopendir
+pub static FDOPENDIR_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(fd: libc::c_int) -> *mut libc::DIR,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"fdopendir\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(fd: libc::c_int) -> *mut libc::DIR,
+    >(handle)
+});
+
+extern "C" {
+    fn fdopendir_from_fs(fd: libc::c_int) -> *mut libc::DIR;
+}
+
+#[no_mangle]
+unsafe extern "C-unwind" fn fdopendir(fd: libc::c_int) -> *mut libc::DIR {
+    // println!("rust fdopendir: {:?}", fd);
+
+    fdopendir_from_fs(fd)
+}
+
+//readdir
+pub static READDIR_HANDLE: std::sync::LazyLock<
     unsafe extern "C-unwind" fn(dirp: *mut libc::DIR) -> *mut libc::dirent,
 > = std::sync::LazyLock::new(|| unsafe {
-    let handle = libc::dlsym(libc::RTLD_NEXT, b"readdir\0".as_ptr() as _);
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"readdir\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(dirp: *mut libc::DIR) -> *mut libc::dirent,
+    >(handle)
+});
+
+extern "C" {
+    fn readdir_from_fs(dirp: *mut libc::DIR) -> *mut libc::dirent;
+}
+
+#[no_mangle]
+unsafe extern "C-unwind" fn readdir(dirp: *mut libc::DIR) -> *mut libc::dirent {
+    readdir_from_fs(dirp)
+}
+
+//telledir
+// pub static TELLDIR_HANDLE: std::sync::LazyLock<
+//     unsafe extern "C-unwind" fn(dirp: *mut libc::DIR) -> libc::c_long,
+// > = std::sync::LazyLock::new(|| unsafe {
+//     let handle = libc::dlsym(libc::RTLD_NEXT, b"telldir\0".as_ptr() as _);
+//     std::mem::transmute::<
+//         *mut libc::c_void,
+//         unsafe extern "C-unwind" fn(dirp: *mut libc::DIR) -> libc::c_long,
+//     >(handle)
+// });
+
+// #[no_mangle]
+// unsafe extern "C-unwind" fn telldir(dirp: *mut libc::DIR) -> libc::c_long {
+//     println!("rust telldir: {:?}", dirp);
+
+//     TELLDIR_HANDLE(dirp)
+// }
+
+//rewinddir
+pub static REWINDDIR_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(dirp: *mut libc::DIR),
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"rewinddir\0".as_ptr() as _);
+    std::mem::transmute::<*mut libc::c_void, unsafe extern "C-unwind" fn(dirp: *mut libc::DIR)>(
+        handle,
+    )
+});
+
+extern "C" {
+    fn rewinddir_from_fs(dirp: *mut libc::DIR);
+}
+
+#[no_mangle]
+unsafe extern "C-unwind" fn rewinddir(dirp: *mut libc::DIR) {
+    // println!("rust rewinddir: {:?}", dirp);
+
+    rewinddir_from_fs(dirp)
+}
+
+//seekdir
+// pub static SEEKDIR_HANDLE: std::sync::LazyLock<
+//     unsafe extern "C-unwind" fn(dirp: *mut libc::DIR, loc: libc::c_long),
+// > = std::sync::LazyLock::new(|| unsafe {
+//     let handle = libc::dlsym(libc::RTLD_NEXT, b"seekdir\0".as_ptr() as _);
+//     std::mem::transmute::<
+//         *mut libc::c_void,
+//         unsafe extern "C-unwind" fn(dirp: *mut libc::DIR, loc: libc::c_long),
+//     >(handle)
+// });
+
+// #[no_mangle]
+// unsafe extern "C-unwind" fn seekdir(dirp: *mut libc::DIR, loc: libc::c_long) {
+//     println!("rust seekdir: {:?}", dirp);
+
+//     SEEKDIR_HANDLE(dirp, loc)
+// }
+
+//dirfd
+// pub static DIRFD_HANDLE: std::sync::LazyLock<
+//     unsafe extern "C-unwind" fn(dirp: *mut libc::DIR) -> libc::c_int,
+// > = std::sync::LazyLock::new(|| unsafe {
+//     let handle = libc::dlsym(libc::RTLD_NEXT, b"dirfd\0".as_ptr() as _);
+//     std::mem::transmute::<
+//         *mut libc::c_void,
+//         unsafe extern "C-unwind" fn(dirp: *mut libc::DIR) -> libc::c_int,
+//     >(handle)
+// });
+
+// #[no_mangle]
+// unsafe extern "C-unwind" fn dirfd(dirp: *mut libc::DIR) -> libc::c_int {
+//     println!("rust dirfd: {:?}", dirp);
+
+//     DIRFD_HANDLE(dirp)
+// }
+
+//mkdir
+pub static MKDIR_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(path: *const libc::c_char, mode: libc::mode_t) -> libc::c_int,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"mkdir\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(path: *const libc::c_char, mode: libc::mode_t) -> libc::c_int,
+    >(handle)
+});
+
+extern "C" {
+    fn mkdir_from_fs(path: *const libc::c_char, mode: libc::mode_t) -> libc::c_int;
+}
+
+#[no_mangle]
+unsafe extern "C-unwind" fn mkdir(path: *const libc::c_char, mode: libc::mode_t) -> libc::c_int {
+    // println!("rust mkdir: {:?}", CStr::from_ptr(path));
+
+    mkdir_from_fs(path, mode)
+}
+
+//unlink
+pub static UNLINK_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(path: *const libc::c_char) -> libc::c_int,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"unlink\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(path: *const libc::c_char) -> libc::c_int,
+    >(handle)
+});
+
+extern "C" {
+    fn unlink_from_fs(path: *const libc::c_char) -> libc::c_int;
+}
+
+#[no_mangle]
+unsafe extern "C-unwind" fn unlink(path: *const libc::c_char) -> libc::c_int {
+    unlink_from_fs(path)
+}
+
+//unlinkat
+pub static UNLINKAT_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(
+        dirfd: libc::c_int,
+        path: *const libc::c_char,
+        flags: libc::c_int,
+    ) -> libc::c_int,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"unlinkat\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(
+            dirfd: libc::c_int,
+            path: *const libc::c_char,
+            flags: libc::c_int,
+        ) -> libc::c_int,
+    >(handle)
+});
+
+extern "C" {
+    fn unlinkat_from_fs(
+        dirfd: libc::c_int,
+        path: *const libc::c_char,
+        flags: libc::c_int,
+    ) -> libc::c_int;
+}
+
+#[no_mangle]
+unsafe extern "C-unwind" fn unlinkat(
+    dirfd: libc::c_int,
+    path: *const libc::c_char,
+    flags: libc::c_int,
+) -> libc::c_int {
+    unlinkat_from_fs(dirfd, path, flags)
+}
+
+//rmdir
+pub static RMDIR_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(path: *const libc::c_char) -> libc::c_int,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"rmdir\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(path: *const libc::c_char) -> libc::c_int,
+    >(handle)
+});
+
+extern "C" {
+    fn rmdir_from_fs(path: *const libc::c_char) -> libc::c_int;
+}
+
+#[no_mangle]
+unsafe extern "C-unwind" fn rmdir(path: *const libc::c_char) -> libc::c_int {
+    rmdir_from_fs(path)
+}
+
+//rename
+pub static RENAME_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(old: *const libc::c_char, new: *const libc::c_char) -> libc::c_int,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"rename\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(
+            old: *const libc::c_char,
+            new: *const libc::c_char,
+        ) -> libc::c_int,
+    >(handle)
+});
+
+extern "C" {
+    fn rename_from_fs(old: *const libc::c_char, new: *const libc::c_char) -> libc::c_int;
+}
+
+#[no_mangle]
+unsafe extern "C-unwind" fn rename(
+    old: *const libc::c_char,
+    new: *const libc::c_char,
+) -> libc::c_int {
+    rename_from_fs(old, new)
+}
+
+//link
+pub static LINK_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(old: *const libc::c_char, new: *const libc::c_char) -> libc::c_int,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"link\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(
+            old: *const libc::c_char,
+            new: *const libc::c_char,
+        ) -> libc::c_int,
+    >(handle)
+});
+
+extern "C" {
+    fn link_from_fs(old: *const libc::c_char, new: *const libc::c_char) -> libc::c_int;
+}
+
+#[no_mangle]
+unsafe extern "C-unwind" fn link(
+    old: *const libc::c_char,
+    new: *const libc::c_char,
+) -> libc::c_int {
+    link_from_fs(old, new)
+}
+
+//symlink
+pub static SYMLINK_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(
+        target: *const libc::c_char,
+        linkpath: *const libc::c_char,
+    ) -> libc::c_int,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"symlink\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(
+            target: *const libc::c_char,
+            linkpath: *const libc::c_char,
+        ) -> libc::c_int,
+    >(handle)
+});
+
+extern "C" {
+    fn symlink_from_fs(target: *const libc::c_char, linkpath: *const libc::c_char) -> libc::c_int;
+}
+
+#[no_mangle]
+unsafe extern "C-unwind" fn symlink(
+    target: *const libc::c_char,
+    linkpath: *const libc::c_char,
+) -> libc::c_int {
+    symlink_from_fs(target, linkpath)
+}
+
+//chmod
+pub static CHMOD_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(path: *const libc::c_char, mode: libc::mode_t) -> libc::c_int,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"chmod\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(path: *const libc::c_char, mode: libc::mode_t) -> libc::c_int,
+    >(handle)
+});
+
+extern "C" {
+    fn chmod_from_fs(path: *const libc::c_char, mode: libc::mode_t) -> libc::c_int;
+}
+
+#[no_mangle]
+unsafe extern "C-unwind" fn chmod(path: *const libc::c_char, mode: libc::mode_t) -> libc::c_int {
+    chmod_from_fs(path, mode)
+}
+
+//truncate
+pub static TRUNCATE_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(path: *const libc::c_char, length: libc::off_t) -> libc::c_int,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"truncate\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(path: *const libc::c_char, length: libc::off_t) -> libc::c_int,
+    >(handle)
+});
+
+extern "C" {
+    fn truncate_from_fs(path: *const libc::c_char, length: libc::off_t) -> libc::c_int;
+}
+
+#[no_mangle]
+unsafe extern "C-unwind" fn truncate(
+    path: *const libc::c_char,
+    length: libc::off_t,
+) -> libc::c_int {
+    truncate_from_fs(path, length)
+}
+
+//utimensat
+pub static UTIMENSAT_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(
+        dirfd: libc::c_int,
+        path: *const libc::c_char,
+        times: *const libc::timespec,
+        flags: libc::c_int,
+    ) -> libc::c_int,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"utimensat\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(
+            dirfd: libc::c_int,
+            path: *const libc::c_char,
+            times: *const libc::timespec,
+            flags: libc::c_int,
+        ) -> libc::c_int,
+    >(handle)
+});
+
+extern "C" {
+    fn utimensat_from_fs(
+        dirfd: libc::c_int,
+        path: *const libc::c_char,
+        times: *const libc::timespec,
+        flags: libc::c_int,
+    ) -> libc::c_int;
+}
+
+#[no_mangle]
+unsafe extern "C-unwind" fn utimensat(
+    dirfd: libc::c_int,
+    path: *const libc::c_char,
+    times: *const libc::timespec,
+    flags: libc::c_int,
+) -> libc::c_int {
+    utimensat_from_fs(dirfd, path, times, flags)
+}
+
+//mkdirat
+pub static MKDIRAT_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(
+        dirfd: libc::c_int,
+        path: *const libc::c_char,
+        mode: libc::mode_t,
+    ) -> libc::c_int,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"mkdirat\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(
+            dirfd: libc::c_int,
+            path: *const libc::c_char,
+            mode: libc::mode_t,
+        ) -> libc::c_int,
+    >(handle)
+});
+
+extern "C" {
+    fn mkdirat_from_fs(
+        dirfd: libc::c_int,
+        path: *const libc::c_char,
+        mode: libc::mode_t,
+    ) -> libc::c_int;
+}
+
+#[no_mangle]
+unsafe extern "C-unwind" fn mkdirat(
+    dirfd: libc::c_int,
+    path: *const libc::c_char,
+    mode: libc::mode_t,
+) -> libc::c_int {
+    mkdirat_from_fs(dirfd, path, mode)
+}
+
+//renameat
+pub static RENAMEAT_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(
+        olddirfd: libc::c_int,
+        old: *const libc::c_char,
+        newdirfd: libc::c_int,
+        new: *const libc::c_char,
+    ) -> libc::c_int,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"renameat\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(
+            olddirfd: libc::c_int,
+            old: *const libc::c_char,
+            newdirfd: libc::c_int,
+            new: *const libc::c_char,
+        ) -> libc::c_int,
+    >(handle)
+});
+
+extern "C" {
+    fn renameat_from_fs(
+        olddirfd: libc::c_int,
+        old: *const libc::c_char,
+        newdirfd: libc::c_int,
+        new: *const libc::c_char,
+    ) -> libc::c_int;
+}
+
+#[no_mangle]
+unsafe extern "C-unwind" fn renameat(
+    olddirfd: libc::c_int,
+    old: *const libc::c_char,
+    newdirfd: libc::c_int,
+    new: *const libc::c_char,
+) -> libc::c_int {
+    renameat_from_fs(olddirfd, old, newdirfd, new)
+}
+
+//renameat2
+#[cfg(target_os = "linux")]
+pub static RENAMEAT2_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(
+        olddirfd: libc::c_int,
+        old: *const libc::c_char,
+        newdirfd: libc::c_int,
+        new: *const libc::c_char,
+        flags: libc::c_uint,
+    ) -> libc::c_int,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"renameat2\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(
+            olddirfd: libc::c_int,
+            old: *const libc::c_char,
+            newdirfd: libc::c_int,
+            new: *const libc::c_char,
+            flags: libc::c_uint,
+        ) -> libc::c_int,
+    >(handle)
+});
+
+#[cfg(target_os = "linux")]
+extern "C" {
+    fn renameat2_from_fs(
+        olddirfd: libc::c_int,
+        old: *const libc::c_char,
+        newdirfd: libc::c_int,
+        new: *const libc::c_char,
+        flags: libc::c_uint,
+    ) -> libc::c_int;
+}
+
+#[cfg(target_os = "linux")]
+#[no_mangle]
+unsafe extern "C-unwind" fn renameat2(
+    olddirfd: libc::c_int,
+    old: *const libc::c_char,
+    newdirfd: libc::c_int,
+    new: *const libc::c_char,
+    flags: libc::c_uint,
+) -> libc::c_int {
+    renameat2_from_fs(olddirfd, old, newdirfd, new, flags)
+}
+
+//linkat
+pub static LINKAT_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(
+        olddirfd: libc::c_int,
+        old: *const libc::c_char,
+        newdirfd: libc::c_int,
+        new: *const libc::c_char,
+        flags: libc::c_int,
+    ) -> libc::c_int,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"linkat\0".as_ptr() as _);
     std::mem::transmute::<
         *mut libc::c_void,
-        unsafe extern "C-unwind" fn(dirp: *mut libc::DIR) -> *mut libc::dirent,
+        unsafe extern "C-unwind" fn(
+            olddirfd: libc::c_int,
+            old: *const libc::c_char,
+            newdirfd: libc::c_int,
+            new: *const libc::c_char,
+            flags: libc::c_int,
+        ) -> libc::c_int,
     >(handle)
 });
 
 extern "C" {
-    fn readdir_from_fs(dirp: *mut libc::DIR) -> *mut libc::dirent;
+    fn linkat_from_fs(
+        olddirfd: libc::c_int,
+        old: *const libc::c_char,
+        newdirfd: libc::c_int,
+        new: *const libc::c_char,
+        flags: libc::c_int,
+    ) -> libc::c_int;
 }
 
 #[no_mangle]
-unsafe extern "C-unwind" fn readdir(dirp: *mut libc::DIR) -> *mut libc::dirent {
-    readdir_from_fs(dirp)
+unsafe extern "C-unwind" fn linkat(
+    olddirfd: libc::c_int,
+    old: *const libc::c_char,
+    newdirfd: libc::c_int,
+    new: *const libc::c_char,
+    flags: libc::c_int,
+) -> libc::c_int {
+    linkat_from_fs(olddirfd, old, newdirfd, new, flags)
 }
 
-//telledir
-// pub static TELLDIR_HANDLE: std::sync::LazyLock<
-//     unsafe extern "C-unwind" fn(dirp: *mut libc::DIR) -> libc::c_long,
-// > = std::sync::LazyLock::new(|| unsafe {
-//     let handle = libc::dlsym(libc::RTLD_NEXT, b"telldir\0".as_ptr() as _);
-//     std::mem::transmute::<
-//         *mut libc::c_void,
-//         unsafe extern "C-unwind" fn(dirp: *mut libc::DIR) -> libc::c_long,
-//     >(handle)
-// });
+//symlinkat
+pub static SYMLINKAT_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(
+        target: *const libc::c_char,
+        newdirfd: libc::c_int,
+        linkpath: *const libc::c_char,
+    ) -> libc::c_int,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"symlinkat\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(
+            target: *const libc::c_char,
+            newdirfd: libc::c_int,
+            linkpath: *const libc::c_char,
+        ) -> libc::c_int,
+    >(handle)
+});
 
-// #[no_mangle]
-// unsafe extern "C-unwind" fn telldir(dirp: *mut libc::DIR) -> libc::c_long {
-//     println!("rust telldir: {:?}", dirp);
+extern "C" {
+    fn symlinkat_from_fs(
+        target: *const libc::c_char,
+        newdirfd: libc::c_int,
+        linkpath: *const libc::c_char,
+    ) -> libc::c_int;
+}
 
-//     TELLDIR_HANDLE(dirp)
-// }
+#[no_mangle]
+unsafe extern "C-unwind" fn symlinkat(
+    target: *const libc::c_char,
+    newdirfd: libc::c_int,
+    linkpath: *const libc::c_char,
+) -> libc::c_int {
+    symlinkat_from_fs(target, newdirfd, linkpath)
+}
 
-//rewinddir
-pub static REWINDDIR_HANDLE: std::sync::LazyLock<
-    unsafe extern "C-unwind" fn(dirp: *mut libc::DIR),
+//fchmod
+pub static FCHMOD_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(fd: libc::c_int, mode: libc::mode_t) -> libc::c_int,
 > = std::sync::LazyLock::new(|| unsafe {
-    let handle = libc::dlsym(libc::RTLD_NEXT, b"rewinddir\0".as_ptr() as _);
-    std::mem::transmute::<*mut libc::c_void, unsafe extern "C-unwind" fn(dirp: *mut libc::DIR)>(
-        handle,
-    )
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"fchmod\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(fd: libc::c_int, mode: libc::mode_t) -> libc::c_int,
+    >(handle)
 });
 
 extern "C" {
-    fn rewinddir_from_fs(dirp: *mut libc::DIR);
+    fn fchmod_from_fs(fd: libc::c_int, mode: libc::mode_t) -> libc::c_int;
 }
 
 #[no_mangle]
-unsafe extern "C-unwind" fn rewinddir(dirp: *mut libc::DIR) {
-    // println!("rust rewinddir: {:?}", dirp);
+unsafe extern "C-unwind" fn fchmod(fd: libc::c_int, mode: libc::mode_t) -> libc::c_int {
+    fchmod_from_fs(fd, mode)
+}
 
-    rewinddir_from_fs(dirp)
+//fchmodat
+pub static FCHMODAT_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(
+        dirfd: libc::c_int,
+        path: *const libc::c_char,
+        mode: libc::mode_t,
+        flags: libc::c_int,
+    ) -> libc::c_int,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"fchmodat\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(
+            dirfd: libc::c_int,
+            path: *const libc::c_char,
+            mode: libc::mode_t,
+            flags: libc::c_int,
+        ) -> libc::c_int,
+    >(handle)
+});
+
+extern "C" {
+    fn fchmodat_from_fs(
+        dirfd: libc::c_int,
+        path: *const libc::c_char,
+        mode: libc::mode_t,
+        flags: libc::c_int,
+    ) -> libc::c_int;
 }
 
-//seekdir
-// pub static SEEKDIR_HANDLE: std::sync::LazyLock<
-//     unsafe extern "C-unwind" fn(dirp: *mut libc::DIR, loc: libc::c_long),
-// > = std::sync::LazyLock::new(|| unsafe {
-//     let handle = libc::dlsym(libc::RTLD_NEXT, b"seekdir\0".as_ptr() as _);
-//     std::mem::transmute::<
-//         *mut libc::c_void,
-//         unsafe extern "C-unwind" fn(dirp: *mut libc::DIR, loc: libc::c_long),
-//     >(handle)
-// });
+#[no_mangle]
+unsafe extern "C-unwind" fn fchmodat(
+    dirfd: libc::c_int,
+    path: *const libc::c_char,
+    mode: libc::mode_t,
+    flags: libc::c_int,
+) -> libc::c_int {
+    fchmodat_from_fs(dirfd, path, mode, flags)
+}
 
-// #[no_mangle]
-// unsafe extern "C-unwind" fn seekdir(dirp: *mut libc::DIR, loc: libc::c_long) {
-//     println!("rust seekdir: {:?}", dirp);
+//chown
+pub static CHOWN_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(
+        path: *const libc::c_char,
+        owner: libc::uid_t,
+        group: libc::gid_t,
+    ) -> libc::c_int,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"chown\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(
+            path: *const libc::c_char,
+            owner: libc::uid_t,
+            group: libc::gid_t,
+        ) -> libc::c_int,
+    >(handle)
+});
 
-//     SEEKDIR_HANDLE(dirp, loc)
-// }
+extern "C" {
+    fn chown_from_fs(
+        path: *const libc::c_char,
+        owner: libc::uid_t,
+        group: libc::gid_t,
+    ) -> libc::c_int;
+}
 
-//dirfd
-// pub static DIRFD_HANDLE: std::sync::LazyLock<
-//     unsafe extern "C-unwind" fn(dirp: *mut libc::DIR) -> libc::c_int,
-// > = std::sync::LazyLock::new(|| unsafe {
-//     let handle = libc::dlsym(libc::RTLD_NEXT, b"dirfd\0".as_ptr() as _);
-//     std::mem::transmute::<
-//         *mut libc::c_void,
-//         unsafe extern "C-unwind" fn(dirp: *mut libc::DIR) -> libc::c_int,
-//     >(handle)
-// });
+#[no_mangle]
+unsafe extern "C-unwind" fn chown(
+    path: *const libc::c_char,
+    owner: libc::uid_t,
+    group: libc::gid_t,
+) -> libc::c_int {
+    chown_from_fs(path, owner, group)
+}
 
-// #[no_mangle]
-// unsafe extern "C-unwind" fn dirfd(dirp: *mut libc::DIR) -> libc::c_int {
-//     println!("rust dirfd: {:?}", dirp);
+//lchown
+pub static LCHOWN_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(
+        path: *const libc::c_char,
+        owner: libc::uid_t,
+        group: libc::gid_t,
+    ) -> libc::c_int,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"lchown\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(
+            path: *const libc::c_char,
+            owner: libc::uid_t,
+            group: libc::gid_t,
+        ) -> libc::c_int,
+    >(handle)
+});
 
-//     DIRFD_HANDLE(dirp)
-// }
+extern "C" {
+    fn lchown_from_fs(
+        path: *const libc::c_char,
+        owner: libc::uid_t,
+        group: libc::gid_t,
+    ) -> libc::c_int;
+}
 
-//mkdir
-pub static MKDIR_HANDLE: std::sync::LazyLock<
-    unsafe extern "C-unwind" fn(path: *const libc::c_char, mode: libc::mode_t) -> libc::c_int,
+#[no_mangle]
+unsafe extern "C-unwind" fn lchown(
+    path: *const libc::c_char,
+    owner: libc::uid_t,
+    group: libc::gid_t,
+) -> libc::c_int {
+    lchown_from_fs(path, owner, group)
+}
+
+//fchown
+pub static FCHOWN_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(
+        fd: libc::c_int,
+        owner: libc::uid_t,
+        group: libc::gid_t,
+    ) -> libc::c_int,
 > = std::sync::LazyLock::new(|| unsafe {
-    let handle = libc::dlsym(libc::RTLD_NEXT, b"mkdir\0".as_ptr() as _);
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"fchown\0".as_ptr() as _);
     std::mem::transmute::<
         *mut libc::c_void,
-        unsafe extern "C-unwind" fn(path: *const libc::c_char, mode: libc::mode_t) -> libc::c_int,
+        unsafe extern "C-unwind" fn(
+            fd: libc::c_int,
+            owner: libc::uid_t,
+            group: libc::gid_t,
+        ) -> libc::c_int,
     >(handle)
 });
 
 extern "C" {
-    fn mkdir_from_fs(path: *const libc::c_char, mode: libc::mode_t) -> libc::c_int;
+    fn fchown_from_fs(fd: libc::c_int, owner: libc::uid_t, group: libc::gid_t) -> libc::c_int;
 }
 
 #[no_mangle]
-unsafe extern "C-unwind" fn mkdir(path: *const libc::c_char, mode: libc::mode_t) -> libc::c_int {
-    // println!("rust mkdir: {:?}", CStr::from_ptr(path));
+unsafe extern "C-unwind" fn fchown(
+    fd: libc::c_int,
+    owner: libc::uid_t,
+    group: libc::gid_t,
+) -> libc::c_int {
+    fchown_from_fs(fd, owner, group)
+}
 
-    mkdir_from_fs(path, mode)
+//fchownat
+pub static FCHOWNAT_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(
+        dirfd: libc::c_int,
+        path: *const libc::c_char,
+        owner: libc::uid_t,
+        group: libc::gid_t,
+        flags: libc::c_int,
+    ) -> libc::c_int,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"fchownat\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(
+            dirfd: libc::c_int,
+            path: *const libc::c_char,
+            owner: libc::uid_t,
+            group: libc::gid_t,
+            flags: libc::c_int,
+        ) -> libc::c_int,
+    >(handle)
+});
+
+extern "C" {
+    fn fchownat_from_fs(
+        dirfd: libc::c_int,
+        path: *const libc::c_char,
+        owner: libc::uid_t,
+        group: libc::gid_t,
+        flags: libc::c_int,
+    ) -> libc::c_int;
+}
+
+#[no_mangle]
+unsafe extern "C-unwind" fn fchownat(
+    dirfd: libc::c_int,
+    path: *const libc::c_char,
+    owner: libc::uid_t,
+    group: libc::gid_t,
+    flags: libc::c_int,
+) -> libc::c_int {
+    fchownat_from_fs(dirfd, path, owner, group, flags)
 }
 
 //closedir
@@ -711,6 +1941,26 @@ unsafe extern "C-unwind" fn chdir(path: *const libc::c_char) -> libc::c_int {
     chdir_from_fs(path)
 }
 
+//fchdir
+pub static FCHDIR_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(fd: libc::c_int) -> libc::c_int,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"fchdir\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(fd: libc::c_int) -> libc::c_int,
+    >(handle)
+});
+
+extern "C" {
+    fn fchdir_from_fs(fd: libc::c_int) -> libc::c_int;
+}
+
+#[no_mangle]
+unsafe extern "C-unwind" fn fchdir(fd: libc::c_int) -> libc::c_int {
+    fchdir_from_fs(fd)
+}
+
 //readlink
 // pub static READLINK_HANDLE: std::sync::LazyLock<
 //     unsafe extern "C-unwind" fn(
@@ -773,29 +2023,57 @@ unsafe extern "C-unwind" fn realpath(
     realpath_from_fs(path, resolved_path)
 }
 
-// //dlopen
-// pub static DLOPEN_HANDLE: std::sync::LazyLock<
-//     unsafe extern "C-unwind" fn(
-//         filename: *const libc::c_char,
-//         flag: libc::c_int,
-//     ) -> *mut libc::c_void,
-// > = std::sync::LazyLock::new(|| unsafe {
-//     let handle = libc::dlsym(libc::RTLD_NEXT, b"dlopen\0".as_ptr() as _);
-//     std::mem::transmute::<
-//         *mut libc::c_void,
-//         unsafe extern "C-unwind" fn(
-//             filename: *const libc::c_char,
-//             flag: libc::c_int,
-//         ) -> *mut libc::c_void,
-//     >(handle)
-// });
+//canonicalize_file_name
+#[cfg(target_os = "linux")]
+pub static CANONICALIZE_FILE_NAME_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(path: *const libc::c_char) -> *mut libc::c_char,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"canonicalize_file_name\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(path: *const libc::c_char) -> *mut libc::c_char,
+    >(handle)
+});
 
-// #[no_mangle]
-// unsafe extern "C-unwind" fn dlopen(
-//     filename: *const libc::c_char,
-//     flag: libc::c_int,
-// ) -> *mut libc::c_void {
-//     println!("rust dlopen: {:?}", CStr::from_ptr(filename));
+#[cfg(target_os = "linux")]
+extern "C" {
+    fn canonicalize_file_name_from_fs(path: *const libc::c_char) -> *mut libc::c_char;
+}
 
-//     DLOPEN_HANDLE(filename, flag)
-// }
+#[cfg(target_os = "linux")]
+#[no_mangle]
+unsafe extern "C-unwind" fn canonicalize_file_name(path: *const libc::c_char) -> *mut libc::c_char {
+    canonicalize_file_name_from_fs(path)
+}
+
+//dlopen
+#[cfg(target_os = "linux")]
+pub static DLOPEN_HANDLE: std::sync::LazyLock<
+    unsafe extern "C-unwind" fn(
+        filename: *const libc::c_char,
+        flag: libc::c_int,
+    ) -> *mut libc::c_void,
+> = std::sync::LazyLock::new(|| unsafe {
+    let handle = libc::dlsym(libc::RTLD_NEXT, b"dlopen\0".as_ptr() as _);
+    std::mem::transmute::<
+        *mut libc::c_void,
+        unsafe extern "C-unwind" fn(
+            filename: *const libc::c_char,
+            flag: libc::c_int,
+        ) -> *mut libc::c_void,
+    >(handle)
+});
+
+#[cfg(target_os = "linux")]
+extern "C" {
+    fn dlopen_from_fs(filename: *const libc::c_char, flag: libc::c_int) -> *mut libc::c_void;
+}
+
+#[cfg(target_os = "linux")]
+#[no_mangle]
+unsafe extern "C-unwind" fn dlopen(
+    filename: *const libc::c_char,
+    flag: libc::c_int,
+) -> *mut libc::c_void {
+    dlopen_from_fs(filename, flag)
+}
@@ -32,6 +32,28 @@ module Kompo
       key?(name) || super
     end
 
+    # where a path is packed, so the build machine's paths never end up in the binary.
+    # work_dir is served from virtual_root itself, every other root from its own directory
+    # under it. a path outside every root fails the build.
+    def virtual_path(path)
+      root, virtual = embed_roots
+        .select { |dir, _| path == dir || path.start_with?("#{dir}/") }
+        .max_by { |dir, _| dir.size }
+      raise "#{path} is outside every embedded root" unless root
+
+      virtual + path.delete_prefix(root)
+    end
+
+    def embed_roots
+      self[:embed_roots] ||= {self[:work_dir] => self[:virtual_root]}
+    end
+
+    def add_embed_root(dir)
+      return if embed_roots.any? { |root, _| dir == root || dir.start_with?("#{root}/") }
+
+      embed_roots[dir] = File.join(self[:virtual_root], ".roots", embed_roots.size.to_s)
+    end
+
     def resolved(klass)
       self[:resolved][klass] = true
     end
@@ -98,6 +120,7 @@ module Kompo
       opt.option.on("--verbose", "Verbose mode.") { |v| opt.context.verbose = v }
       # opt.option.on("--ignore-stdlib=VAL", Array, "Specify stdlibs not to include, separated by commas.") { |v| opt.context.ignore_stdlib = v }
       # opt.option.on("--dyn-link-lib=VAL", Array, "Specify libraries to be dynamic link, separated by commas.") { |v| opt.context.dyn_link_lib = v }
+      opt.option.on("--virtual-root=VAL", "Absolute path the packed files are served from at runtime. (default: '/__kompo__')") { |v| opt.context.virtual_root = v }
       opt.option.on("--dest-dir=VAL", "Output directry path. (default: current dir)") { |v| opt.context.dest_dir = v }
       # opt.option.on("--ruby-src-path=VAL", "Your Ruby source directry. Must be compiled with '--with-static-linked-ext'.") { |v| opt.context.ruby_src_path = v }
       opt.option.on("--bundle-cache=VAL", "Specify the directory created by 'bundle install --standalone'.") { |v| opt.context.bundle_cache = v }
@@ -112,6 +135,7 @@ module Kompo
     def to_context
       @option.parse!(ARGV)
       @context.args = ARGV
+      @context.virtual_root = File.expand_path(@context.virtual_root || "/__kompo__", "/")
 
       @context.repack = @context.repack || !@context.rebuild
       @context.rebuild = !@context.repack
@@ -636,8 +660,10 @@ module Kompo
   class MakeMainC < Task
     def dependencies
       [
+        CheckStdlibs,
         BundleInstall,
-        BuildNativeGem
+        BuildNativeGem,
+        CollectEmbeds
       ]
     end
 
@@ -702,13 +728,39 @@ module Kompo
     end
   end
 
+  class CollectEmbeds < Task
+    def dependencies
+      [
+        CpProjectDir,
+        CdWorkingDir,
+        CheckStdlibs,
+        RequireBundlerSetup
+      ]
+    end
+
+    def exec
+      context.embeds = (context.args + context.gems + context.ruby_std_libs + [context.work_dir_entrypoint, context.work_dir_bundler_setup].compact)
+        .map { |path| File.expand_path(path) }
+
+      context.add_embed_root(File.expand_path(context.bundle_cache)) if context.bundle_cache
+      context.embeds.each do |path|
+        context.add_embed_root(File.directory?(path) ? path : File.dirname(path))
+      end
+    end
+
+    def clean
+      context.embed_roots = nil
+    end
+  end
+
   KompoFile = Struct.new(:path, :bytes)
 
   class MakeFsC < Task
     def dependencies
       [
         BundleInstall,
-        RequireBundlerSetup
+        RequireBundlerSetup,
+        CollectEmbeds
       ]
     end
 
@@ -719,16 +771,13 @@ module Kompo
     def exec
       return if File.exist?(context.fs_c)
 
-      context.embeds = context.args + context.gems + context.ruby_std_libs + [context.work_dir_entrypoint, context.work_dir_bundler_setup].compact
-
       @files = []
       @file_bytes = []
       @paths = []
       @file_sizes = [0]
 
       Async do
-        context.embeds.each do |arg_path|
-          expand_path = File.expand_path(arg_path)
+        context.embeds.each do |expand_path|
           if File.directory?(expand_path)
             Async do
               Find.find(expand_path) do |path|
@@ -762,7 +811,7 @@ module Kompo
       end.wait
 
       puts path
-      path = (path.bytes << 0)
+      path = (context.virtual_path(path).bytes << 0)
 
       KompoFile.new(path, bytes)
     end